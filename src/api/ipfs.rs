@@ -20,7 +20,7 @@ impl Cli {
     pub async fn add(&self, output_path: &str) -> Result<String, NftgenError> {
         log::info!("Running `ipfs add -r {}`", output_path);
         let add_output = Command::new("ipfs")
-            .args(&["add", "-r", output_path])
+            .args(["add", "-r", output_path])
             .output()
             .await?;
 
@@ -35,7 +35,7 @@ impl Cli {
         let car_file_path = car_file_path.as_ref();
         log::info!("Running `ipfs dag export {}`", cid);
         let output = Command::new("ipfs")
-            .args(&["dag", "export", cid])
+            .args(["dag", "export", cid])
             .output()
             .await?;
 
@@ -47,7 +47,7 @@ impl Cli {
 
         self.daemon = Some(
             Command::new("ipfs")
-                .args(&["daemon"])
+                .args(["daemon"])
                 .stdout(Stdio::piped())
                 .kill_on_drop(true)
                 .spawn()?,
@@ -61,7 +61,7 @@ impl Cli {
 
     async fn init() -> Result<Output, NftgenError> {
         log::info!("Running `ipfs init`");
-        Ok(Command::new("ipfs").args(&["init"]).output().await?)
+        Ok(Command::new("ipfs").args(["init"]).output().await?)
     }
 
     fn parse_cid_from_ipfs_add_output(raw_output: &Output) -> Result<String, NftgenError> {
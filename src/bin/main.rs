@@ -13,6 +13,7 @@ async fn main() -> eyre::Result<()> {
     let opts = Opts::parse_from_config_and_cli()?;
     match opts.sub {
         Subcommands::Generate(cmd) => cmd.run()?,
+        Subcommands::Render(cmd) => cmd.run()?,
         Subcommands::Upload(cmd) => cmd.run()?.await?,
     }
 
@@ -1,17 +1,29 @@
+use std::collections::{BTreeMap, HashSet};
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::{fs, sync::atomic::AtomicU32};
 
 use clap::Parser;
 use rayon::prelude::*;
 
-use crate::cmd::Cmd;
-use crate::nft::{get_layer_groups, ImageBuilder, MetadataBuilder, MetadataWriter};
+use crate::cmd::{is_cli_arg, parse_key_val, Cmd};
+use crate::nft::{
+    check_links, count_combinations, edition_ids, edition_seed, get_layer_groups, one_of_one_ids,
+    placeholder_uri, plan_tokens_exact_excluding, plan_tokens_excluding, random_seed,
+    rarity_report, read_editions, read_one_of_ones, Allocation, Animation, AnimationFormat,
+    AttributeOptions, BlendMode, Dna, Edition, ImageBuilder, ImageCache, ImageFormat, LayerGroup,
+    Metadata, MetadataBuilder, MetadataWriter, NestedAttributes, OneOfOne, PlanExtension, PlanFile,
+    PlannedToken, PngCompression, PngFilter, PngOptions, Position, ResizeFilter, Rules, TierRarity,
+    TokenPlan,
+};
+use crate::NftgenError;
 
 #[derive(Debug, Clone, Parser)]
 pub struct GenerateArgs {
-    /// Number of NFTs to generate
+    /// Number of NFTs to generate, the sum of the edition counts if `--editions-path` is given.
+    /// Required unless `--editions-path` or `--plan-path` is given
     #[clap(short, long)]
-    pub num: usize,
+    pub num: Option<usize>,
 
     /// path to root directory of NFT layers
     #[clap(short, long, default_value="./layers", value_hint = clap::ValueHint::DirPath)]
@@ -21,7 +33,7 @@ pub struct GenerateArgs {
     #[clap(short, long, default_value="./nftgen-output", value_hint = clap::ValueHint::DirPath)]
     pub output_path: PathBuf,
 
-    /// Order of NFT layers from back to front
+    /// Order of NFT layers from back to front, defaults to the order in the layers manifest
     #[clap(
         long,
         multiple_values(true),
@@ -37,57 +49,562 @@ pub struct GenerateArgs {
     /// Description for the collection
     #[clap(short, long)]
     pub description: String,
+
+    /// Seed for the random number generator. Runs with the same seed and layers produce
+    /// identical collections. A random seed is used if omitted.
+    /// The seed of every run is saved to `<output-path>/seed`
+    #[clap(long)]
+    pub seed: Option<u64>,
+
+    /// path to a JSON file of rules for traits that exclude or require each other, e.g.
+    /// {"excludes": [["Hat/cap", "Hair/mohawk"]], "requires": [["Hat/cap", "Hair/short"]]}
+    #[clap(long, value_hint = clap::ValueHint::FilePath)]
+    pub rules_path: Option<PathBuf>,
+
+    /// Layer groups that NFTs may go without, as <layer type>=<probability of skipping>
+    /// e.g. Hat=0.3,Glasses=0.5
+    #[clap(
+        long,
+        parse(try_from_str = parse_key_val),
+        multiple_values(true),
+        use_value_delimiter(true),
+        require_value_delimiter(true)
+    )]
+    pub optional_layers: Vec<(String, f64)>,
+
+    /// Layer groups whose layers must share a variant, as <attribute>=<layer type>+<layer type>,
+    /// e.g. Skin=Body+Hands+Ears. Layers of linked groups are named <name>@<variant>, e.g.
+    /// slim@dark#5.png. The variant is picked once per NFT and recorded as the attribute
+    #[clap(
+        long,
+        parse(try_from_str = parse_key_val),
+        multiple_values(true),
+        use_value_delimiter(true),
+        require_value_delimiter(true)
+    )]
+    pub linked_layers: Vec<(String, String)>,
+
+    /// Attribute value for layer groups an NFT has no layer from.
+    /// Those attributes are left out of the metadata if omitted
+    #[clap(long)]
+    pub none_value: Option<String>,
+
+    /// Attributes of layers in nested directories of a layer group, e.g. Clothing/Shirts/red.png.
+    /// `item` reports the layer name, `category` the directory, and `both` adds the directory
+    /// as `<layer type> Category`
+    #[clap(long, arg_enum, default_value = "item")]
+    pub nested_attributes: NestedAttributes,
+
+    /// Adds the rarity tier of layers, set by the tiers of a layers manifest, to the metadata
+    /// as `<layer type> Tier`
+    #[clap(long)]
+    pub tier_attributes: bool,
+
+    /// How layer weights are turned into layer counts. `weighted` treats weights as
+    /// probabilities. `exact` treats them as exact counts or percentages of `--num`, which
+    /// must split `--num` into whole counts
+    #[clap(long, arg_enum, default_value = "weighted")]
+    pub allocation: Allocation,
+
+    /// Blend modes of layer groups or single traits, as <layer type>=<mode> or
+    /// <layer type>/<name>=<mode>, e.g. Shading=multiply,Lighting/sunset=screen.
+    /// Modes: normal, multiply, screen, overlay, add, darken, lighten
+    #[clap(
+        long,
+        parse(try_from_str = parse_key_val),
+        multiple_values(true),
+        use_value_delimiter(true),
+        require_value_delimiter(true)
+    )]
+    pub blend_modes: Vec<(String, BlendMode)>,
+
+    /// Opacity between 0 and 1 of layer groups or single traits, as <layer type>=<opacity> or
+    /// <layer type>/<name>=<opacity>, e.g. Shading=0.6,Lighting/sunset=0.8
+    #[clap(
+        long,
+        parse(try_from_str = parse_key_val),
+        multiple_values(true),
+        use_value_delimiter(true),
+        require_value_delimiter(true)
+    )]
+    pub opacities: Vec<(String, f64)>,
+
+    /// Positions of layer groups or single traits smaller than the canvas, as <layer type>=<position>
+    /// or <layer type>/<name>=<position>, e.g. Hat=top,Glasses/round=120:80. A position is
+    /// an anchor (top-left, top, top-right, left, center, right, bottom-left, bottom,
+    /// bottom-right) or an <x>:<y> offset in pixels. The back-most layer of an NFT sets the
+    /// canvas size; other layers without a position must have the same size
+    #[clap(
+        long,
+        parse(try_from_str = parse_key_val),
+        multiple_values(true),
+        use_value_delimiter(true),
+        require_value_delimiter(true)
+    )]
+    pub positions: Vec<(String, Position)>,
+
+    /// Width in pixels of the images in `<output-path>/images`, keeping the aspect ratio.
+    /// Images keep the size of their layers if omitted
+    #[clap(long)]
+    pub size: Option<u32>,
+
+    /// Widths in pixels of extra, usually smaller, copies of every image, saved to
+    /// `<output-path>/thumbnails/<width>`, e.g. 256,64
+    #[clap(
+        long,
+        multiple_values(true),
+        use_value_delimiter(true),
+        require_value_delimiter(true)
+    )]
+    pub thumbnail_sizes: Vec<u32>,
+
+    /// Filter for resizing images. Use `nearest` to upscale pixel art by whole multiples,
+    /// other sizes are rejected with `nearest`
+    #[clap(long, arg_enum, default_value = "lanczos3")]
+    pub resize_filter: ResizeFilter,
+
+    /// File format of the images and thumbnails
+    #[clap(long, arg_enum, default_value = "png")]
+    pub format: ImageFormat,
+
+    /// Quality from 0 to 100 of lossy image formats (webp, jpeg)
+    #[clap(long, default_value = "90")]
+    pub quality: u8,
+
+    /// zlib compression level of PNGs: fast, default or best
+    #[clap(long, arg_enum, default_value = "default")]
+    pub png_compression: PngCompression,
+
+    /// Filter for the rows of PNGs: none, sub, up, avg, paeth, or adaptive to pick the best
+    /// filter for every row
+    #[clap(long, arg_enum, default_value = "adaptive")]
+    pub png_filter: PngFilter,
+
+    /// Always save PNGs as RGBA. By default opaque images are saved as RGB and images of
+    /// at most 256 colors with a palette, which is lossless and smaller
+    #[clap(long)]
+    pub png_keep_rgba: bool,
+
+    /// Tries every PNG filter and color type at the best compression and keeps the smallest
+    /// file. Slow, but lossless
+    #[clap(long)]
+    pub png_optimize: bool,
+
+    /// File format of NFTs with animated layers (png, gif, webp or webp-lossless), saved to
+    /// `<output-path>/animations` and linked as `animation_url` in their metadata.
+    /// `png` is an animated PNG, `webp` is lossy with `--quality`
+    #[clap(long, arg_enum, default_value = "png")]
+    pub animation_format: AnimationFormat,
+
+    /// Memory limit in MB for decoded layer images shared by all NFTs.
+    /// Least recently used images are decoded again when the limit is reached
+    #[clap(long, default_value = "1024")]
+    pub image_cache_mb: usize,
+
+    /// path to a directory of hand-made NFTs that do not come from the layers, as PNG, APNG
+    /// or GIF images next to JSON metadata templates of the same name, e.g. legend.png and
+    /// legend.json. They count towards `--num` and take the `id` of their template or random ids
+    #[clap(long, value_hint = clap::ValueHint::DirPath)]
+    pub one_of_ones_path: Option<PathBuf>,
+
+    /// path to a TOML or JSON file of editions: segments of consecutive token ids, each with
+    /// its own `layers_path`, `layers_order`, `count`, `name_template` and `rules_path`.
+    /// Replaces `--layers-path`, `--layers-order` and `--rules-path`
+    #[clap(
+        long,
+        value_hint = clap::ValueHint::FilePath,
+        conflicts_with_all = &["layers-order", "rules-path"]
+    )]
+    pub editions_path: Option<PathBuf>,
+
+    /// Only picks the layers of every NFT and writes them to `<output-path>/plan.json` with
+    /// the rarity report, without rendering. Every run writes the plan of the NFTs it picks
+    #[clap(long, conflicts_with = "plan-path")]
+    pub plan_only: bool,
+
+    /// path to a plan file of an earlier run, e.g. `<output-path>/plan.json` edited by hand.
+    /// Renders the NFTs of the plan as they are instead of picking layers, with the same
+    /// layers, editions and one of ones as the run that wrote it. `--seed` is ignored, and
+    /// `--num` from the config file too
+    #[clap(long, value_hint = clap::ValueHint::FilePath)]
+    pub plan_path: Option<PathBuf>,
+
+    /// Continues a run that stopped before saving every NFT, from `<output-path>/plan.json`.
+    /// NFTs whose image and metadata are already saved are skipped, and `--seed` is ignored
+    #[clap(long, conflicts_with_all = &["plan-path", "plan-only", "extend"])]
+    pub resume: bool,
+
+    /// Adds `--num` NFTs to those already saved to `<output-path>`, numbered after the highest
+    /// saved token id and with DNA unique among the saved NFTs
+    #[clap(
+        long,
+        requires = "num",
+        conflicts_with_all = &["plan-path", "editions-path", "one-of-ones-path"]
+    )]
+    pub extend: bool,
 }
 
 impl Cmd for GenerateArgs {
     type Output = ();
 
     fn run(self) -> eyre::Result<Self::Output> {
+        let plan_path = match &self.plan_path {
+            Some(plan_path) => Some(plan_path.clone()),
+            None if self.resume => {
+                let plan_path = self.output_path.join("plan.json");
+                if !plan_path.is_file() {
+                    eyre::bail!(
+                        "There is no plan to resume: {}. Runs that stopped before writing it \
+                         cannot be resumed, but --extend can add NFTs after those already saved",
+                        plan_path.to_string_lossy()
+                    );
+                }
+                Some(plan_path)
+            }
+            None => None,
+        };
+        let plan_file = match &plan_path {
+            Some(plan_path) => {
+                log::debug!("Loading plan from: {}", plan_path.to_string_lossy());
+                Some(PlanFile::read(plan_path)?)
+            }
+            None => None,
+        };
+        self.generate(plan_file)
+    }
+}
+
+impl GenerateArgs {
+    /// Generates the NFTs of `plan_file` as they are, or picks their layers if there is none
+    pub fn generate(self, plan_file: Option<PlanFile>) -> eyre::Result<()> {
+        if plan_file.is_none() && self.num.is_none() && self.editions_path.is_none() {
+            eyre::bail!("--num is required unless --editions-path or --plan-path is given");
+        }
+        if self.size == Some(0) || self.thumbnail_sizes.contains(&0) {
+            return Err(NftgenError::InvalidSize(
+                "--size and --thumbnail-sizes must be at least 1 pixel".to_string(),
+            )
+            .into());
+        }
+        fs::create_dir_all(self.output_path.as_path())?;
+
+        let editions = match &self.editions_path {
+            Some(editions_path) => {
+                log::debug!("Loading editions from: {}", editions_path.to_string_lossy());
+                read_editions(editions_path)?
+            }
+            None => vec![Edition {
+                name: self.collection_name.clone(),
+                count: self.num.unwrap_or_default(),
+                layers_path: self.layers_path.clone(),
+                layers_order: self.layers_order.clone(),
+                name_template: None,
+                rules_path: self.rules_path.clone(),
+            }],
+        };
+
+        let image_cache = Arc::new(ImageCache::new(Some(self.image_cache_mb * 1024 * 1024)));
+        let edition_groups = editions
+            .iter()
+            .map(|edition| self.edition_layer_groups(edition, &image_cache))
+            .collect::<eyre::Result<Vec<_>>>()?;
+        self.check_layer_settings(&edition_groups)?;
+        let edition_rules = editions
+            .iter()
+            .zip(&edition_groups)
+            .map(|(edition, layer_groups)| match &edition.rules_path {
+                Some(rules_path) => {
+                    log::debug!("Loading layer rules from: {}", rules_path.to_string_lossy());
+                    let rules = Rules::from_file(rules_path)?;
+                    rules.validate(layer_groups)?;
+                    Ok(rules)
+                }
+                None => Ok(Rules::default()),
+            })
+            .collect::<Result<Vec<_>, NftgenError>>()?;
+
+        let one_of_ones = match &self.one_of_ones_path {
+            Some(one_of_ones_path) => {
+                log::debug!(
+                    "Loading one of one NFTs from: {}",
+                    one_of_ones_path.to_string_lossy()
+                );
+                read_one_of_ones(one_of_ones_path)?
+            }
+            None => vec![],
+        };
+
+        let nfts = self.plan_nfts(
+            plan_file,
+            &editions,
+            &edition_groups,
+            &edition_rules,
+            &one_of_ones,
+        )?;
+        self.write_rarity(&editions, &edition_groups, &nfts)?;
+
+        if self.plan_only {
+            log::info!(
+                "Planned {} NFTs in: {}",
+                nfts.len(),
+                self.output_path.join("plan.json").to_string_lossy()
+            );
+            return Ok(());
+        }
+
+        let nfts = if self.resume {
+            self.skip_saved(nfts)
+        } else {
+            nfts
+        };
+        self.render_nfts(nfts, &editions)
+    }
+
+    /// Looks up the NFTs of `plan_file`, or picks their layers if there is none, and writes
+    /// their plan to `<output-path>/plan.json`
+    fn plan_nfts<'a>(
+        &self,
+        plan_file: Option<PlanFile>,
+        editions: &[Edition],
+        edition_groups: &'a [Vec<LayerGroup>],
+        edition_rules: &[Rules],
+        one_of_ones: &'a [OneOfOne],
+    ) -> eyre::Result<Vec<Nft<'a>>> {
+        let plan_file_path = self.output_path.as_path().join("plan.json");
+        let (saved, previous) = if self.extend {
+            self.read_saved(&edition_groups[0])?
+        } else {
+            (BTreeMap::new(), PlanFile::default())
+        };
+
+        let (seed, nfts) = match &plan_file {
+            Some(plan_file) => {
+                let nfts = self.resolve_plan(
+                    plan_file,
+                    editions,
+                    edition_groups,
+                    edition_rules,
+                    one_of_ones,
+                )?;
+                // Keep the plan with the output, so that the run can be resumed
+                if !self.resume {
+                    log::debug!("Writing plan to: {}", plan_file_path.to_string_lossy());
+                    plan_file.write(&plan_file_path)?;
+                }
+                (plan_file.seed, nfts)
+            }
+            None => {
+                let seed = self.seed.unwrap_or_else(random_seed);
+                let nfts = self.pick_nfts(
+                    seed,
+                    editions,
+                    edition_groups,
+                    edition_rules,
+                    one_of_ones,
+                    &saved,
+                )?;
+                let mut plan_file = PlanFile {
+                    seed: Some(seed),
+                    extensions: vec![],
+                    tokens: nfts
+                        .iter()
+                        .map(|nft| match nft {
+                            Nft::Layered(i, token) => PlannedToken::from_plan(
+                                token,
+                                self.editions_path
+                                    .as_ref()
+                                    .map(|_| editions[*i].name.as_str()),
+                            ),
+                            Nft::OneOfOne(id, one_of_one) => {
+                                PlannedToken::from_one_of_one(*id, one_of_one.file_name())
+                            }
+                        })
+                        .collect(),
+                };
+                if self.extend {
+                    // The collection keeps its seed, the new NFTs are recorded with theirs
+                    let first_id = nfts.iter().map(Nft::id).min().unwrap_or_default();
+                    log::info!("Extending from NFT #{} with seed: {}", first_id, seed);
+                    plan_file.seed = previous.seed;
+                    plan_file.extensions = previous.extensions;
+                    plan_file.extensions.push(PlanExtension { first_id, seed });
+                    plan_file.tokens.splice(0..0, previous.tokens);
+                }
+                log::debug!("Writing plan to: {}", plan_file_path.to_string_lossy());
+                plan_file.write(&plan_file_path)?;
+                (Some(seed).filter(|_| !self.extend), nfts)
+            }
+        };
+        if let Some(seed) = seed {
+            log::info!("Generating with seed: {}", seed);
+            fs::write(self.output_path.as_path().join("seed"), seed.to_string())?;
+        }
+        Ok(nfts)
+    }
+
+    /// Reads the DNA of the NFTs saved to the output directory with the plan of the run that
+    /// saved them, and checks that `layer_groups` have combinations left for `--num` more NFTs
+    fn read_saved(
+        &self,
+        layer_groups: &[LayerGroup],
+    ) -> eyre::Result<(BTreeMap<u32, Dna>, PlanFile)> {
+        let metadata_path = self.output_path.as_path().join("metadata");
+        log::debug!(
+            "Loading the DNA of NFTs saved to: {}",
+            metadata_path.to_string_lossy()
+        );
+        let saved = MetadataWriter::new(&metadata_path).saved_dna()?;
+        let plan_file_path = self.output_path.as_path().join("plan.json");
+        let previous = if plan_file_path.is_file() {
+            PlanFile::read(&plan_file_path)?
+        } else {
+            PlanFile::default()
+        };
+
+        // Extending keeps to a single edition without one of ones, so the saved NFTs of
+        // the layers use up its combinations, unlike the DNA of other editions
+        let saved_one_of_ones = previous
+            .tokens
+            .iter()
+            .filter(|token| token.one_of_one.is_some() && saved.contains_key(&token.id))
+            .count();
+        let available = count_combinations(layer_groups)
+            .saturating_sub((saved.len() - saved_one_of_ones) as u128);
+        let requested = self.num.unwrap_or_default();
+        if requested as u128 > available {
+            return Err(NftgenError::NotEnoughCombinations {
+                requested,
+                available,
+            }
+            .into());
+        }
+        Ok((saved, previous))
+    }
+
+    /// Logs how many NFTs of every edition got each tier and writes it to `rarity.json`
+    fn write_rarity(
+        &self,
+        editions: &[Edition],
+        edition_groups: &[Vec<LayerGroup>],
+        nfts: &[Nft],
+    ) -> eyre::Result<()> {
+        let mut rarity = vec![];
+        for (i, (edition, layer_groups)) in editions.iter().zip(edition_groups).enumerate() {
+            let plan: Vec<TokenPlan> = nfts
+                .iter()
+                .filter_map(|nft| match nft {
+                    Nft::Layered(edition_index, token) if *edition_index == i => {
+                        Some(token.clone())
+                    }
+                    _ => None,
+                })
+                .collect();
+            rarity.extend(
+                rarity_report(layer_groups, &plan)
+                    .into_iter()
+                    .map(|tier| TierRarity {
+                        edition: self.editions_path.as_ref().map(|_| edition.name.clone()),
+                        ..tier
+                    }),
+            );
+        }
+
+        if !rarity.is_empty() {
+            for tier in &rarity {
+                log::info!(
+                    "{}{} {}: {} NFTs ({:.2}%, expected {:.2}%)",
+                    tier.edition
+                        .as_ref()
+                        .map(|edition| format!("{} ", edition))
+                        .unwrap_or_default(),
+                    tier.layer_type,
+                    tier.tier,
+                    tier.count,
+                    tier.percent,
+                    tier.expected_percent
+                );
+            }
+            fs::write(
+                self.output_path.as_path().join("rarity.json"),
+                serde_json::to_string_pretty(&rarity)?,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Leaves out the NFTs whose image and metadata are already saved
+    fn skip_saved<'a>(&self, mut nfts: Vec<Nft<'a>>) -> Vec<Nft<'a>> {
+        let planned = nfts.len();
+        nfts.retain(|nft| !self.is_saved(nft.id()));
+        log::info!(
+            "Skipping {} NFTs that are already saved",
+            planned - nfts.len()
+        );
+        nfts
+    }
+
+    /// Renders or copies the images of `nfts` and saves them with their metadata
+    fn render_nfts(&self, nfts: Vec<Nft>, editions: &[Edition]) -> eyre::Result<()> {
         let images_path = self.output_path.as_path().join("images");
         let metadata_path = self.output_path.as_path().join("metadata");
 
         fs::create_dir_all(images_path.as_path())?;
         fs::create_dir_all(metadata_path.as_path())?;
+        let thumbnails_path = self.output_path.as_path().join("thumbnails");
+        for width in &self.thumbnail_sizes {
+            fs::create_dir_all(thumbnails_path.join(width.to_string()))?;
+        }
 
-        log::debug!("Parsing layer groups");
-        let mut layer_groups = get_layer_groups(&self.layers_path, &self.layers_order)?;
-        log::debug!(
-            "Sorting layer groups according to order: {}",
-            self.layers_order.join(", ")
-        );
-        layer_groups.sort_by(|a, b| a.partial_cmp(b).unwrap());
-
+        let png_options = PngOptions {
+            compression: self.png_compression,
+            filter: self.png_filter,
+            reduce_colors: !self.png_keep_rgba,
+            optimize: self.png_optimize,
+        };
+        let attribute_options = AttributeOptions {
+            none_value: self.none_value.as_deref(),
+            nested: self.nested_attributes,
+            tiers: self.tier_attributes,
+        };
+        let num = nfts.len();
         let counter = AtomicU32::new(0);
         log::debug!("Creating Images and Metadata");
-        let results: eyre::Result<Vec<()>> = (0..self.num)
+        let results: eyre::Result<Vec<()>> = nfts
             .into_par_iter()
-            .map(|n| {
-                let image_file_path = images_path.as_path().join(format!("{}.png", n));
-
-                let (nft, layers) = ImageBuilder::build(&layer_groups)?;
-                let metadata = MetadataBuilder::build(
-                    n as u32,
-                    &self.description,
-                    &self.collection_name,
-                    &self.layers_order,
-                    &layers,
-                );
-
-                log::debug!(
-                    "Writing image to file: {}",
-                    image_file_path.to_string_lossy()
-                );
-                match nft.save(&image_file_path) {
-                    Ok(_) => {
-                        log::debug!("Saved image to file: {}", image_file_path.to_string_lossy())
+            .map(|nft| {
+                match nft {
+                    Nft::Layered(i, token) => {
+                        let animation = ImageBuilder::render_animation(&token.layers)?;
+                        let mut metadata = MetadataBuilder::build(
+                            token.id,
+                            &self.description,
+                            &self.collection_name,
+                            &token.layers,
+                            &token.dna,
+                            &attribute_options,
+                            self.format.extension(),
+                        );
+                        metadata.name = editions[i].token_name(&self.collection_name, token.id);
+                        self.save_nft(token.id, animation, metadata, &png_options)?;
                     }
-                    Err(e) => eyre::bail!(e),
-                };
-
-                MetadataWriter::new(&metadata_path).write(&metadata, &n.to_string())?;
-
+                    Nft::OneOfOne(id, one_of_one) => {
+                        log::debug!(
+                            "Copying one of one NFT {} from: {}",
+                            id,
+                            one_of_one.image_path.to_string_lossy()
+                        );
+                        let animation = Animation::read(&one_of_one.image_path)?;
+                        let metadata = one_of_one.metadata(
+                            id,
+                            &self.description,
+                            &self.collection_name,
+                            self.format.extension(),
+                        );
+                        self.save_nft(id, animation, metadata, &png_options)?;
+                    }
+                }
                 counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                log::info!("Saved {:?} / {} NFTs", counter, self.num);
+                log::info!("Saved {:?} / {} NFTs", counter, num);
                 Ok(())
             })
             .collect();
@@ -95,4 +612,384 @@ impl Cmd for GenerateArgs {
         results?;
         Ok(())
     }
+
+    /// Picks unique layers for the NFTs of every edition and the ids of the one of ones,
+    /// sorted by token id. With `saved` NFTs, the new NFTs are numbered after them
+    fn pick_nfts<'a>(
+        &self,
+        seed: u64,
+        editions: &[Edition],
+        edition_groups: &'a [Vec<LayerGroup>],
+        edition_rules: &[Rules],
+        one_of_ones: &'a [OneOfOne],
+        saved: &BTreeMap<u32, Dna>,
+    ) -> eyre::Result<Vec<Nft<'a>>> {
+        let num: usize = editions.iter().map(|edition| edition.count).sum();
+        if let Some(requested) = self.num.filter(|&requested| requested != num) {
+            return Err(NftgenError::InvalidEditions(format!(
+                "--num is {} but the editions add up to {} NFTs",
+                requested, num
+            ))
+            .into());
+        }
+        let one_of_one_ids = one_of_one_ids(one_of_ones, num, seed)?;
+
+        // Every edition keeps clear of the DNA of the saved NFTs and the editions before it
+        let mut taken: HashSet<Dna> = saved.values().cloned().collect();
+        let start = saved.keys().next_back().map_or(0, |id| id + 1);
+        let mut nfts = vec![];
+        for (i, ids) in edition_ids(editions).into_iter().enumerate() {
+            let (edition, layer_groups) = (&editions[i], &edition_groups[i]);
+            let layered_ids: Vec<u32> = ids
+                .filter(|id| !one_of_one_ids.contains(id))
+                .map(|id| start + id)
+                .collect();
+            let edition_seed = edition_seed(seed, i);
+
+            log::debug!(
+                "Picking unique layers for {} NFTs of edition {}",
+                layered_ids.len(),
+                edition.name
+            );
+            let mut edition_plan = match self.allocation {
+                Allocation::Weighted => plan_tokens_excluding(
+                    layer_groups,
+                    &edition_rules[i],
+                    layered_ids.len(),
+                    edition_seed,
+                    &taken,
+                )?,
+                Allocation::Exact => plan_tokens_exact_excluding(
+                    layer_groups,
+                    &edition_rules[i],
+                    layered_ids.len(),
+                    edition_seed,
+                    &taken,
+                )?,
+            };
+            for (token, &id) in edition_plan.iter_mut().zip(&layered_ids) {
+                token.id = id;
+                taken.insert(token.dna.clone());
+            }
+            nfts.extend(edition_plan.into_iter().map(|token| Nft::Layered(i, token)));
+        }
+        nfts.extend(
+            one_of_ones
+                .iter()
+                .zip(one_of_one_ids)
+                .map(|(one_of_one, id)| Nft::OneOfOne(id, one_of_one)),
+        );
+        nfts.sort_by_key(Nft::id);
+        Ok(nfts)
+    }
+
+    /// Looks up the layers and one of ones of a plan file as they are
+    /// NFTs that break the layer rules are only warned about, since they were chosen by hand
+    fn resolve_plan<'a>(
+        &self,
+        plan_file: &PlanFile,
+        editions: &[Edition],
+        edition_groups: &'a [Vec<LayerGroup>],
+        edition_rules: &[Rules],
+        one_of_ones: &'a [OneOfOne],
+    ) -> Result<Vec<Nft<'a>>, NftgenError> {
+        // `--num` of the config file is meant for runs that pick their layers
+        if let Some(requested) = self.num.filter(|&requested| {
+            requested != plan_file.tokens.len() && is_cli_arg(&["--num", "-n"])
+        }) {
+            return Err(NftgenError::InvalidPlan(format!(
+                "--num is {} but the plan has {} NFTs",
+                requested,
+                plan_file.tokens.len()
+            )));
+        }
+
+        let mut ids = HashSet::new();
+        let mut dnas = HashSet::new();
+        plan_file
+            .tokens
+            .iter()
+            .map(|token| {
+                let invalid = |message: String| {
+                    NftgenError::InvalidPlan(format!("NFT #{} {}", token.id, message))
+                };
+                if !ids.insert(token.id) {
+                    return Err(invalid("is listed twice".to_string()));
+                }
+                let nft = match &token.one_of_one {
+                    Some(name) => Nft::OneOfOne(
+                        token.id,
+                        one_of_ones
+                            .iter()
+                            .find(|one_of_one| one_of_one.file_name() == name)
+                            .ok_or_else(|| {
+                                invalid(format!("has an unknown one of one {}", name))
+                            })?,
+                    ),
+                    None => {
+                        let i = match (&token.edition, &self.editions_path) {
+                            (Some(name), Some(_)) => editions
+                                .iter()
+                                .position(|edition| &edition.name == name)
+                                .ok_or_else(|| {
+                                    invalid(format!("has an unknown edition {}", name))
+                                })?,
+                            (None, None) => 0,
+                            (Some(name), None) => {
+                                return Err(invalid(format!(
+                                    "has the edition {} but there is no --editions-path",
+                                    name
+                                )))
+                            }
+                            (None, Some(_)) => return Err(invalid("has no edition".to_string())),
+                        };
+                        let token_plan = token.resolve(&edition_groups[i])?;
+                        if !edition_rules[i].allows_all(&token_plan.layers) {
+                            log::warn!("NFT #{} breaks the layer rules", token.id);
+                        }
+                        Nft::Layered(i, token_plan)
+                    }
+                };
+                let dna = match &nft {
+                    Nft::Layered(_, token_plan) => token_plan.dna.clone(),
+                    Nft::OneOfOne(_, one_of_one) => one_of_one.dna(),
+                };
+                if !dnas.insert(dna) {
+                    return Err(invalid("has the same layers as another NFT".to_string()));
+                }
+                Ok(nft)
+            })
+            .collect()
+    }
+    /// Parses the layer groups of `edition` and applies the layer settings to those it has
+    fn edition_layer_groups(
+        &self,
+        edition: &Edition,
+        image_cache: &Arc<ImageCache>,
+    ) -> eyre::Result<Vec<LayerGroup>> {
+        log::debug!("Parsing layer groups of edition {}", edition.name);
+        let mut layer_groups = get_layer_groups(&edition.layers_path, &edition.layers_order)?;
+        layer_groups.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        log::debug!(
+            "Sorted layer groups according to order: {}",
+            layer_groups
+                .iter()
+                .map(|layer_group| layer_group.layer_type.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        let mut layer_groups: Vec<_> = layer_groups
+            .into_iter()
+            .map(|layer_group| layer_group.with_image_cache(image_cache.clone()))
+            .collect();
+
+        for (layer_type, skip_probability) in &self.optional_layers {
+            if let Some(index) = layer_groups
+                .iter()
+                .position(|group| &group.layer_type == layer_type)
+            {
+                let layer_group = layer_groups.remove(index);
+                layer_groups.insert(index, layer_group.with_skip_probability(*skip_probability));
+            }
+        }
+
+        for (link, layer_types) in &self.linked_layers {
+            for layer_type in layer_types.split('+') {
+                if let Some(index) = layer_groups
+                    .iter()
+                    .position(|group| group.layer_type == layer_type)
+                {
+                    let layer_group = layer_groups.remove(index);
+                    layer_groups.insert(index, layer_group.with_link(link)?);
+                }
+            }
+        }
+        check_links(&layer_groups)?;
+
+        configure_layers(&mut layer_groups, &self.blend_modes, |group, mode, name| {
+            group.with_blend_mode(mode, name)
+        })?;
+        configure_layers(
+            &mut layer_groups,
+            &self.opacities,
+            |group, opacity, name| group.with_opacity(opacity, name),
+        )?;
+        configure_layers(
+            &mut layer_groups,
+            &self.positions,
+            |group, position, name| group.with_position(position, name),
+        )?;
+        Ok(layer_groups)
+    }
+
+    /// Checks that every layer type or trait named by a layer setting is in some edition
+    fn check_layer_settings(&self, edition_groups: &[Vec<LayerGroup>]) -> Result<(), NftgenError> {
+        let keys = self
+            .optional_layers
+            .iter()
+            .map(|(layer_type, _)| layer_type.clone())
+            .chain(
+                self.linked_layers
+                    .iter()
+                    .flat_map(|(_, layer_types)| layer_types.split('+').map(str::to_string)),
+            )
+            .chain(self.blend_modes.iter().map(|(key, _)| key.clone()))
+            .chain(self.opacities.iter().map(|(key, _)| key.clone()))
+            .chain(self.positions.iter().map(|(key, _)| key.clone()));
+        for key in keys {
+            if !edition_groups
+                .iter()
+                .any(|layer_groups| has_layer(layer_groups, &key))
+            {
+                return Err(NftgenError::UnknownLayer(key));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if NFT `id` is saved to the output directory, which writes its metadata last
+    fn is_saved(&self, id: u32) -> bool {
+        let image_file_path =
+            self.output_path
+                .join("images")
+                .join(format!("{}.{}", id, self.format.extension()));
+        image_file_path.is_file()
+            && self
+                .output_path
+                .join("metadata")
+                .join(id.to_string())
+                .is_file()
+    }
+
+    /// Saves the image, thumbnails, animation and metadata of NFT `id` to the output directory
+    fn save_nft(
+        &self,
+        id: u32,
+        animation: Animation,
+        mut metadata: Metadata,
+        png_options: &PngOptions,
+    ) -> eyre::Result<()> {
+        let extension = self.format.extension();
+        let image_file_path = self
+            .output_path
+            .join("images")
+            .join(format!("{}.{}", id, extension));
+
+        let nft = animation.first_frame().clone();
+        for width in self.size.iter().chain(&self.thumbnail_sizes) {
+            self.resize_filter.check_scale(nft.width, *width)?;
+        }
+        if animation.is_animated() {
+            let animations_path = self.output_path.join("animations");
+            let animation_extension = self.animation_format.extension();
+            let animation_file_path =
+                animations_path.join(format!("{}.{}", id, animation_extension));
+            fs::create_dir_all(animations_path.as_path())?;
+            let animation = match self.size {
+                Some(width) => animation.resize_to_width(width, self.resize_filter),
+                None => animation,
+            };
+            log::debug!(
+                "Writing animation to file: {}",
+                animation_file_path.to_string_lossy()
+            );
+            animation.save_as(
+                &animation_file_path,
+                self.animation_format,
+                self.quality,
+                png_options,
+            )?;
+            metadata.animation_url = Some(placeholder_uri(id, animation_extension));
+        }
+
+        for width in &self.thumbnail_sizes {
+            let thumbnail_file_path = self
+                .output_path
+                .join("thumbnails")
+                .join(width.to_string())
+                .join(format!("{}.{}", id, extension));
+            nft.resize_to_width(*width, self.resize_filter).save_as(
+                &thumbnail_file_path,
+                self.format,
+                self.quality,
+                png_options,
+            )?;
+        }
+        let nft = match self.size {
+            Some(width) => nft.resize_to_width(width, self.resize_filter),
+            None => nft,
+        };
+
+        log::debug!(
+            "Writing image to file: {}",
+            image_file_path.to_string_lossy()
+        );
+        match nft.save_as(&image_file_path, self.format, self.quality, png_options) {
+            Ok(_) => {
+                log::debug!("Saved image to file: {}", image_file_path.to_string_lossy())
+            }
+            Err(e) => eyre::bail!(e),
+        };
+
+        MetadataWriter::new(&self.output_path.join("metadata")).write(&metadata, id.to_string())?;
+        Ok(())
+    }
+}
+
+/// An NFT to render: layers picked from the edition at an index, or a one of one
+enum Nft<'a> {
+    Layered(usize, TokenPlan<'a>),
+    OneOfOne(u32, &'a OneOfOne),
+}
+
+impl Nft<'_> {
+    fn id(&self) -> u32 {
+        match self {
+            Nft::Layered(_, token) => token.id,
+            Nft::OneOfOne(id, _) => *id,
+        }
+    }
+}
+
+/// Applies `settings` keyed by `<layer type>` to whole layer groups, then those keyed by
+/// `<layer type>/<name>` to single traits, so trait settings override group settings
+/// Settings of layer types or traits that are not in `layer_groups` are skipped
+fn configure_layers<T: Copy>(
+    layer_groups: &mut Vec<LayerGroup>,
+    settings: &[(String, T)],
+    configure: impl Fn(LayerGroup, T, Option<&str>) -> Result<LayerGroup, NftgenError>,
+) -> Result<(), NftgenError> {
+    let mut settings: Vec<_> = settings.iter().collect();
+    settings.sort_by_key(|(key, _)| key.contains('/'));
+
+    for (key, value) in settings {
+        if !has_layer(layer_groups, key) {
+            continue;
+        }
+        let (layer_type, name) = split_layer_key(key);
+        let index = layer_groups
+            .iter()
+            .position(|group| group.layer_type == layer_type)
+            .ok_or_else(|| NftgenError::UnknownLayer(key.clone()))?;
+        let layer_group = layer_groups.remove(index);
+        layer_groups.insert(index, configure(layer_group, *value, name)?);
+    }
+    Ok(())
+}
+
+/// Splits a `<layer type>` or `<layer type>/<name>` key of a layer setting
+fn split_layer_key(key: &str) -> (&str, Option<&str>) {
+    match key.split_once('/') {
+        Some((layer_type, name)) => (layer_type, Some(name)),
+        None => (key, None),
+    }
+}
+
+/// Returns true if `layer_groups` have the layer type or trait named by `key`
+fn has_layer(layer_groups: &[LayerGroup], key: &str) -> bool {
+    let (layer_type, name) = split_layer_key(key);
+    layer_groups.iter().any(|group| {
+        group.layer_type == layer_type && name.is_none_or(|name| group.has_layer(name))
+    })
 }
@@ -1,5 +1,6 @@
 mod config;
 mod generate;
+mod render;
 mod upload;
 
 pub mod opts;
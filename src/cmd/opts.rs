@@ -1,7 +1,7 @@
 use clap::{Parser, Subcommand};
 use std::env;
 
-use crate::cmd::{config, generate::GenerateArgs, upload::UploadArgs};
+use crate::cmd::{config, generate::GenerateArgs, render::RenderArgs, upload::UploadArgs};
 
 /// Generate images and metadata for NFTs by layering PNGs together.
 #[derive(Debug, Parser)]
@@ -19,6 +19,10 @@ pub enum Subcommands {
     #[clap(about = "Generate nft images and metadata")]
     Generate(GenerateArgs),
 
+    #[clap(visible_alias = "r")]
+    #[clap(about = "Render nft images and metadata of exact traits from a spec")]
+    Render(RenderArgs),
+
     #[clap(about = "Upload nft images and metadata to IPFS")]
     #[clap(visible_alias = "u")]
     Upload(UploadArgs),
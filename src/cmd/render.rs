@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use crate::cmd::{generate::GenerateArgs, is_cli_arg, Cmd};
+use crate::nft::{read_spec, PlanFile};
+
+#[derive(Debug, Clone, Parser)]
+pub struct RenderArgs {
+    /// path to a CSV or JSON file of the NFTs to render, with an `id` column and one column per
+    /// layer type naming the layer, e.g. `tee` or `Shirts/tee`, and empty for no layer.
+    /// Columns named after a link of `--linked-layers` hold the variant, an `edition` column
+    /// the edition of `--editions-path`. `--num` and `--seed` are ignored
+    #[clap(long, value_hint = clap::ValueHint::FilePath)]
+    pub spec: PathBuf,
+
+    #[clap(flatten)]
+    pub generate: GenerateArgs,
+}
+
+impl Cmd for RenderArgs {
+    type Output = ();
+
+    fn run(self) -> eyre::Result<Self::Output> {
+        // Options of the config file apply to every command, so only reject those given here
+        for name in ["--plan-path", "--plan-only", "--extend"] {
+            if is_cli_arg(&[name]) {
+                eyre::bail!("{} cannot be used with --spec", name);
+            }
+        }
+        let generate = GenerateArgs {
+            num: None,
+            seed: None,
+            plan_path: None,
+            plan_only: false,
+            extend: false,
+            ..self.generate
+        };
+
+        log::debug!("Loading spec from: {}", self.spec.to_string_lossy());
+        let links: Vec<&str> = generate
+            .linked_layers
+            .iter()
+            .map(|(link, _)| link.as_str())
+            .collect();
+        let tokens = read_spec(&self.spec, &links)?;
+        generate.generate(Some(PlanFile {
+            seed: None,
+            extensions: vec![],
+            tokens,
+        }))
+    }
+}
@@ -36,6 +36,8 @@ impl UploadArgs {
         let metadata_path = output_path.as_path().join("metadata");
         let images_car_file_path = output_path.as_path().join("images.car");
         let metadata_car_file_path = output_path.as_path().join("metadata.car");
+        let animations_path = output_path.as_path().join("animations");
+        let animations_car_file_path = output_path.as_path().join("animations.car");
 
         let ipfs_cli = ipfs::Cli::new().await?;
 
@@ -45,6 +47,19 @@ impl UploadArgs {
 
         MetadataWriter::new(metadata_path.as_path()).update_base_uri_for_all_images(&images_cid)?;
 
+        // Only collections with animated layers have animations
+        let animations_cid = match animations_path.is_dir() {
+            true => {
+                let animations_cid = ipfs_cli
+                    .add(animations_path.as_os_str().to_string_lossy().as_ref())
+                    .await?;
+                MetadataWriter::new(metadata_path.as_path())
+                    .update_base_uri_for_all_animations(&animations_cid)?;
+                Some(animations_cid)
+            }
+            false => None,
+        };
+
         let metadata_cid = ipfs_cli
             .add(metadata_path.as_os_str().to_string_lossy().as_ref())
             .await?;
@@ -57,11 +72,23 @@ impl UploadArgs {
             .dag_export(&metadata_cid, &metadata_car_file_path)
             .await?;
 
+        if let Some(animations_cid) = &animations_cid {
+            ipfs_cli
+                .dag_export(animations_cid, &animations_car_file_path)
+                .await?;
+        }
+
         let nftstorage_client = nftstorage::Client::new(api_key);
         nftstorage_client
             .upload_car_to_nft_storage(&images_car_file_path)
             .await?;
 
+        if animations_cid.is_some() {
+            nftstorage_client
+                .upload_car_to_nft_storage(&animations_car_file_path)
+                .await?;
+        }
+
         nftstorage_client
             .upload_car_to_nft_storage(&metadata_car_file_path)
             .await?;
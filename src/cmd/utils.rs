@@ -3,3 +3,29 @@ pub trait Cmd: clap::Parser + Sized {
     type Output;
     fn run(self) -> eyre::Result<Self::Output>;
 }
+
+/// Returns true if one of `names`, e.g. `["--num", "-n"]`, is given on the command line rather
+/// than only in the config file
+pub fn is_cli_arg(names: &[&str]) -> bool {
+    std::env::args_os().skip(1).any(|arg| {
+        arg.to_str().is_some_and(|arg| {
+            names.iter().any(|&name| match arg.strip_prefix(name) {
+                Some(rest) if name.starts_with("--") => rest.is_empty() || rest.starts_with('='),
+                Some(_) => !arg.starts_with("--"),
+                None => false,
+            })
+        })
+    })
+}
+
+/// Parses a single `KEY=VALUE` command line argument
+pub fn parse_key_val<T>(s: &str) -> eyre::Result<(String, T)>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match s.split_once('=') {
+        Some((key, value)) => Ok((key.to_string(), value.parse()?)),
+        None => eyre::bail!("invalid KEY=VALUE: no `=` found in `{}`", s),
+    }
+}
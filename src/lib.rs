@@ -18,6 +18,28 @@ pub enum NftgenError {
     #[error(transparent)]
     Decode(#[from] png::DecodingError),
 
+    #[error(transparent)]
+    GifDecode(#[from] gif::DecodingError),
+
+    #[error("Animation has no frames: {0}")]
+    EmptyAnimation(PathBuf),
+
+    #[error(transparent)]
+    JpegEncode(#[from] jpeg_encoder::EncodingError),
+
+    #[error(transparent)]
+    GifEncode(#[from] gif::EncodingError),
+
+    #[error("Could not encode WebP image: {0}")]
+    WebpEncode(String),
+
+    #[error("Image of {width}x{height} pixels is too large for {format}")]
+    ImageTooLarge {
+        format: String,
+        width: u32,
+        height: u32,
+    },
+
     #[error(transparent)]
     JsonEncode(#[from] serde_json::Error),
 
@@ -36,6 +58,72 @@ pub enum NftgenError {
     #[error("Unknown layer: '{0}'")]
     UnknownLayer(String),
 
+    #[error("Layer part has no layer of the same name: '{0}'")]
+    OrphanLayerPart(PathBuf),
+
+    #[error("Unknown blend mode: '{0}'")]
+    InvalidBlendMode(String),
+
+    #[error("Invalid layer position, expected an anchor or <x>:<y>: '{0}'")]
+    InvalidPosition(String),
+
+    #[error(
+        "Layer '{path}' is {}x{} but the canvas is {}x{}; give it a position to place smaller layers",
+        found.0, found.1, expected.0, expected.1
+    )]
+    DimensionMismatch {
+        path: PathBuf,
+        expected: (u32, u32),
+        found: (u32, u32),
+    },
+
+    #[error("Invalid image size: {0}")]
+    InvalidSize(String),
+
+    #[error("NFT has no layers to render")]
+    NothingToRender,
+
+    #[error("Invalid palette: {0}")]
+    InvalidPalette(String),
+
+    #[error("Invalid linked layers: {0}")]
+    InvalidLink(String),
+
+    #[error("Invalid layer manifest: {0}")]
+    InvalidManifest(String),
+
+    #[error("Invalid editions: {0}")]
+    InvalidEditions(String),
+
+    #[error("Invalid plan: {0}")]
+    InvalidPlan(String),
+
+    #[error("Invalid spec: {0}")]
+    InvalidSpec(String),
+
+    #[error("Invalid one of one NFT: {0}")]
+    InvalidOneOfOne(String),
+
+    #[error("Saved metadata '{path}' has no valid dna to extend the collection with: {reason}")]
+    InvalidSavedMetadata { path: PathBuf, reason: String },
+
+    #[error("Invalid layer rule: {0}")]
+    InvalidRule(String),
+
+    #[error("No combination of layers satisfies the layer rules")]
+    UnsatisfiableRules,
+
+    #[error(
+        "Requested {requested} NFTs but the layers only allow {available} unique combinations"
+    )]
+    NotEnoughCombinations { requested: usize, available: u128 },
+
+    #[error("Layer quotas cannot be met: {0}")]
+    QuotaUnmet(String),
+
+    #[error("Ran out of unique layer combinations while picking layers for NFT #{0}")]
+    CombinationsExhausted(u32),
+
     #[error("CAR file > 100MB; too large to upload to nft.storage: '{0}'")]
     CarTooLarge(PathBuf),
 
@@ -0,0 +1,601 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::nft::png_encoder::encode_png;
+use crate::{AnimationFormat, BlendMode, Image, NftgenError, PngOptions, ResizeFilter};
+
+/// Delay of frames that do not set one, as browsers do for GIFs
+const DEFAULT_DELAY_MS: u32 = 100;
+
+/// Longest animation rendered from animated layers of different durations
+/// Longer loops are cut to the duration of the longest layer
+pub const MAX_ANIMATION_MS: u32 = 60_000;
+
+/// A complete image shown for `delay_ms`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub image: Image,
+    pub delay_ms: u32,
+}
+
+/// Frames of a looping animation. Still images are animations with a single frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct Animation {
+    pub frames: Vec<Frame>,
+}
+
+impl Animation {
+    pub fn still(image: Image) -> Self {
+        Animation {
+            frames: vec![Frame { image, delay_ms: 0 }],
+        }
+    }
+
+    /// Reads a PNG, APNG or GIF (by extension) as RGBA frames
+    /// Frames that only update part of the image are composited into complete images
+    pub fn read<P: AsRef<Path>>(image_path: P) -> Result<Self, NftgenError> {
+        let image_path = image_path.as_ref();
+        let animation = match image_path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("gif") => {
+                Animation::read_gif(image_path)?
+            }
+            _ => Animation::read_png(image_path)?,
+        };
+        if animation.frames.is_empty() {
+            return Err(NftgenError::EmptyAnimation(image_path.to_owned()));
+        }
+        Ok(animation)
+    }
+
+    fn read_png(image_path: &Path) -> Result<Self, NftgenError> {
+        let mut decoder = png::Decoder::new(File::open(image_path)?);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let info = reader.info();
+        let (width, height) = (info.width, info.height);
+        let num_frames = info.animation_control().map(|control| control.num_frames);
+        let mut buf = vec![0; reader.output_buffer_size()];
+
+        let num_frames = match num_frames {
+            Some(num_frames) => num_frames,
+            None => {
+                let frame = reader.next_frame(&mut buf)?;
+                return Ok(Animation::still(Image::from_png_frame(buf, &frame)));
+            }
+        };
+        if reader.info().frame_control().is_none() {
+            // The default image of an APNG without a frame control is not part of the animation
+            reader.next_frame(&mut buf)?;
+        }
+
+        let mut compositor = Compositor::new(width, height);
+        for _ in 0..num_frames {
+            let frame = reader.next_frame(&mut buf)?;
+            let subframe = Image::from_png_frame(buf.clone(), &frame);
+            let control = reader.info().frame_control().copied().unwrap_or_default();
+            let delay_den = match control.delay_den {
+                0 => 100,
+                delay_den => delay_den,
+            };
+            compositor.add(
+                &subframe,
+                (control.x_offset as i64, control.y_offset as i64),
+                control.blend_op == png::BlendOp::Source,
+                match control.dispose_op {
+                    png::DisposeOp::None => Disposal::Keep,
+                    png::DisposeOp::Background => Disposal::Clear,
+                    png::DisposeOp::Previous => Disposal::Restore,
+                },
+                control.delay_num as u32 * 1000 / delay_den as u32,
+            );
+        }
+        Ok(compositor.into_animation())
+    }
+
+    fn read_gif(image_path: &Path) -> Result<Self, NftgenError> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(File::open(image_path)?)?;
+
+        let mut compositor = Compositor::new(decoder.width() as u32, decoder.height() as u32);
+        while let Some(frame) = decoder.read_next_frame()? {
+            let subframe = Image::new(
+                frame.buffer.to_vec(),
+                4,
+                frame.width as u32,
+                frame.height as u32,
+            );
+            compositor.add(
+                &subframe,
+                (frame.left as i64, frame.top as i64),
+                false,
+                match frame.dispose {
+                    gif::DisposalMethod::Background => Disposal::Clear,
+                    gif::DisposalMethod::Previous => Disposal::Restore,
+                    _ => Disposal::Keep,
+                },
+                frame.delay as u32 * 10,
+            );
+        }
+        Ok(compositor.into_animation())
+    }
+
+    /// Returns true if there is more than one frame
+    pub fn is_animated(&self) -> bool {
+        self.frames.len() > 1
+    }
+
+    /// Time it takes to show every frame once
+    pub fn duration_ms(&self) -> u32 {
+        self.frames.iter().map(|frame| frame.delay_ms).sum()
+    }
+
+    pub fn first_frame(&self) -> &Image {
+        &self.frames[0].image
+    }
+
+    /// Returns the frame shown `time_ms` after the animation started, looping it
+    pub fn frame_at(&self, time_ms: u32) -> &Image {
+        let duration_ms = self.duration_ms();
+        if duration_ms == 0 {
+            return self.first_frame();
+        }
+        let mut time_ms = time_ms % duration_ms;
+        for frame in &self.frames {
+            if time_ms < frame.delay_ms {
+                return &frame.image;
+            }
+            time_ms -= frame.delay_ms;
+        }
+        unreachable!("Time within the duration should fall into a frame")
+    }
+
+    /// Size of the pixel data of all frames in bytes
+    pub fn size_in_bytes(&self) -> usize {
+        self.frames
+            .iter()
+            .map(|frame| frame.image.size_in_bytes())
+            .sum()
+    }
+
+    /// Resizes every frame to `width` pixels wide, keeping the aspect ratio
+    pub fn resize_to_width(&self, width: u32, filter: ResizeFilter) -> Animation {
+        Animation {
+            frames: self
+                .frames
+                .iter()
+                .map(|frame| Frame {
+                    image: frame.image.resize_to_width(width, filter),
+                    delay_ms: frame.delay_ms,
+                })
+                .collect(),
+        }
+    }
+
+    /// Saves the animation in `format`, looping forever
+    /// `quality` from 0 to 100 applies to the lossy `webp` format, `png_options` to `png`
+    pub fn save_as<P: AsRef<Path>>(
+        &self,
+        output_path: P,
+        format: AnimationFormat,
+        quality: u8,
+        png_options: &PngOptions,
+    ) -> Result<(), NftgenError> {
+        let first_frame = self.first_frame();
+        match format {
+            AnimationFormat::Png => {
+                let frames: Vec<&Image> = self.frames.iter().map(|frame| &frame.image).collect();
+                let delays_ms: Vec<u32> = self.frames.iter().map(|frame| frame.delay_ms).collect();
+                let png = encode_png(&frames, &delays_ms, png_options)?;
+                Ok(std::fs::write(output_path, png)?)
+            }
+            AnimationFormat::Gif => {
+                let (width, height) = first_frame.size_u16("GIF")?;
+                let w = BufWriter::new(File::create(output_path)?);
+                let mut encoder = gif::Encoder::new(w, width, height, &[])?;
+                encoder.set_repeat(gif::Repeat::Infinite)?;
+                for frame in &self.frames {
+                    let mut pixels = frame.image.data().to_vec();
+                    let mut gif_frame = gif::Frame::from_rgba_speed(width, height, &mut pixels, 10);
+                    gif_frame.delay = (frame.delay_ms / 10).min(u16::MAX as u32) as u16;
+                    // Frames are complete images, so transparent pixels must not show the previous frame
+                    gif_frame.dispose = gif::DisposalMethod::Background;
+                    encoder.write_frame(&gif_frame)?;
+                }
+                Ok(())
+            }
+            AnimationFormat::Webp | AnimationFormat::WebpLossless => {
+                let mut config = webp::WebPConfig::new()
+                    .map_err(|_| NftgenError::WebpEncode("Invalid configuration".to_string()))?;
+                config.lossless = (format == AnimationFormat::WebpLossless) as i32;
+                config.quality = quality.min(100) as f32;
+                let mut encoder =
+                    webp::AnimEncoder::new(first_frame.width, first_frame.height, &config);
+                encoder.set_loop_count(0);
+                let mut timestamp_ms = 0;
+                for frame in &self.frames {
+                    encoder.add_frame(webp::AnimFrame::from_rgba(
+                        frame.image.data(),
+                        frame.image.width,
+                        frame.image.height,
+                        timestamp_ms as i32,
+                    ));
+                    timestamp_ms += frame.delay_ms;
+                }
+                let webp = encoder
+                    .try_encode()
+                    .map_err(|e| NftgenError::WebpEncode(format!("{:?}", e)))?;
+                let mut webp = webp.to_vec();
+                set_webp_duration(&mut webp, timestamp_ms);
+                Ok(std::fs::write(output_path, webp)?)
+            }
+        }
+    }
+
+    /// Start time and delay of every frame of an NFT made of `animations`, which loop
+    /// independently. Frames change whenever one of the animations changes frames.
+    /// The NFT loops after the least common multiple of the durations of the animations,
+    /// or after the longest animation if that exceeds `MAX_ANIMATION_MS`
+    pub fn timeline(animations: &[&Animation]) -> Vec<(u32, u32)> {
+        let durations: Vec<u64> = animations
+            .iter()
+            .filter(|animation| animation.is_animated())
+            .map(|animation| animation.duration_ms() as u64)
+            .filter(|&duration_ms| duration_ms > 0)
+            .collect();
+        let longest = match durations.iter().max() {
+            Some(&longest) => longest,
+            None => return vec![(0, 0)],
+        };
+        let mut length = durations.iter().fold(1, |length: u64, &duration| {
+            (length / gcd(length, duration)).saturating_mul(duration)
+        });
+        if length > MAX_ANIMATION_MS as u64 {
+            log::warn!(
+                "Animated layers only line up again after {} ms, cutting the animation to {} ms",
+                length,
+                longest
+            );
+            length = longest;
+        }
+
+        let mut changes = BTreeSet::new();
+        for animation in animations
+            .iter()
+            .filter(|animation| animation.is_animated())
+        {
+            let mut time_ms = 0;
+            'animation: loop {
+                for frame in &animation.frames {
+                    if time_ms >= length {
+                        break 'animation;
+                    }
+                    changes.insert(time_ms);
+                    time_ms += frame.delay_ms as u64;
+                }
+                if animation.duration_ms() == 0 {
+                    break;
+                }
+            }
+        }
+        let changes: Vec<u64> = changes.into_iter().collect();
+        changes
+            .iter()
+            .zip(changes.iter().skip(1).chain([&length]))
+            .map(|(&start, &end)| (start as u32, (end - start) as u32))
+            .collect()
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Sets the duration of the last frame of an animated WebP, so the animation lasts `duration_ms`
+/// The webp crate does not pass the end of the animation to libwebp, which then guesses it
+fn set_webp_duration(webp: &mut [u8], duration_ms: u32) {
+    // After the RIFF header, chunks are a FourCC, a little endian size and an even sized payload
+    let mut offset = 12;
+    let mut elapsed_ms = 0;
+    let mut last_frame = None;
+    while offset + 8 <= webp.len() {
+        let size = u32::from_le_bytes(webp[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        if &webp[offset..offset + 4] == b"ANMF" {
+            // Frame position and size precede the 24 bit duration
+            let duration_offset = offset + 8 + 12;
+            let frame_ms = u32::from_le_bytes([
+                webp[duration_offset],
+                webp[duration_offset + 1],
+                webp[duration_offset + 2],
+                0,
+            ]);
+            last_frame = Some((duration_offset, elapsed_ms));
+            elapsed_ms += frame_ms;
+        }
+        offset += 8 + size + size % 2;
+    }
+    if let Some((duration_offset, start_ms)) = last_frame {
+        let frame_ms = duration_ms.saturating_sub(start_ms).min(0xFFFFFF);
+        webp[duration_offset..duration_offset + 3].copy_from_slice(&frame_ms.to_le_bytes()[..3]);
+    }
+}
+
+/// What happens to the area of a frame before the next frame is drawn
+enum Disposal {
+    Keep,
+    Clear,
+    Restore,
+}
+
+/// Draws the partial frames of APNGs and GIFs onto a canvas, keeping every complete frame
+struct Compositor {
+    canvas: Image,
+    frames: Vec<Frame>,
+}
+
+impl Compositor {
+    fn new(width: u32, height: u32) -> Self {
+        Compositor {
+            canvas: Image::new(
+                vec![0; width as usize * height as usize * 4],
+                4,
+                width,
+                height,
+            ),
+            frames: vec![],
+        }
+    }
+
+    fn add(
+        &mut self,
+        subframe: &Image,
+        offset: (i64, i64),
+        replace: bool,
+        disposal: Disposal,
+        delay_ms: u32,
+    ) {
+        let previous = match disposal {
+            Disposal::Restore => Some(self.canvas.clone()),
+            _ => None,
+        };
+        if replace {
+            self.canvas
+                .clear_region(offset, subframe.width, subframe.height);
+        }
+        self.canvas
+            .blend_at(subframe, offset, BlendMode::Normal, 1.0);
+        self.frames.push(Frame {
+            image: self.canvas.clone(),
+            delay_ms: match delay_ms {
+                0 => DEFAULT_DELAY_MS,
+                delay_ms => delay_ms,
+            },
+        });
+
+        match disposal {
+            Disposal::Keep => {}
+            Disposal::Clear => self
+                .canvas
+                .clear_region(offset, subframe.width, subframe.height),
+            Disposal::Restore => self.canvas = previous.unwrap(),
+        }
+    }
+
+    fn into_animation(self) -> Animation {
+        Animation {
+            frames: self.frames,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nft::tests::fixture::Fixture;
+
+    const R: [u8; 4] = [255, 0, 0, 255];
+    const G: [u8; 4] = [0, 255, 0, 255];
+    const B: [u8; 4] = [0, 0, 255, 255];
+    const W: [u8; 4] = [255, 255, 255, 255];
+    const K: [u8; 4] = [0, 0, 0, 255];
+    const T: [u8; 4] = [0, 0, 0, 0];
+
+    fn image(pixels: &[[u8; 4]]) -> Image {
+        Image::new(pixels.concat(), 4, 4, 2)
+    }
+
+    fn delays(animation: &Animation) -> Vec<u32> {
+        animation
+            .frames
+            .iter()
+            .map(|frame| frame.delay_ms)
+            .collect()
+    }
+
+    /// A 1x1 animation with a frame of a different color for every delay
+    fn animation_of(delays: &[u32]) -> Animation {
+        Animation {
+            frames: delays
+                .iter()
+                .enumerate()
+                .map(|(i, &delay_ms)| Frame {
+                    image: Image::new(vec![i as u8, 0, 0, 255], 4, 1, 1),
+                    delay_ms,
+                })
+                .collect(),
+        }
+    }
+
+    mod read {
+        use super::*;
+
+        #[test]
+        fn still_png() {
+            let path = Fixture::source_path("rgb8.png");
+            let animation = Animation::read(&path).unwrap();
+            assert!(!animation.is_animated());
+            assert_eq!(animation.first_frame(), &Image::read(&path).unwrap());
+        }
+
+        #[test]
+        fn apng_subframes() {
+            let animation = Animation::read(Fixture::source_path("animated.png")).unwrap();
+            assert_eq!(delays(&animation), vec![100, 200, 100]);
+            assert_eq!(animation.frames[0].image, image(&[R, R, R, R, R, R, R, R]));
+            assert_eq!(animation.frames[1].image, image(&[R, R, R, R, R, R, G, G]));
+            // Source blending replaces the pixel with a transparent one
+            assert_eq!(animation.frames[2].image, image(&[T, R, R, R, R, R, G, G]));
+        }
+
+        #[test]
+        fn apng_skips_default_image_without_frame_control() {
+            let animation =
+                Animation::read(Fixture::source_path("animated_hidden_default.png")).unwrap();
+            assert_eq!(delays(&animation), vec![50, 50]);
+            assert_eq!(animation.frames[0].image, image(&[W; 8]));
+            assert_eq!(animation.frames[1].image, image(&[K; 8]));
+        }
+
+        #[test]
+        fn gif_frames() {
+            let animation = Animation::read(Fixture::source_path("animated.gif")).unwrap();
+            assert_eq!(delays(&animation), vec![100, 300, DEFAULT_DELAY_MS]);
+            assert_eq!(animation.frames[0].image, image(&[R; 8]));
+            // Transparent pixels of a frame show the frame below
+            assert_eq!(animation.frames[1].image, image(&[R, B, R, R, R, R, R, R]));
+            // The second frame is cleared before the third is drawn
+            assert_eq!(animation.frames[2].image, image(&[R, T, T, R, G, R, R, R]));
+        }
+
+        #[test]
+        fn invalid_png() {
+            let result = Animation::read(Fixture::source_path("empty.png"));
+            assert!(matches!(result, Err(NftgenError::Decode(_))));
+        }
+    }
+
+    #[test]
+    fn frame_at_loops() {
+        let animation = animation_of(&[100, 200]);
+        let frame_index = |time_ms| animation.frame_at(time_ms).data()[0];
+        assert_eq!(frame_index(0), 0);
+        assert_eq!(frame_index(99), 0);
+        assert_eq!(frame_index(100), 1);
+        assert_eq!(frame_index(299), 1);
+        assert_eq!(frame_index(300), 0);
+        assert_eq!(frame_index(450), 1);
+    }
+
+    mod timeline {
+        use super::*;
+
+        #[test]
+        fn still_images() {
+            let still = Animation::still(Image::new(vec![0; 4], 4, 1, 1));
+            assert_eq!(Animation::timeline(&[&still, &still]), vec![(0, 0)]);
+        }
+
+        #[test]
+        fn loops_animations_until_they_line_up() {
+            let still = Animation::still(Image::new(vec![0; 4], 4, 1, 1));
+            let blinking = animation_of(&[250, 50]);
+            let flickering = animation_of(&[100, 100]);
+            assert_eq!(
+                Animation::timeline(&[&still, &blinking, &flickering]),
+                vec![
+                    (0, 100),
+                    (100, 100),
+                    (200, 50),
+                    (250, 50),
+                    (300, 100),
+                    (400, 100),
+                    (500, 50),
+                    (550, 50),
+                ]
+            );
+        }
+
+        #[test]
+        fn cuts_long_loops_to_longest_animation() {
+            let first = animation_of(&[7000, 6000]);
+            let second = animation_of(&[9000, 8000]);
+            let timeline = Animation::timeline(&[&first, &second]);
+            assert_eq!(timeline.iter().map(|(_, delay)| delay).sum::<u32>(), 17000);
+            assert_eq!(
+                timeline,
+                vec![(0, 7000), (7000, 2000), (9000, 4000), (13000, 4000)]
+            );
+        }
+    }
+
+    mod save_as {
+        use super::*;
+
+        fn animation() -> Animation {
+            Animation {
+                frames: vec![
+                    Frame {
+                        image: image(&[R, R, G, G, B, B, T, T]),
+                        delay_ms: 100,
+                    },
+                    Frame {
+                        image: image(&[B, B, R, R, G, G, T, T]),
+                        delay_ms: 250,
+                    },
+                ],
+            }
+        }
+
+        #[test]
+        fn apng() {
+            let fixture = Fixture::blank("");
+            let path = fixture.path.join("1.png");
+            animation()
+                .save_as(&path, AnimationFormat::Png, 90, &PngOptions::default())
+                .unwrap();
+            assert_eq!(Animation::read(&path).unwrap(), animation());
+        }
+
+        #[test]
+        fn gif() {
+            let fixture = Fixture::blank("");
+            let path = fixture.path.join("1.gif");
+            animation()
+                .save_as(&path, AnimationFormat::Gif, 90, &PngOptions::default())
+                .unwrap();
+            assert_eq!(Animation::read(&path).unwrap(), animation());
+        }
+
+        #[test]
+        fn webp() {
+            let fixture = Fixture::blank("");
+            let path = fixture.path.join("1.webp");
+            animation()
+                .save_as(
+                    &path,
+                    AnimationFormat::WebpLossless,
+                    90,
+                    &PngOptions::default(),
+                )
+                .unwrap();
+
+            let data = std::fs::read(&path).unwrap();
+            let decoded = webp::AnimDecoder::new(&data).decode().unwrap();
+            assert_eq!(decoded.len(), 2);
+            let timestamps: Vec<_> = (0..decoded.len())
+                .map(|i| decoded.get_frame(i).unwrap().get_time_ms())
+                .collect();
+            assert_eq!(timestamps, vec![100, 350]);
+            assert_eq!(
+                decoded.get_frame(1).unwrap().get_image(),
+                animation().frames[1].image.data()
+            );
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::str::FromStr;
+
+use clap::ArgEnum;
+
+use crate::NftgenError;
+
+/// How the colors of a layer are combined with the layers below it
+/// See https://www.w3.org/TR/compositing-1/#blending
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ArgEnum)]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Add,
+    Darken,
+    Lighten,
+}
+
+impl BlendMode {
+    /// Blends `source` color over `backdrop` color, both in the range `0.0..=1.0`
+    pub fn apply(self, backdrop: f64, source: f64) -> f64 {
+        match self {
+            BlendMode::Normal => source,
+            BlendMode::Multiply => backdrop * source,
+            BlendMode::Screen => backdrop + source - backdrop * source,
+            BlendMode::Overlay => {
+                if backdrop <= 0.5 {
+                    BlendMode::Multiply.apply(source, 2.0 * backdrop)
+                } else {
+                    BlendMode::Screen.apply(source, 2.0 * backdrop - 1.0)
+                }
+            }
+            BlendMode::Add => (backdrop + source).min(1.0),
+            BlendMode::Darken => backdrop.min(source),
+            BlendMode::Lighten => backdrop.max(source),
+        }
+    }
+}
+
+impl FromStr for BlendMode {
+    type Err = NftgenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <BlendMode as ArgEnum>::from_str(s, true)
+            .map_err(|_| NftgenError::InvalidBlendMode(s.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_blend_mode() {
+        assert_eq!(
+            "multiply".parse::<BlendMode>().unwrap(),
+            BlendMode::Multiply
+        );
+        assert_eq!("Screen".parse::<BlendMode>().unwrap(), BlendMode::Screen);
+        assert!(matches!(
+            "burn".parse::<BlendMode>(),
+            Err(NftgenError::InvalidBlendMode(_))
+        ));
+    }
+
+    #[test]
+    fn apply() {
+        let cases = [
+            (BlendMode::Normal, 0.2),
+            (BlendMode::Multiply, 0.1),
+            (BlendMode::Screen, 0.6),
+            (BlendMode::Overlay, 0.2),
+            (BlendMode::Add, 0.7),
+            (BlendMode::Darken, 0.2),
+            (BlendMode::Lighten, 0.5),
+        ];
+        for (blend_mode, expected) in cases {
+            let blended = blend_mode.apply(0.5, 0.2);
+            assert!((blended - expected).abs() < 1e-9, "{:?}", blend_mode);
+        }
+        assert_eq!(BlendMode::Add.apply(0.8, 0.8), 1.0);
+        assert!((BlendMode::Overlay.apply(0.75, 0.5) - 0.75).abs() < 1e-9);
+    }
+}
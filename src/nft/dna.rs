@@ -0,0 +1,61 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Fingerprint of the traits that make up a single NFT
+/// Two NFTs with the same DNA have exactly the same traits
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Dna(String);
+
+impl Dna {
+    /// Hashes `(trait_type, value)` pairs into a DNA value
+    pub fn new<I, K, V>(traits: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut hasher = Sha256::new();
+        for (trait_type, value) in traits {
+            hasher.update(trait_type.as_ref().as_bytes());
+            hasher.update([0x1f]);
+            hasher.update(value.as_ref().as_bytes());
+            hasher.update([0x1e]);
+        }
+        Dna(format!("{:x}", hasher.finalize()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for Dna {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_traits_have_same_dna() {
+        let a = Dna::new([("background", "red"), ("face", "smile")]);
+        let b = Dna::new([("background", "red"), ("face", "smile")]);
+        assert_eq!(a, b);
+        assert_eq!(a.as_str().len(), 64);
+    }
+
+    #[test]
+    fn different_traits_have_different_dna() {
+        let a = Dna::new([("background", "red"), ("face", "smile")]);
+        let b = Dna::new([("background", "red"), ("face", "frown")]);
+        let c = Dna::new([("background", "redface"), ("", "smile")]);
+        assert_ne!(a, b);
+        assert_ne!(a, c);
+    }
+}
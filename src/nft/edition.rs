@@ -0,0 +1,174 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::NftgenError;
+
+/// Segment of consecutive token ids generated from its own layers, e.g. the genesis edition
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Edition {
+    pub name: String,
+    /// Number of NFTs in the edition, including one of ones that land in its ids
+    pub count: usize,
+    /// Root directory of the layers of the edition
+    pub layers_path: PathBuf,
+    /// Order of the layers from back to front, defaults to the order in the layers manifest
+    #[serde(default)]
+    pub layers_order: Vec<String>,
+    /// Name of the NFTs of the edition, `{id}` is replaced by the token id
+    /// Defaults to `<collection name> #<id>`
+    pub name_template: Option<String>,
+    /// JSON file of layer rules for the edition, see `Rules`
+    pub rules_path: Option<PathBuf>,
+}
+
+/// Editions file, e.g.
+/// [[editions]]
+/// name = "Genesis"
+/// count = 1000
+/// layers_path = "genesis"
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EditionsFile {
+    editions: Vec<Edition>,
+}
+
+impl Edition {
+    /// Name of the NFT `id` of this edition
+    pub fn token_name(&self, collection_name: &str, id: u32) -> String {
+        match &self.name_template {
+            Some(name_template) => name_template.replace("{id}", &id.to_string()),
+            None => format!("{} #{}", collection_name, id),
+        }
+    }
+}
+
+/// Reads the editions of a TOML or JSON (by extension) editions file, in the order of their
+/// token ids. Relative paths are relative to the directory of the file
+pub fn read_editions<P: AsRef<Path>>(path: P) -> Result<Vec<Edition>, NftgenError> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)?;
+    let invalid = |e: &dyn std::fmt::Display| {
+        NftgenError::InvalidEditions(format!("{}: {}", path.to_string_lossy(), e))
+    };
+    let file: EditionsFile = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => serde_json::from_str(&contents).map_err(|e| invalid(&e))?,
+        _ => toml::from_str(&contents).map_err(|e| invalid(&e))?,
+    };
+
+    if file.editions.is_empty() {
+        return Err(invalid(&"There are no editions"));
+    }
+    let mut names = BTreeSet::new();
+    for edition in &file.editions {
+        if !names.insert(&edition.name) {
+            return Err(invalid(&format!(
+                "Edition {} is listed twice",
+                edition.name
+            )));
+        }
+        if edition.count == 0 {
+            return Err(invalid(&format!("Edition {} has no NFTs", edition.name)));
+        }
+    }
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    Ok(file
+        .editions
+        .into_iter()
+        .map(|mut edition| {
+            edition.layers_path = dir.join(&edition.layers_path);
+            edition.rules_path = edition.rules_path.map(|rules_path| dir.join(rules_path));
+            edition
+        })
+        .collect())
+}
+
+/// Token ids of every edition, numbered consecutively from 0 in the order of `editions`
+pub fn edition_ids(editions: &[Edition]) -> Vec<std::ops::Range<u32>> {
+    let mut start = 0;
+    editions
+        .iter()
+        .map(|edition| {
+            let ids = start..start + edition.count as u32;
+            start = ids.end;
+            ids
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nft::tests::fixture::Fixture;
+
+    #[test]
+    fn read_editions_works() {
+        let fixture = Fixture::blank("minimal.png");
+        let path = fixture.path.join("editions.toml");
+        std::fs::write(
+            &path,
+            r#"
+                [[editions]]
+                name = "Genesis"
+                count = 1000
+                layers_path = "genesis"
+                name_template = "Genesis #{id}"
+
+                [[editions]]
+                name = "Expansion"
+                count = 9000
+                layers_path = "expansion"
+                layers_order = ["Background", "Body", "Hat"]
+                rules_path = "expansion.json"
+            "#,
+        )
+        .unwrap();
+
+        let editions = read_editions(&path).unwrap();
+        assert_eq!(editions.len(), 2);
+        assert_eq!(editions[0].layers_path, fixture.path.join("genesis"));
+        assert_eq!(
+            editions[1].rules_path,
+            Some(fixture.path.join("expansion.json"))
+        );
+        assert_eq!(editions[0].token_name("Great", 7), "Genesis #7");
+        assert_eq!(editions[1].token_name("Great", 1007), "Great #1007");
+        assert_eq!(edition_ids(&editions), vec![0..1000, 1000..10000]);
+    }
+
+    #[test]
+    fn read_editions_rejects_invalid_editions() {
+        let fixture = Fixture::blank("minimal.png");
+        let path = fixture.path.join("editions.json");
+        let edition = r#"{"name": "Genesis", "count": 10, "layers_path": "genesis"}"#;
+
+        std::fs::write(&path, r#"{"editions": []}"#).unwrap();
+        assert!(matches!(
+            read_editions(&path),
+            Err(NftgenError::InvalidEditions(_))
+        ));
+
+        std::fs::write(
+            &path,
+            format!(r#"{{"editions": [{}, {}]}}"#, edition, edition),
+        )
+        .unwrap();
+        assert!(matches!(
+            read_editions(&path),
+            Err(NftgenError::InvalidEditions(message)) if message.contains("twice")
+        ));
+
+        std::fs::write(
+            &path,
+            r#"{"editions": [{"name": "Genesis", "count": 10, "layers": "genesis"}]}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            read_editions(&path),
+            Err(NftgenError::InvalidEditions(_))
+        ));
+    }
+}
@@ -0,0 +1,50 @@
+use clap::ArgEnum;
+
+/// File format of generated NFT images
+/// `webp` and `jpeg` are lossy and much smaller than `png` for painted art.
+/// `jpeg` has no transparency, `gif` only has fully transparent or opaque pixels and 256 colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ArgEnum)]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    Webp,
+    WebpLossless,
+    Jpeg,
+    Gif,
+}
+
+impl ImageFormat {
+    /// File extension of images in this format, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Webp | ImageFormat::WebpLossless => "webp",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Gif => "gif",
+        }
+    }
+}
+
+/// File format of animated NFTs
+/// `png` is an animated PNG (APNG), lossless with full transparency. `gif` has 256 colors
+/// per frame and only fully transparent or opaque pixels. `webp` is lossy and much smaller,
+/// `webp-lossless` is lossless, both with full transparency
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ArgEnum)]
+pub enum AnimationFormat {
+    #[default]
+    Png,
+    Gif,
+    Webp,
+    WebpLossless,
+}
+
+impl AnimationFormat {
+    /// File extension of animations in this format, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            AnimationFormat::Png => "png",
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Webp | AnimationFormat::WebpLossless => "webp",
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use crate::NftgenError;
+use crate::nft::png_encoder::encode_png;
+use crate::nft::resize::resample;
+use crate::{BlendMode, ImageFormat, NftgenError, PngOptions, ResizeFilter};
 use std::fs::File;
 use std::io::BufWriter;
 use std::path::Path;
@@ -21,45 +23,290 @@ impl Image {
         }
     }
 
+    /// Size of the pixel data in bytes
+    pub fn size_in_bytes(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns a copy of this image resampled to `width` x `height` pixels
+    pub fn resize(&self, width: u32, height: u32, filter: ResizeFilter) -> Image {
+        let data = resample(
+            &self.data,
+            self.bytes_per_pixel,
+            (self.width, self.height),
+            (width, height),
+            filter,
+        );
+        Image::new(data, self.bytes_per_pixel, width, height)
+    }
+
+    /// Resizes to `width` pixels wide, keeping the aspect ratio
+    pub fn resize_to_width(&self, width: u32, filter: ResizeFilter) -> Image {
+        let height =
+            (self.height as u64 * width as u64 + self.width as u64 / 2) / self.width as u64;
+        self.resize(width, (height as u32).max(1), filter)
+    }
+
+    /// Raw pixel data, row by row
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Makes every pixel black and fully transparent
+    pub fn clear(&mut self) {
+        self.data.fill(0);
+    }
+
+    /// Like `clear`, for the `width` x `height` pixels with their top left corner at `offset`
+    pub fn clear_region(&mut self, (x, y): (i64, i64), width: u32, height: u32) {
+        let left = x.clamp(0, self.width as i64) as usize;
+        let top = y.clamp(0, self.height as i64) as usize;
+        let right = (x + width as i64).clamp(0, self.width as i64) as usize;
+        let bottom = (y + height as i64).clamp(0, self.height as i64) as usize;
+        let row_len = self.width as usize * self.bytes_per_pixel;
+        for row in top..bottom {
+            let start = row * row_len;
+            self.data[start + left * self.bytes_per_pixel..start + right * self.bytes_per_pixel]
+                .fill(0);
+        }
+    }
+
+    /// Reads a PNG of any color type and bit depth as RGBA with 8 bits per channel
+    /// Palettes and tRNS transparency are expanded and 16 bit channels are reduced to 8 bits
     pub fn read<P: AsRef<Path>>(image_path: P) -> Result<Self, NftgenError> {
-        let decoder = png::Decoder::new(File::open(image_path)?);
+        let mut decoder = png::Decoder::new(File::open(image_path)?);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
         let mut reader = decoder.read_info()?;
         let mut buf = vec![0; reader.output_buffer_size()];
-        reader.next_frame(&mut buf)?;
-        let info = reader.info();
-        let bytes_per_pixel = info.bytes_per_pixel();
-        let (width, height) = info.size();
+        let frame = reader.next_frame(&mut buf)?;
+        Ok(Image::from_png_frame(buf, &frame))
+    }
+
+    /// Converts a PNG frame decoded with `Transformations::normalize_to_color8` to RGBA
+    pub(crate) fn from_png_frame(mut buf: Vec<u8>, frame: &png::OutputInfo) -> Self {
+        buf.truncate(frame.buffer_size());
+
+        let data = match frame.color_type {
+            png::ColorType::Rgba => buf,
+            png::ColorType::Rgb => buf
+                .chunks_exact(3)
+                .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], u8::MAX])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+                .collect(),
+            png::ColorType::Grayscale => buf.iter().flat_map(|&g| [g, g, g, u8::MAX]).collect(),
+            png::ColorType::Indexed => unreachable!("Palettes should be expanded by the decoder"),
+        };
+
+        Image::new(data, 4, frame.width, frame.height)
+    }
+
+    /// Composites `images` from back to front over this image with Porter-Duff source-over
+    /// Pixels are straight (non-premultiplied) alpha like in PNG files. They are blended
+    /// as premultiplied colors and converted back, so transparent pixels do not darken edges.
+    /// Images without an alpha channel are opaque and cover everything below them
+    pub fn stack<I: AsRef<Image>>(&mut self, images: &[I]) {
+        for image in images.iter() {
+            self.blend(image.as_ref(), BlendMode::Normal, 1.0);
+        }
+    }
 
-        Ok(Image::new(buf, bytes_per_pixel, width, height))
+    /// Composites `image` over this image, mixing colors with `blend_mode` and scaling the
+    /// alpha of `image` by `opacity`
+    pub fn blend(&mut self, image: &Image, blend_mode: BlendMode, opacity: f64) {
+        self.blend_at(image, (0, 0), blend_mode, opacity);
     }
 
-    pub fn stack(&mut self, images: &[Image]) {
-        for (pixel_index, bottom_pixel) in self.data.chunks_mut(self.bytes_per_pixel).enumerate() {
-            for image in images.iter().rev() {
-                let top_pixel = &image.data[pixel_index * self.bytes_per_pixel
-                    ..pixel_index * self.bytes_per_pixel + self.bytes_per_pixel];
+    /// Like `blend`, with the top left corner of `image` placed at `offset`
+    /// The parts of `image` outside of this image are cut off
+    pub fn blend_at(
+        &mut self,
+        image: &Image,
+        (x, y): (i64, i64),
+        blend_mode: BlendMode,
+        opacity: f64,
+    ) {
+        let bytes_per_pixel = self.bytes_per_pixel;
+        let left = x.max(0);
+        let top = y.max(0);
+        let right = (x + image.width as i64).min(self.width as i64);
+        let bottom = (y + image.height as i64).min(self.height as i64);
+        if left >= right || top >= bottom {
+            return;
+        }
+
+        let row_len = (right - left) as usize * bytes_per_pixel;
+        for row in top..bottom {
+            let start = (row as usize * self.width as usize + left as usize) * bytes_per_pixel;
+            let image_start =
+                ((row - y) as usize * image.width as usize + (left - x) as usize) * bytes_per_pixel;
+            blend_pixels(
+                &mut self.data[start..start + row_len],
+                &image.data[image_start..image_start + row_len],
+                bytes_per_pixel,
+                blend_mode,
+                opacity,
+            );
+        }
+    }
 
-                if top_pixel.iter().any(|&p| p == 1) {
-                    for (i, _) in top_pixel.iter().enumerate() {
-                        bottom_pixel[i] = top_pixel[i];
-                    }
-                    break;
+    pub fn save<P: AsRef<Path>>(&self, output_path: P) -> Result<(), NftgenError> {
+        self.save_png(output_path, &PngOptions::default())
+    }
+
+    /// Saves the image as a PNG encoded with `options`
+    pub fn save_png<P: AsRef<Path>>(
+        &self,
+        output_path: P,
+        options: &PngOptions,
+    ) -> Result<(), NftgenError> {
+        let png = encode_png(&[self], &[], options)?;
+        Ok(std::fs::write(output_path, png)?)
+    }
+
+    /// Saves the image in `format`
+    /// `quality` from 0 to 100 applies to the lossy formats `webp` and `jpeg`,
+    /// `png_options` to `png`
+    pub fn save_as<P: AsRef<Path>>(
+        &self,
+        output_path: P,
+        format: ImageFormat,
+        quality: u8,
+        png_options: &PngOptions,
+    ) -> Result<(), NftgenError> {
+        let quality = quality.min(100);
+        match format {
+            ImageFormat::Png => self.save_png(output_path, png_options),
+            ImageFormat::Webp | ImageFormat::WebpLossless => {
+                let encoder = webp::Encoder::from_rgba(&self.data, self.width, self.height);
+                let webp = encoder
+                    .encode_simple(format == ImageFormat::WebpLossless, quality as f32)
+                    .map_err(|e| NftgenError::WebpEncode(format!("{:?}", e)))?;
+                Ok(std::fs::write(output_path, &*webp)?)
+            }
+            ImageFormat::Jpeg => {
+                let (width, height) = self.size_u16("JPEG")?;
+                // JPEG has no transparency, so transparent parts become white
+                let mut flattened = Image::new(
+                    vec![u8::MAX; self.data.len()],
+                    self.bytes_per_pixel,
+                    self.width,
+                    self.height,
+                );
+                flattened.stack(&[self]);
+                let encoder = jpeg_encoder::Encoder::new_file(output_path, quality)?;
+                Ok(encoder.encode(
+                    &flattened.data,
+                    width,
+                    height,
+                    jpeg_encoder::ColorType::Rgba,
+                )?)
+            }
+            ImageFormat::Gif => {
+                let (width, height) = self.size_u16("GIF")?;
+                let w = BufWriter::new(File::create(output_path)?);
+                let mut encoder = gif::Encoder::new(w, width, height, &[])?;
+                let mut pixels = self.data.clone();
+                let frame = gif::Frame::from_rgba_speed(width, height, &mut pixels, 10);
+                Ok(encoder.write_frame(&frame)?)
+            }
+        }
+    }
+
+    pub(crate) fn size_u16(&self, format: &str) -> Result<(u16, u16), NftgenError> {
+        match (u16::try_from(self.width), u16::try_from(self.height)) {
+            (Ok(width), Ok(height)) => Ok((width, height)),
+            _ => Err(NftgenError::ImageTooLarge {
+                format: format.to_string(),
+                width: self.width,
+                height: self.height,
+            }),
+        }
+    }
+}
+
+/// Blends a row of `top` pixels over the `bottom` pixels in place
+fn blend_pixels(
+    bottom: &mut [u8],
+    top: &[u8],
+    bytes_per_pixel: usize,
+    blend_mode: BlendMode,
+    opacity: f64,
+) {
+    let opacity = opacity.clamp(0.0, 1.0);
+    let pixels = bottom
+        .chunks_exact_mut(bytes_per_pixel)
+        .zip(top.chunks_exact(bytes_per_pixel));
+
+    if blend_mode == BlendMode::Normal && opacity == 1.0 {
+        match bytes_per_pixel {
+            // Grayscale with alpha or RGBA
+            2 | 4 => pixels.for_each(|(bottom, top)| source_over(bottom, top)),
+            _ => bottom.copy_from_slice(top),
+        }
+        return;
+    }
+
+    let mut blended = [0; 4];
+    for (bottom, top) in pixels {
+        match bytes_per_pixel {
+            2 | 4 => {
+                let alpha = bytes_per_pixel - 1;
+                let blended = &mut blended[..bytes_per_pixel];
+                blended.copy_from_slice(top);
+                blended[alpha] = (top[alpha] as f64 * opacity).round() as u8;
+                // Where there is a backdrop, the source color becomes the blended color
+                let backdrop_alpha = bottom[alpha] as f64 / 255.0;
+                for i in 0..alpha {
+                    let mixed = blend_mode.apply(bottom[i] as f64 / 255.0, top[i] as f64 / 255.0);
+                    let source =
+                        (1.0 - backdrop_alpha) * top[i] as f64 / 255.0 + backdrop_alpha * mixed;
+                    blended[i] = (source * 255.0).round() as u8;
+                }
+                source_over(bottom, blended);
+            }
+            _ => {
+                for (b, t) in bottom.iter_mut().zip(top) {
+                    let mixed = blend_mode.apply(*b as f64 / 255.0, *t as f64 / 255.0);
+                    let color = *b as f64 / 255.0 * (1.0 - opacity) + mixed * opacity;
+                    *b = (color * 255.0).round() as u8;
                 }
             }
         }
     }
+}
 
-    pub fn save<P: AsRef<Path>>(&self, output_path: P) -> Result<(), NftgenError> {
-        let file = File::create(output_path)?;
-        let w = &mut BufWriter::new(file);
+/// Blends the straight alpha `top` pixel over the `bottom` pixel in place
+/// The alpha channel is the last byte of each pixel
+fn source_over(bottom: &mut [u8], top: &[u8]) {
+    let alpha = top.len() - 1;
+    let top_alpha = top[alpha] as u32;
+    match top_alpha {
+        0 => return,
+        255 => return bottom.copy_from_slice(top),
+        _ => (),
+    }
 
-        let mut encoder = png::Encoder::new(w, self.width, self.height);
-        encoder.set_color(png::ColorType::Rgba);
-        encoder.set_depth(png::BitDepth::Eight);
-        let mut writer = encoder.write_header()?;
+    // Output alpha and premultiplied colors are scaled by 255 * 255 to stay in integers
+    let bottom_alpha = bottom[alpha] as u32 * (255 - top_alpha);
+    let out_alpha = top_alpha * 255 + bottom_alpha;
+    for i in 0..alpha {
+        let premultiplied = top[i] as u32 * top_alpha * 255 + bottom[i] as u32 * bottom_alpha;
+        bottom[i] = rounded_div(premultiplied, out_alpha) as u8;
+    }
+    bottom[alpha] = rounded_div(out_alpha, 255) as u8;
+}
 
-        writer.write_image_data(self.data.as_slice())?;
-        Ok(())
+/// Divides rounding half up
+fn rounded_div(numerator: u32, denominator: u32) -> u32 {
+    (2 * numerator + denominator) / (2 * denominator)
+}
+
+impl AsRef<Image> for Image {
+    fn as_ref(&self) -> &Image {
+        self
     }
 }
 
@@ -71,9 +318,13 @@ mod tests {
     mod stack {
         use super::*;
 
+        fn rgba(pixels: &[[u8; 4]]) -> Image {
+            Image::new(pixels.concat(), 4, pixels.len() as u32, 1)
+        }
+
         #[test]
         fn transparent_layers() {
-            let image = Image::new(vec![0, 0, 0, 0], 4, 1, 1);
+            let image = rgba(&[[0, 0, 0, 0]]);
             let mut bkg_image = image.clone();
             let eyes_image = image.clone();
 
@@ -82,31 +333,118 @@ mod tests {
         }
 
         #[test]
-        fn layers_with_content() {
-            let mut bkg_image = Image::new(vec![1, 0, 0, 1], 4, 1, 1);
-            let eyes_image = Image::new(vec![0, 1, 1, 0], 4, 1, 1);
+        fn opaque_layers_cover_layers_below() {
+            let mut bkg_image = rgba(&[[200, 100, 50, 255], [10, 20, 30, 255]]);
+            let eyes_image = rgba(&[[0, 0, 0, 0], [40, 50, 60, 255]]);
 
             bkg_image.stack(&[eyes_image]);
-            assert_eq!(bkg_image.data, vec![0, 1, 1, 0]);
+            assert_eq!(bkg_image, rgba(&[[200, 100, 50, 255], [40, 50, 60, 255]]));
         }
 
         #[test]
-        fn layers_with_two_pixels() {
-            let mut bkg_image = Image::new(vec![1, 0, 0, 1, 1, 1, 1, 1], 4, 1, 1);
-            let eyes_image = Image::new(vec![0, 0, 0, 0, 1, 0, 0, 0], 4, 1, 1);
+        fn semi_transparent_over_opaque() {
+            let mut bkg_image = rgba(&[[0, 0, 255, 255]]);
+            let shadow_image = rgba(&[[255, 0, 0, 128]]);
 
-            bkg_image.stack(&[eyes_image]);
-            assert_eq!(bkg_image.data, vec![1, 0, 0, 1, 1, 0, 0, 0]);
+            bkg_image.stack(&[shadow_image]);
+            assert_eq!(bkg_image, rgba(&[[128, 0, 127, 255]]));
+        }
+
+        #[test]
+        fn semi_transparent_over_transparent_keeps_color() {
+            // Blending straight colors would darken the edge towards the black transparent pixel
+            let mut bkg_image = rgba(&[[0, 0, 0, 0]]);
+            let edge_image = rgba(&[[255, 0, 0, 128]]);
+
+            bkg_image.stack(&[edge_image]);
+            assert_eq!(bkg_image, rgba(&[[255, 0, 0, 128]]));
+        }
+
+        #[test]
+        fn semi_transparent_over_semi_transparent() {
+            let mut bkg_image = rgba(&[[0, 0, 255, 128]]);
+            let glow_image = rgba(&[[255, 0, 0, 128]]);
+
+            bkg_image.stack(&[glow_image]);
+            assert_eq!(bkg_image, rgba(&[[170, 0, 85, 192]]));
         }
 
         #[test]
         fn three_layers() {
-            let mut bkg_image = Image::new(vec![0, 0, 1], 1, 1, 1);
-            let eyes_image = Image::new(vec![1, 0, 0], 1, 1, 1);
-            let mouth_image = Image::new(vec![0, 1, 0], 1, 1, 1);
+            let mut bkg_image = Image::new(vec![0, 0, 1], 1, 3, 1);
+            let eyes_image = Image::new(vec![1, 0, 0], 1, 3, 1);
+            let mouth_image = Image::new(vec![0, 1, 0], 1, 3, 1);
 
+            // Without an alpha channel the top layer is opaque
             bkg_image.stack(&[eyes_image, mouth_image]);
-            assert_eq!(bkg_image.data, vec![1, 1, 1]);
+            assert_eq!(bkg_image.data, vec![0, 1, 0]);
+        }
+
+        #[test]
+        fn blend_modes() {
+            let bkg_image = rgba(&[[255, 128, 0, 255], [64, 64, 64, 0]]);
+            let light_image = rgba(&[[128, 128, 128, 255], [128, 128, 128, 255]]);
+            let cases = [
+                (BlendMode::Multiply, [128, 64, 0, 255]),
+                (BlendMode::Screen, [255, 192, 128, 255]),
+                (BlendMode::Overlay, [255, 128, 0, 255]),
+                (BlendMode::Add, [255, 255, 128, 255]),
+                (BlendMode::Darken, [128, 128, 0, 255]),
+                (BlendMode::Lighten, [255, 128, 128, 255]),
+            ];
+            for (blend_mode, expected) in cases {
+                let mut image = bkg_image.clone();
+                image.blend(&light_image, blend_mode, 1.0);
+                // Without a backdrop the layer keeps its own color
+                assert_eq!(
+                    image,
+                    rgba(&[expected, [128, 128, 128, 255]]),
+                    "{:?}",
+                    blend_mode
+                );
+            }
+        }
+
+        #[test]
+        fn opacity() {
+            let mut bkg_image = rgba(&[[0, 0, 255, 255]]);
+            let shadow_image = rgba(&[[255, 0, 0, 255]]);
+
+            bkg_image.blend(&shadow_image, BlendMode::Normal, 0.5);
+            assert_eq!(bkg_image, rgba(&[[128, 0, 127, 255]]));
+
+            let mut bkg_image = rgba(&[[0, 0, 255, 255]]);
+            bkg_image.blend(&shadow_image, BlendMode::Multiply, 0.0);
+            assert_eq!(bkg_image, rgba(&[[0, 0, 255, 255]]));
+        }
+
+        #[test]
+        fn blend_at_offset() {
+            let mut image = Image::new(vec![0; 3 * 2 * 4], 4, 3, 2);
+            let sprite = rgba(&[[1, 1, 1, 255], [2, 2, 2, 255]]);
+
+            image.blend_at(&sprite, (1, 1), BlendMode::Normal, 1.0);
+            let mut expected = [[0; 4]; 6];
+            expected[4] = [1, 1, 1, 255];
+            expected[5] = [2, 2, 2, 255];
+            assert_eq!(image.data, expected.concat());
+
+            // Pixels outside of the image are cut off
+            image.blend_at(&sprite, (-1, 0), BlendMode::Normal, 1.0);
+            expected[0] = [2, 2, 2, 255];
+            assert_eq!(image.data, expected.concat());
+            image.blend_at(&sprite, (3, 0), BlendMode::Normal, 1.0);
+            image.blend_at(&sprite, (0, -1), BlendMode::Normal, 1.0);
+            assert_eq!(image.data, expected.concat());
+        }
+
+        #[test]
+        fn matches_reference_composite() {
+            let read = |name: &str| Image::read(Fixture::source_path(name)).unwrap();
+            let mut image = read("composite_bottom.png");
+
+            image.stack(&[read("composite_top.png"), read("composite_shadow.png")]);
+            assert_eq!(image, read("composite_expected.png"));
         }
     }
 
@@ -125,6 +463,101 @@ mod tests {
             assert_eq!(bkg_image.height, 1);
         }
 
+        fn read_fixture(fixture_filename: &str) -> Image {
+            Image::read(Fixture::source_path(fixture_filename)).unwrap()
+        }
+
+        #[test]
+        fn normalizes_to_rgba8() {
+            let cases = [
+                (
+                    "rgb8.png",
+                    [
+                        [255, 0, 0, 255],
+                        [0, 255, 0, 255],
+                        [0, 0, 255, 255],
+                        [255, 255, 255, 255],
+                    ],
+                ),
+                (
+                    "rgb8_trns.png",
+                    [
+                        [255, 0, 0, 255],
+                        [0, 255, 0, 255],
+                        [0, 0, 255, 0],
+                        [255, 255, 255, 255],
+                    ],
+                ),
+                (
+                    "gray8.png",
+                    [
+                        [0, 0, 0, 255],
+                        [85, 85, 85, 255],
+                        [170, 170, 170, 255],
+                        [255, 255, 255, 255],
+                    ],
+                ),
+                (
+                    "gray_alpha8.png",
+                    [
+                        [0, 0, 0, 255],
+                        [85, 85, 85, 128],
+                        [170, 170, 170, 0],
+                        [255, 255, 255, 255],
+                    ],
+                ),
+                (
+                    "gray1.png",
+                    [
+                        [0, 0, 0, 255],
+                        [255, 255, 255, 255],
+                        [0, 0, 0, 255],
+                        [255, 255, 255, 255],
+                    ],
+                ),
+                (
+                    "rgba16.png",
+                    [
+                        [255, 0, 0, 255],
+                        [0, 255, 0, 128],
+                        [0, 0, 255, 0],
+                        [255, 255, 255, 255],
+                    ],
+                ),
+                (
+                    "palette.png",
+                    [
+                        [255, 0, 0, 255],
+                        [0, 255, 0, 128],
+                        [0, 0, 255, 0],
+                        [255, 255, 255, 255],
+                    ],
+                ),
+            ];
+            for (fixture_filename, pixels) in cases {
+                let image = read_fixture(fixture_filename);
+                assert_eq!(image.bytes_per_pixel, 4, "{}", fixture_filename);
+                assert_eq!((image.width, image.height), (4, 1), "{}", fixture_filename);
+                assert_eq!(image.data, pixels.concat(), "{}", fixture_filename);
+            }
+        }
+
+        #[test]
+        fn stacks_layers_of_different_color_types() {
+            let mut image = read_fixture("rgb8.png");
+            image.stack(&[read_fixture("palette.png")]);
+            assert_eq!(
+                image.data,
+                [
+                    [255, 0, 0, 255],
+                    [0, 255, 0, 255],
+                    [0, 0, 255, 255],
+                    [255, 255, 255, 255]
+                ]
+                .concat()
+            );
+        }
+
         #[test]
         fn empty_image() {
             let fixture = Fixture::create_layers_dirs("empty.png", &["background"]);
@@ -144,4 +577,45 @@ mod tests {
 
         bkg_image.save(fixture.path.join("output.png")).unwrap();
     }
+
+    #[test]
+    fn save_as() {
+        let fixture = Fixture::blank("composite_expected.png");
+        let image = Image::read(Fixture::source_path("composite_expected.png")).unwrap();
+        let signatures: [(ImageFormat, &[u8]); 5] = [
+            (ImageFormat::Png, b"\x89PNG"),
+            (ImageFormat::Webp, b"RIFF"),
+            (ImageFormat::WebpLossless, b"RIFF"),
+            (ImageFormat::Jpeg, b"\xff\xd8\xff"),
+            (ImageFormat::Gif, b"GIF89a"),
+        ];
+
+        for (format, signature) in signatures {
+            let path = fixture
+                .path
+                .join(format!("{:?}.{}", format, format.extension()));
+            image
+                .save_as(&path, format, 80, &PngOptions::default())
+                .unwrap();
+            let saved = std::fs::read(&path).unwrap();
+            assert!(saved.starts_with(signature), "{:?}", format);
+        }
+
+        // Lossless WebP keeps every visible pixel
+        let opaque = Image::read(Fixture::source_path("rgb8.png")).unwrap();
+        let path = fixture.path.join("opaque.webp");
+        opaque
+            .save_as(&path, ImageFormat::WebpLossless, 0, &PngOptions::default())
+            .unwrap();
+        let lossless = std::fs::read(path).unwrap();
+        let decoded = webp::Decoder::new(&lossless).decode().unwrap();
+        // Decoded as RGB, since the image has no transparent pixels
+        let rgb: Vec<u8> = opaque
+            .data
+            .chunks_exact(4)
+            .flat_map(|rgba| &rgba[..3])
+            .copied()
+            .collect();
+        assert_eq!(&*decoded, rgb.as_slice());
+    }
 }
@@ -1,4 +1,11 @@
-use crate::{Image, Layer, LayerGroup, NftgenError};
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::nft::link::allows_variant;
+use crate::{
+    Animation, Frame, Image, Layer, LayerGroup, NftgenError, PickedLayer, Rules, NONE_LAYER_NAME,
+};
 
 pub struct ImageBuilder<'a> {
     pub image: Image,
@@ -17,31 +24,151 @@ impl<'a> ImageBuilder<'a> {
         self.layers.push(layer);
     }
 
-    pub fn build(layer_groups: &'a [LayerGroup]) -> Result<(Image, Vec<&'a Layer>), NftgenError> {
-        let base = layer_groups.get(0).unwrap().pick().get_image()?;
+    /// Picks one layer from every layer group such that the picked layers satisfy `rules`
+    /// Optional groups may be skipped, which is recorded as a `none` pick
+    /// Linked groups only pick layers of the variant picked first for their link
+    /// Returns none if the layers picked so far leave no valid layer in a later group
+    pub fn pick<R: Rng + ?Sized>(
+        layer_groups: &'a [LayerGroup],
+        rules: &Rules,
+        rng: &mut R,
+    ) -> Option<Vec<PickedLayer<'a>>> {
+        let mut picked: Vec<(&str, &str)> = Vec::with_capacity(layer_groups.len());
+        let mut picks = Vec::with_capacity(layer_groups.len());
+        let mut variants: Vec<(&str, &str)> = vec![];
+
+        for layer_group in layer_groups.iter() {
+            let layer_type = layer_group.layer_type.as_str();
+            let pick =
+                if layer_group.skip(rng) && rules.allows(&picked, (layer_type, NONE_LAYER_NAME)) {
+                    layer_group.picked(None)
+                } else {
+                    let layer = layer_group.pick_where(rng, |layer| {
+                        rules.allows(&picked, (layer_type, layer.name().unwrap_or_default()))
+                            && allows_variant(&variants, layer)
+                    });
+                    match layer {
+                        Some(layer) => layer_group.picked(Some(layer)),
+                        None => {
+                            log::debug!("No layer in {} satisfies the layer rules", layer_type);
+                            return None;
+                        }
+                    }
+                };
+            log::debug!("Picked layer: {}", pick.name());
+            if let Some((link, variant)) = pick.linked_variant() {
+                if !variants.iter().any(|&(other, _)| other == link) {
+                    variants.push((link, variant));
+                }
+            }
+            picked.push((layer_type, pick.name()));
+            picks.push(pick);
+        }
+        Some(picks)
+    }
+
+    /// Blends already picked layers from back to front into a single image
+    /// Animated layers show their first frame
+    pub fn render(picks: &[PickedLayer<'a>]) -> Result<Image, NftgenError> {
+        let layers = ImageBuilder::load_layers(picks)?;
+        ImageBuilder::render_at(&layers, 0)
+    }
+
+    /// Blends already picked layers into an animation. Still layers are repeated in every
+    /// frame while animated layers loop, see `Animation::timeline`
+    /// Picks without animated layers are rendered into a single frame
+    pub fn render_animation(picks: &[PickedLayer<'a>]) -> Result<Animation, NftgenError> {
+        let layers = ImageBuilder::load_layers(picks)?;
+        let animations: Vec<&Animation> =
+            layers.iter().map(|(_, animation)| &**animation).collect();
+        if !animations.iter().any(|animation| animation.is_animated()) {
+            return Ok(Animation::still(ImageBuilder::render_at(&layers, 0)?));
+        }
+
+        let frames = Animation::timeline(&animations)
+            .into_iter()
+            .map(|(start_ms, delay_ms)| {
+                Ok(Frame {
+                    image: ImageBuilder::render_at(&layers, start_ms)?,
+                    delay_ms,
+                })
+            })
+            .collect::<Result<_, NftgenError>>()?;
+        Ok(Animation { frames })
+    }
+
+    /// Loads the images of the picked layers from back to front
+    /// Parts of layers are drawn right behind the layer type they name, see `LayerPart`
+    fn load_layers(
+        picks: &[PickedLayer<'a>],
+    ) -> Result<Vec<(&'a Layer, Arc<Animation>)>, NftgenError> {
+        let mut layers = vec![];
+        for pick in picks {
+            for layer in picks.iter().filter_map(|pick| pick.layer) {
+                for part in layer.parts.iter().filter(|p| p.behind == pick.layer_type) {
+                    layers.push((layer, layer.get_part_animation(part)?));
+                }
+            }
+            if let Some(layer) = pick.layer {
+                layers.push((layer, layer.get_animation()?));
+            }
+        }
+        Ok(layers)
+    }
+
+    /// Blends the frames that `layers` show at `time_ms`
+    fn render_at(
+        layers: &[(&'a Layer, Arc<Animation>)],
+        time_ms: u32,
+    ) -> Result<Image, NftgenError> {
+        let ((base_layer, base_animation), layers) =
+            layers.split_first().ok_or(NftgenError::NothingToRender)?;
+        let base_image = base_animation.frame_at(time_ms);
+        let mut base = base_image.clone();
+        // Blend modes have no effect without layers below, only opacity and position do
+        if base_layer.opacity < 1.0 || base_layer.position.is_some() {
+            base.clear();
+            ImageBuilder::blend_layer(&mut base, base_layer, base_image)?;
+        }
         log::debug!(
             "Building image with width: {}, height: {}",
             base.width,
             base.height
         );
         let mut builder = ImageBuilder::new(base);
+        builder.add(base_layer);
 
-        for layer_group in layer_groups.iter() {
-            let layer = layer_group.pick();
+        for (layer, animation) in layers {
             log::debug!("Adding layer: {}", layer.name().unwrap_or_default());
             builder.add(layer);
+            ImageBuilder::blend_layer(&mut builder.image, layer, animation.frame_at(time_ms))?;
             log::debug!("Added layer: {}", layer.name().unwrap_or_default());
         }
-        builder.image.stack(
-            builder
-                .layers
-                .iter()
-                .map(|layer| layer.get_image())
-                .collect::<Result<Vec<_>, _>>()?
-                .as_slice(),
-        );
 
-        Ok((builder.image, builder.layers))
+        Ok(builder.image)
+    }
+
+    /// Blends `layer_image`, the current frame of `layer`, over `image` at the position of the layer
+    fn blend_layer(
+        image: &mut Image,
+        layer: &Layer,
+        layer_image: &Image,
+    ) -> Result<(), NftgenError> {
+        let canvas = (image.width, image.height);
+        let size = (layer_image.width, layer_image.height);
+        let offset = match layer.position {
+            Some(position) => position.offset(canvas, size),
+            None if size == canvas => (0, 0),
+            None => {
+                return Err(NftgenError::DimensionMismatch {
+                    path: layer.image_path.clone(),
+                    expected: canvas,
+                    found: size,
+                })
+            }
+        };
+        image.blend_at(layer_image, offset, layer.blend_mode, layer.opacity);
+        Ok(())
     }
 }
 
@@ -52,7 +179,7 @@ mod tests {
 
     #[test]
     fn add() {
-        let layers = vec![
+        let layers = [
             Layer::new("bkg.png", 1),
             Layer::new("face.png", 1),
             Layer::new("eyes.png", 1),
@@ -69,7 +196,17 @@ mod tests {
 
     mod build {
         use super::*;
-        use crate::get_layer_groups;
+        use crate::{get_layer_groups, token_rng, Anchor, BlendMode, Position, TokenRng};
+        use std::path::Path;
+
+        /// Picks the layers of an NFT without layer rules and renders them
+        fn pick_and_render<'a>(
+            layer_groups: &'a [LayerGroup],
+            rng: &mut TokenRng,
+        ) -> (Image, Vec<PickedLayer<'a>>) {
+            let picks = ImageBuilder::pick(layer_groups, &Rules::default(), rng).unwrap();
+            (ImageBuilder::render(&picks).unwrap(), picks)
+        }
 
         #[test]
         fn build() {
@@ -78,10 +215,227 @@ mod tests {
 
             let layer_groups = get_layer_groups(&fixture.path, layer_dirs).unwrap();
 
-            let (image, layers) = ImageBuilder::build(&layer_groups).unwrap();
+            let (image, layers) = pick_and_render(&layer_groups, &mut token_rng(0, 0));
             let expected = Image::read(fixture.path.join("layer1/image1#1.png")).unwrap();
             assert_eq!(expected, image);
             assert_eq!(layers.len(), 2);
         }
+
+        #[test]
+        fn build_is_reproducible_from_seed() {
+            let layer_dirs = &["layer1", "layer2"];
+            let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+
+            let layer_groups = get_layer_groups(&fixture.path, layer_dirs).unwrap();
+
+            for token_id in 0..10 {
+                let (_, first) = pick_and_render(&layer_groups, &mut token_rng(99, token_id));
+                let (_, second) = pick_and_render(&layer_groups, &mut token_rng(99, token_id));
+                assert_eq!(first, second);
+            }
+        }
+
+        #[test]
+        fn build_skips_optional_groups() {
+            let layer_dirs = &["layer1", "layer2"];
+            let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+
+            let mut layer_groups = get_layer_groups(&fixture.path, layer_dirs).unwrap();
+            layer_groups.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let layer2 = layer_groups.pop().unwrap().with_skip_probability(1.0);
+            layer_groups.push(layer2);
+
+            let (_, picks) = pick_and_render(&layer_groups, &mut token_rng(0, 0));
+            assert!(picks[0].layer.is_some());
+            assert_eq!(picks[1], PickedLayer::new("layer2", None));
+        }
+
+        /// Layer groups with a single layer each, copied from a fixture file with its extension
+        fn layer_groups_of(layers: &[(&str, &str)]) -> (Fixture, Vec<LayerGroup>) {
+            let fixture = Fixture::blank("minimal.png");
+            for (layer_dir, source) in layers {
+                std::fs::create_dir(fixture.path.join(layer_dir)).unwrap();
+                std::fs::copy(
+                    Fixture::source_path(source),
+                    fixture
+                        .path
+                        .join(layer_dir)
+                        .join("image#1")
+                        .with_extension(Path::new(source).extension().unwrap()),
+                )
+                .unwrap();
+            }
+            let layer_dirs: Vec<_> = layers.iter().map(|(layer_dir, _)| *layer_dir).collect();
+            let mut layer_groups = get_layer_groups(&fixture.path, &layer_dirs).unwrap();
+            layer_groups.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            (fixture, layer_groups)
+        }
+
+        fn first_layers(layer_groups: &[LayerGroup]) -> Vec<PickedLayer<'_>> {
+            layer_groups
+                .iter()
+                .map(|group| PickedLayer::new(&group.layer_type, group.layers().first()))
+                .collect()
+        }
+
+        #[test]
+        fn render_blends_layers() {
+            let (_fixture, mut layer_groups) = layer_groups_of(&[
+                ("bottom", "composite_bottom.png"),
+                ("top", "composite_top.png"),
+            ]);
+            let bottom = Image::read(Fixture::source_path("composite_bottom.png")).unwrap();
+            let top = Image::read(Fixture::source_path("composite_top.png")).unwrap();
+
+            let picks = first_layers(&layer_groups);
+            let mut expected = bottom.clone();
+            expected.stack(&[&top]);
+            assert_eq!(ImageBuilder::render(&picks).unwrap(), expected);
+
+            let top_group = layer_groups.pop().unwrap();
+            layer_groups.push(
+                top_group
+                    .with_blend_mode(BlendMode::Multiply, Some("image"))
+                    .unwrap()
+                    .with_opacity(0.5, None)
+                    .unwrap(),
+            );
+            let picks = first_layers(&layer_groups);
+            let mut expected = bottom;
+            expected.blend(&top, BlendMode::Multiply, 0.5);
+            assert_eq!(ImageBuilder::render(&picks).unwrap(), expected);
+        }
+
+        #[test]
+        fn render_rejects_layers_of_different_size() {
+            let (_fixture, layer_groups) =
+                layer_groups_of(&[("body", "composite_bottom.png"), ("hat", "rgb8.png")]);
+
+            let result = ImageBuilder::render(&first_layers(&layer_groups));
+            match result {
+                Err(NftgenError::DimensionMismatch {
+                    path,
+                    expected,
+                    found,
+                }) => {
+                    assert!(path.ends_with("hat/image#1.png"));
+                    assert_eq!(expected, (16, 16));
+                    assert_eq!(found, (4, 1));
+                }
+                _ => panic!("Expected a dimension mismatch, got {:?}", result),
+            }
+        }
+
+        #[test]
+        fn render_places_positioned_layers() {
+            let (_fixture, mut layer_groups) =
+                layer_groups_of(&[("body", "composite_bottom.png"), ("hat", "rgb8.png")]);
+            let hat = layer_groups.pop().unwrap();
+            layer_groups.push(
+                hat.with_position(Position::Anchor(Anchor::Bottom), None)
+                    .unwrap(),
+            );
+            let body = Image::read(Fixture::source_path("composite_bottom.png")).unwrap();
+            let hat = Image::read(Fixture::source_path("rgb8.png")).unwrap();
+
+            let mut expected = body;
+            expected.blend_at(&hat, (6, 15), BlendMode::Normal, 1.0);
+            assert_eq!(
+                ImageBuilder::render(&first_layers(&layer_groups)).unwrap(),
+                expected
+            );
+        }
+
+        #[test]
+        fn render_animation_repeats_still_layers() {
+            let (_fixture, mut layer_groups) =
+                layer_groups_of(&[("body", "animated.png"), ("hat", "rgb8.png")]);
+            let hat = layer_groups.pop().unwrap();
+            layer_groups.push(
+                hat.with_position(Position::Anchor(Anchor::Top), None)
+                    .unwrap(),
+            );
+            let body = Animation::read(Fixture::source_path("animated.png")).unwrap();
+            let hat = Image::read(Fixture::source_path("rgb8.png")).unwrap();
+
+            let picks = first_layers(&layer_groups);
+            let animation = ImageBuilder::render_animation(&picks).unwrap();
+            assert_eq!(animation.frames.len(), body.frames.len());
+            for (frame, body_frame) in animation.frames.iter().zip(&body.frames) {
+                let mut expected = body_frame.image.clone();
+                expected.stack(&[&hat]);
+                assert_eq!(frame.image, expected);
+                assert_eq!(frame.delay_ms, body_frame.delay_ms);
+            }
+            assert_eq!(
+                &ImageBuilder::render(&picks).unwrap(),
+                animation.first_frame()
+            );
+        }
+
+        #[test]
+        fn render_animation_loops_animated_layers() {
+            let (_fixture, layer_groups) =
+                layer_groups_of(&[("body", "animated.png"), ("eyes", "animated.gif")]);
+            let body = Animation::read(Fixture::source_path("animated.png")).unwrap();
+            let eyes = Animation::read(Fixture::source_path("animated.gif")).unwrap();
+
+            let animation = ImageBuilder::render_animation(&first_layers(&layer_groups)).unwrap();
+            let timeline = Animation::timeline(&[&body, &eyes]);
+            assert_eq!(animation.frames.len(), timeline.len());
+            for (frame, (start_ms, delay_ms)) in animation.frames.iter().zip(timeline) {
+                let mut expected = body.frame_at(start_ms).clone();
+                expected.stack(&[eyes.frame_at(start_ms)]);
+                assert_eq!(frame.image, expected);
+                assert_eq!(frame.delay_ms, delay_ms);
+            }
+        }
+
+        #[test]
+        fn render_animation_of_still_layers() {
+            let (_fixture, layer_groups) = layer_groups_of(&[
+                ("bottom", "composite_bottom.png"),
+                ("top", "composite_top.png"),
+            ]);
+            let picks = first_layers(&layer_groups);
+            let animation = ImageBuilder::render_animation(&picks).unwrap();
+            assert!(!animation.is_animated());
+            assert_eq!(
+                animation.first_frame(),
+                &ImageBuilder::render(&picks).unwrap()
+            );
+        }
+
+        #[test]
+        fn render_draws_parts_behind_other_layer_types() {
+            let (fixture, _) = layer_groups_of(&[
+                ("head", "composite_top.png"),
+                ("hair", "composite_bottom.png"),
+            ]);
+            std::fs::copy(
+                Fixture::source_path("composite_top.png"),
+                fixture.path.join("hair/image~head.png"),
+            )
+            .unwrap();
+            let mut layer_groups = get_layer_groups(&fixture.path, &["head", "hair"]).unwrap();
+            layer_groups.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let top = Image::read(Fixture::source_path("composite_top.png")).unwrap();
+            let bottom = Image::read(Fixture::source_path("composite_bottom.png")).unwrap();
+
+            let picks = first_layers(&layer_groups);
+            assert_eq!(picks.len(), 2);
+            let mut expected = top.clone();
+            expected.stack(&[&top, &bottom]);
+            assert_eq!(ImageBuilder::render(&picks).unwrap(), expected);
+        }
+
+        #[test]
+        fn render_errors_without_layers() {
+            let picks = [PickedLayer::new("layer1", None)];
+            assert!(matches!(
+                ImageBuilder::render(&picks),
+                Err(NftgenError::NothingToRender)
+            ));
+        }
     }
 }
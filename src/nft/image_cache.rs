@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::{Animation, NftgenError};
+
+/// Thread-safe cache of decoded layer images, shared by all layers of a collection
+/// Animated layers are cached with all of their frames. Images are decoded the first time
+/// they are requested. Once the decoded images exceed `max_bytes`, the least recently used
+/// images are dropped and decoded again on demand
+pub struct ImageCache {
+    max_bytes: Option<usize>,
+    state: Mutex<CacheState>,
+}
+
+/// Path of a layer image and the name of its variant, if any
+type CacheKey = (PathBuf, Option<String>);
+
+#[derive(Default)]
+struct CacheState {
+    images: HashMap<CacheKey, CachedImage>,
+    used_bytes: usize,
+    clock: u64,
+}
+
+struct CachedImage {
+    image: Arc<Animation>,
+    last_used: u64,
+}
+
+impl ImageCache {
+    /// Creates a cache that holds at most `max_bytes` of decoded images, or all of them if none
+    pub fn new(max_bytes: Option<usize>) -> Self {
+        ImageCache {
+            max_bytes,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Returns the decoded image at `image_path`, decoding it if it is not cached
+    pub fn get<P: AsRef<Path>>(&self, image_path: P) -> Result<Arc<Animation>, NftgenError> {
+        let image_path = image_path.as_ref();
+        let key = (image_path.to_owned(), None);
+        if let Some(image) = self.lookup(&key) {
+            return Ok(image);
+        }
+
+        // Decode without holding the lock, so other threads can keep using the cache
+        log::debug!("Decoding image: {}", image_path.to_string_lossy());
+        let image = Arc::new(Animation::read(image_path)?);
+        self.insert(key, image.clone());
+        Ok(image)
+    }
+
+    /// Returns the `variant` of the image at `image_path`, creating it from the decoded
+    /// image with `create` if it is not cached
+    pub fn get_variant<P: AsRef<Path>>(
+        &self,
+        image_path: P,
+        variant: &str,
+        create: impl FnOnce(&Animation) -> Animation,
+    ) -> Result<Arc<Animation>, NftgenError> {
+        let image_path = image_path.as_ref();
+        let key = (image_path.to_owned(), Some(variant.to_string()));
+        if let Some(image) = self.lookup(&key) {
+            return Ok(image);
+        }
+
+        let image = Arc::new(create(&*self.get(image_path)?));
+        self.insert(key, image.clone());
+        Ok(image)
+    }
+
+    /// Total size of the decoded images currently held by the cache
+    pub fn used_bytes(&self) -> usize {
+        self.state.lock().unwrap().used_bytes
+    }
+
+    fn lookup(&self, key: &CacheKey) -> Option<Arc<Animation>> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        state.images.get_mut(key).map(|cached| {
+            cached.last_used = clock;
+            cached.image.clone()
+        })
+    }
+
+    fn insert(&self, key: CacheKey, image: Arc<Animation>) {
+        let size = image.size_in_bytes();
+        if self.max_bytes.is_some_and(|max_bytes| size > max_bytes) {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let cached = CachedImage {
+            image,
+            last_used: state.clock,
+        };
+        if let Some(previous) = state.images.insert(key, cached) {
+            state.used_bytes -= previous.image.size_in_bytes();
+        }
+        state.used_bytes += size;
+
+        while self
+            .max_bytes
+            .is_some_and(|max_bytes| state.used_bytes > max_bytes)
+        {
+            let least_recently_used = state
+                .images
+                .iter()
+                .min_by_key(|(_, cached)| cached.last_used)
+                .map(|(path, _)| path.clone())
+                .expect("Cache over its limit should hold an image");
+            let evicted = state.images.remove(&least_recently_used).unwrap();
+            state.used_bytes -= evicted.image.size_in_bytes();
+            log::debug!(
+                "Evicted image from cache: {}",
+                least_recently_used.0.to_string_lossy()
+            );
+        }
+    }
+}
+
+impl Default for ImageCache {
+    fn default() -> Self {
+        ImageCache::new(None)
+    }
+}
+
+impl Debug for ImageCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ImageCache")
+            .field("max_bytes", &self.max_bytes)
+            .field("used_bytes", &self.used_bytes())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nft::tests::fixture::Fixture;
+
+    #[test]
+    fn get_decodes_once() {
+        let fixture = Fixture::create_layers_dirs("minimal.png", &["background"]);
+        let image_path = fixture.path.join("background/image1#1.png");
+        let cache = ImageCache::default();
+
+        let first = cache.get(&image_path).unwrap();
+        std::fs::remove_file(&image_path).unwrap();
+        let second = cache.get(&image_path).unwrap();
+
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(cache.used_bytes(), 4);
+    }
+
+    #[test]
+    fn get_returns_err_for_invalid_png() {
+        let fixture = Fixture::create_layers_dirs("empty.png", &["background"]);
+        let cache = ImageCache::default();
+
+        let result = cache.get(fixture.path.join("background/image1#1.png"));
+        assert!(matches!(result, Err(NftgenError::Decode(_))));
+        assert_eq!(cache.used_bytes(), 0);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_images() {
+        let fixture = Fixture::create_layers_dirs("minimal.png", &["background"]);
+        let path = |i: usize| {
+            fixture
+                .path
+                .join(format!("background/image{}#{}.png", i, i))
+        };
+        // Every image takes 4 bytes
+        let cache = ImageCache::new(Some(8));
+
+        let first = cache.get(path(1)).unwrap();
+        cache.get(path(2)).unwrap();
+        cache.get(path(1)).unwrap();
+        cache.get(path(3)).unwrap();
+        assert_eq!(cache.used_bytes(), 8);
+
+        // image2 was evicted, image1 is still cached
+        std::fs::remove_file(path(1)).unwrap();
+        std::fs::remove_file(path(2)).unwrap();
+        assert!(Arc::ptr_eq(&first, &cache.get(path(1)).unwrap()));
+        assert!(cache.get(path(2)).is_err());
+    }
+
+    #[test]
+    fn get_variant_creates_variant_once() {
+        let fixture = Fixture::create_layers_dirs("minimal.png", &["background"]);
+        let image_path = fixture.path.join("background/image1#1.png");
+        let cache = ImageCache::default();
+
+        let original = cache.get(&image_path).unwrap();
+        let variant = cache
+            .get_variant(&image_path, "slow", |animation| {
+                let mut animation = animation.clone();
+                animation.frames[0].delay_ms = 1000;
+                animation
+            })
+            .unwrap();
+        assert_ne!(original, variant);
+        let cached = cache
+            .get_variant(&image_path, "slow", |_| panic!("Variant should be cached"))
+            .unwrap();
+        assert!(Arc::ptr_eq(&variant, &cached));
+        assert!(Arc::ptr_eq(&original, &cache.get(&image_path).unwrap()));
+        assert_eq!(cache.used_bytes(), 8);
+    }
+}
@@ -1,51 +1,217 @@
+use std::borrow::Cow;
 use std::fs::DirEntry;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use crate::{Image, NftgenError};
+use crate::{Animation, BlendMode, ImageCache, NftgenError, Palette, Position};
+
+/// Name of the reserved layer file (e.g. `none#5.png`) that leaves its layer group empty
+pub const NONE_LAYER_NAME: &str = "none";
+
+/// Separates the name of a linked layer from its variant, e.g. `slim@dark#5.png`
+pub const VARIANT_DELIMITER: char = '@';
+
+/// Separates the name of a layer from the layer type a part of it is drawn behind,
+/// e.g. `long~Head.png` for the back of `Hair/long#5.png`
+pub const PART_DELIMITER: char = '~';
+
+/// Image of a layer drawn at another depth than its layer group, e.g. the back of a hairstyle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerPart {
+    pub image_path: PathBuf,
+    /// Layer type the part is drawn right behind
+    pub behind: String,
+}
+
+impl LayerPart {
+    /// Parses a part from its file name, e.g. `long~Head.png`
+    /// Returns the name of the layer it belongs to with the part, or none if it is not a part
+    pub fn parse<P: Into<PathBuf>>(image_path: P) -> Option<(String, LayerPart)> {
+        let image_path = image_path.into();
+        let stem = image_path.file_stem()?.to_str()?.split('#').next()?;
+        let (name, behind) = stem.split_once(PART_DELIMITER)?;
+        let (name, behind) = (name.to_string(), behind.to_string());
+        Some((name, LayerPart { image_path, behind }))
+    }
+}
 
 /// Represents a value for single NFT layer
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub struct Layer {
     pub image_path: PathBuf,
-    pub weight: u32,
+    pub weight: f64,
+    pub blend_mode: BlendMode,
+    pub opacity: f64,
+    /// Placement on the canvas. Layers without a position must be as large as the canvas
+    pub position: Option<Position>,
+    /// Colors swapped in the image, for layers that are palette variants of a sprite
+    pub palette: Option<Palette>,
+    /// Attribute shared with layers of other groups that must have the same variant, e.g. `Skin`
+    pub link: Option<String>,
+    /// Images of this layer drawn behind other layer types instead of in its own layer group
+    pub parts: Vec<LayerPart>,
+    /// Names of the nested directories of the layer group this layer is in, e.g. `["Shirts"]`
+    pub category: Vec<String>,
+    /// Name set by a layer manifest, used instead of the file or palette name
+    pub display_name: Option<String>,
+    /// Rarity tier set by a layer manifest, picked before a layer of the tier
+    pub tier: Option<String>,
+    image_cache: Arc<ImageCache>,
 }
 
 impl Layer {
-    pub fn new<P: Into<PathBuf>>(image_path: P, weight: u32) -> Self {
+    pub fn new<P: Into<PathBuf>, W: Into<f64>>(image_path: P, weight: W) -> Self {
         Layer {
             image_path: image_path.into(),
-            weight,
+            weight: weight.into(),
+            blend_mode: BlendMode::Normal,
+            opacity: 1.0,
+            position: None,
+            palette: None,
+            link: None,
+            parts: vec![],
+            category: vec![],
+            display_name: None,
+            tier: None,
+            image_cache: Arc::new(ImageCache::default()),
         }
     }
 
+    /// Shares `image_cache` with other layers, so each image is decoded once per collection
+    pub fn with_image_cache(mut self, image_cache: Arc<ImageCache>) -> Self {
+        self.image_cache = image_cache;
+        self
+    }
+
+    /// Makes this layer a variant of its image with the colors of `palette`
+    pub fn with_palette(mut self, palette: Palette) -> Self {
+        self.palette = Some(palette);
+        self
+    }
+
+    /// Links this layer with layers of other groups through their variants, see `variant`
+    pub fn with_link<S: Into<String>>(mut self, link: S) -> Self {
+        self.link = Some(link.into());
+        self
+    }
+
+    /// Name of the image file without weight, which the file names of its parts start with
+    pub fn file_name(&self) -> Option<&str> {
+        self.image_path.file_stem()?.to_str()?.split('#').next()
+    }
+
+    /// Display name from a layer manifest, or name of the palette, or of the image file without
+    /// weight. Linked layers leave out their variant
     /// Returns none if the filename is not valid unicode
     pub fn name(&self) -> Option<&str> {
-        self.image_path.file_stem()?.to_str()?.split('#').next()
+        match &self.display_name {
+            Some(display_name) => Some(display_name),
+            None => self.base_name(),
+        }
+    }
+
+    fn base_name(&self) -> Option<&str> {
+        let name = self.tagged_name()?;
+        match self.link {
+            Some(_) => name.split(VARIANT_DELIMITER).next(),
+            None => Some(name),
+        }
+    }
+
+    /// Variant of a linked layer, e.g. `dark` for `slim@dark#5.png`
+    /// Returns none for layers that are not linked or have no variant
+    pub fn variant(&self) -> Option<&str> {
+        self.link.as_ref()?;
+        let (_, variant) = self.tagged_name()?.split_once(VARIANT_DELIMITER)?;
+        Some(variant)
     }
 
-    /// Reads PNG from `self.image_path` and returns an `Image`
-    pub fn get_image(&self) -> Result<Image, NftgenError> {
-        Image::read(&self.image_path)
+    fn tagged_name(&self) -> Option<&str> {
+        match &self.palette {
+            Some(palette) => Some(&palette.name),
+            None => self.file_name(),
+        }
+    }
+
+    /// Path of the category of a nested layer, e.g. `Shirts` or `Tops/Shirts`
+    /// Returns none for layers directly in their layer group
+    pub fn category_name(&self) -> Option<String> {
+        match self.category.is_empty() {
+            true => None,
+            false => Some(self.category.join("/")),
+        }
+    }
+
+    /// Name of the layer prefixed with its category, e.g. `Shirts/red`
+    pub fn qualified_name(&self) -> Option<Cow<'_, str>> {
+        let name = self.name()?;
+        match self.category_name() {
+            Some(category) => Some(Cow::Owned(format!("{}/{}", category, name))),
+            None => Some(Cow::Borrowed(name)),
+        }
+    }
+
+    /// Returns true for the reserved `none` layer, which renders nothing
+    pub fn is_none(&self) -> bool {
+        self.base_name() == Some(NONE_LAYER_NAME)
+    }
+
+    /// Returns the decoded frames of the PNG, APNG or GIF at `self.image_path`,
+    /// reading it only if it is not cached yet
+    pub fn get_animation(&self) -> Result<Arc<Animation>, NftgenError> {
+        self.get_animation_at(&self.image_path)
+    }
+
+    /// Like `get_animation`, for the image of `part`
+    pub fn get_part_animation(&self, part: &LayerPart) -> Result<Arc<Animation>, NftgenError> {
+        self.get_animation_at(&part.image_path)
+    }
+
+    fn get_animation_at(&self, image_path: &Path) -> Result<Arc<Animation>, NftgenError> {
+        match &self.palette {
+            Some(palette) => self
+                .image_cache
+                .get_variant(image_path, &palette.name, |animation| {
+                    palette.apply_to_animation(animation)
+                }),
+            None => self.image_cache.get(image_path),
+        }
     }
 
     /// Parses weight from file stem of image file
-    /// `filestem` - A stem of a file i.e. filename without extension e.g. red#5
-    fn parse_weight_from_file_stem(filestem: &str) -> Result<u32, NftgenError> {
+    /// `filestem` - A stem of a file i.e. filename without extension e.g. red#5 or red#2.5
+    pub(crate) fn parse_weight_from_file_stem(filestem: &str) -> Result<f64, NftgenError> {
         let weight_str = filestem.split_once('#').unwrap_or(("", "")).1;
 
-        match weight_str.parse::<u32>() {
-            Ok(weight) => Ok(weight),
-            Err(_) => {
+        match weight_str.parse::<f64>() {
+            Ok(weight) if weight.is_finite() && weight >= 0.0 => Ok(weight),
+            _ => {
                 log::warn!(
                     "Invalid weight for layer with filestem: {}. Using default weight of 1",
                     filestem
                 );
-                Ok(1)
+                Ok(1.0)
             }
         }
     }
 }
 
+impl PartialEq for Layer {
+    fn eq(&self, other: &Self) -> bool {
+        self.image_path == other.image_path
+            && self.weight == other.weight
+            && self.blend_mode == other.blend_mode
+            && self.opacity == other.opacity
+            && self.position == other.position
+            && self.palette == other.palette
+            && self.link == other.link
+            && self.parts == other.parts
+            && self.category == other.category
+            && self.display_name == other.display_name
+            && self.tier == other.tier
+    }
+}
+
 impl TryFrom<DirEntry> for Layer {
     type Error = NftgenError;
 
@@ -53,7 +219,7 @@ impl TryFrom<DirEntry> for Layer {
         if let Some(name) = entry.path().file_stem() {
             match name.to_str() {
                 Some(name) => Ok(Layer::new(
-                    &entry.path(),
+                    entry.path(),
                     Layer::parse_weight_from_file_stem(name)?,
                 )),
                 None => Err(NftgenError::InvalidFilename(entry.path())),
@@ -99,32 +265,102 @@ mod tests {
     }
 
     #[test]
-    fn layer_get_image_works() {
+    fn linked_layer_name_and_variant() {
+        let layer = Layer::new("layers/body/slim@dark#5.png", 5);
+        assert_eq!(layer.name(), Some("slim@dark"));
+        assert_eq!(layer.variant(), None);
+
+        let layer = layer.with_link("Skin");
+        assert_eq!(layer.name(), Some("slim"));
+        assert_eq!(layer.variant(), Some("dark"));
+
+        let layer = Layer::new("layers/body/slim#5.png", 5).with_link("Skin");
+        assert_eq!(layer.name(), Some("slim"));
+        assert_eq!(layer.variant(), None);
+    }
+
+    #[test]
+    fn layer_part_parse_works() {
+        let (name, part) = LayerPart::parse("layers/hair/long~Head.png").unwrap();
+        assert_eq!(name, "long");
+        assert_eq!(part.behind, "Head");
+        assert_eq!(
+            part.image_path,
+            std::path::Path::new("layers/hair/long~Head.png")
+        );
+        assert_eq!(LayerPart::parse("layers/hair/long#5.png"), None);
+    }
+
+    #[test]
+    fn layer_category_name_works() {
+        let mut layer = Layer::new("layers/clothing/tops/shirts/red#5.png", 5);
+        assert_eq!(layer.category_name(), None);
+        assert_eq!(layer.qualified_name().unwrap(), "red");
+
+        layer.category = vec!["Tops".to_string(), "Shirts".to_string()];
+        assert_eq!(layer.category_name(), Some("Tops/Shirts".to_string()));
+        assert_eq!(layer.qualified_name().unwrap(), "Tops/Shirts/red");
+    }
+
+    #[test]
+    fn layer_is_none_works() {
+        assert!(Layer::new("layers/hat/none#5.png", 5).is_none());
+        assert!(Layer::new("layers/hat/none#5", 5).is_none());
+        assert!(!Layer::new("layers/hat/nonetheless#5.png", 5).is_none());
+    }
+
+    #[test]
+    fn layer_get_animation_works() {
         let fixture = Fixture::create_layers_dirs("minimal.png", &["background"]);
         let image_path = fixture.path.join("background/image1#1.png");
 
         let layer = Layer::new(&image_path, 5);
-        let image = layer.get_image();
+        let image = layer.get_animation();
         assert!(image.is_ok());
     }
 
     #[test]
-    fn layer_get_image_returns_err_for_invalid_png() {
+    fn layer_get_animation_uses_shared_cache() {
+        let fixture = Fixture::create_layers_dirs("minimal.png", &["background"]);
+        let image_path = fixture.path.join("background/image1#1.png");
+        let image_cache = Arc::new(ImageCache::default());
+
+        let first = Layer::new(&image_path, 5).with_image_cache(image_cache.clone());
+        let second = Layer::new(&image_path, 5).with_image_cache(image_cache);
+        assert!(Arc::ptr_eq(
+            &first.get_animation().unwrap(),
+            &second.get_animation().unwrap()
+        ));
+    }
+
+    #[test]
+    fn layer_get_animation_returns_err_for_invalid_png() {
         let fixture = Fixture::create_layers_dirs("empty.png", &["background"]);
         let image_path = fixture.path.join("background/image1#1.png");
 
         let layer = Layer::new(&image_path, 5);
-        let image = layer.get_image();
+        let image = layer.get_animation();
         assert!(image.is_err());
     }
 
     #[test]
     fn parse_weight_from_filestem_works() {
-        assert_eq!(Layer::parse_weight_from_file_stem("beauty#10").unwrap(), 10)
+        assert_eq!(
+            Layer::parse_weight_from_file_stem("beauty#10").unwrap(),
+            10.0
+        );
+        assert_eq!(
+            Layer::parse_weight_from_file_stem("beauty#2.5").unwrap(),
+            2.5
+        )
     }
 
     #[test]
     fn parse_weight_from_filestem_returns_1_if_invalid_filestem() {
-        assert_eq!(Layer::parse_weight_from_file_stem("beauty").unwrap(), 1)
+        assert_eq!(Layer::parse_weight_from_file_stem("beauty").unwrap(), 1.0);
+        assert_eq!(
+            Layer::parse_weight_from_file_stem("beauty#-2").unwrap(),
+            1.0
+        )
     }
 }
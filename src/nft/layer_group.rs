@@ -1,48 +1,531 @@
+use std::borrow::Cow;
 use std::cmp::Ordering;
+use std::collections::BTreeSet;
 use std::path::Path;
+use std::sync::Arc;
 
 use rand::distributions::WeightedIndex;
 use rand::prelude::*;
 
-use crate::nft::parse_layers_from_path;
-use crate::{Layer, NftgenError};
+use crate::nft::{apportion, parse_layer_tree};
+use crate::{
+    read_manifest, BlendMode, GroupManifest, ImageCache, Layer, NftgenError, Position, Tier,
+    NONE_LAYER_NAME,
+};
 
 /// Represents all of the values for a particular NFT layer group
 /// e.g. Background, Foreground, etc.
 #[derive(Debug)]
 pub struct LayerGroup {
     pub layer_type: String,
+    trait_type: Option<String>,
     layers: Vec<Layer>,
+    categories: Vec<LayerCategory>,
+    tiers: Vec<Tier>,
     order: u8,
+    skip_probability: f64,
+    link: Option<String>,
+    /// Probability of every layer, see `probability`, computed once when the group is built
+    probabilities: Vec<f64>,
+    /// Indices of the layers inside every category, nested categories included
+    category_layers: Vec<Vec<usize>>,
+}
+
+/// Nested directory of a layer group, e.g. `Clothing/Shirts#3`, picked by its weight among
+/// the layers and categories next to it before one of the layers inside it is picked
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerCategory {
+    /// Names of the directories from the layer group down to the category, e.g. `["Shirts"]`
+    pub path: Vec<String>,
+    pub weight: f64,
+}
+
+/// The layer picked from a layer group for a single NFT
+/// `layer` is none if the group was skipped or its `none` layer was picked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PickedLayer<'a> {
+    pub layer_type: &'a str,
+    /// Attribute name of the layer group in the metadata, usually the layer type
+    pub trait_type: &'a str,
+    pub layer: Option<&'a Layer>,
+}
+
+impl<'a> PickedLayer<'a> {
+    pub fn new(layer_type: &'a str, layer: Option<&'a Layer>) -> Self {
+        PickedLayer {
+            layer_type,
+            trait_type: layer_type,
+            layer: layer.filter(|layer| !layer.is_none()),
+        }
+    }
+
+    /// Name of the picked layer, or `none` if nothing was picked
+    pub fn name(&self) -> &'a str {
+        match self.layer {
+            Some(layer) => layer.name().expect("Layer name should be valid unicode"),
+            None => NONE_LAYER_NAME,
+        }
+    }
+
+    /// Name of the picked layer prefixed with its category, see `Layer::qualified_name`
+    pub fn qualified_name(&self) -> Cow<'a, str> {
+        match self.layer {
+            Some(layer) => layer
+                .qualified_name()
+                .expect("Layer name should be valid unicode"),
+            None => Cow::Borrowed(NONE_LAYER_NAME),
+        }
+    }
+
+    /// Link and variant of the picked layer, if it is linked with other groups
+    pub fn linked_variant(&self) -> Option<(&'a str, &'a str)> {
+        let layer = self.layer?;
+        Some((layer.link.as_deref()?, layer.variant()?))
+    }
 }
 
 impl LayerGroup {
+    /// Parses the layers in `layer_path`, set up by the `layers.toml` or `layers.json` manifest
+    /// of the directory if there is one
     pub fn new<T: AsRef<str>, P: AsRef<Path>>(
         layer_path: P,
         layers_order: &[T],
     ) -> Result<Self, NftgenError> {
         let layer_path = layer_path.as_ref();
-        let layers = parse_layers_from_path(layer_path)?;
+        let manifest: Option<GroupManifest> = read_manifest(layer_path)?;
+        LayerGroup::from_manifest(layer_path, layers_order, manifest.as_ref())
+    }
+
+    /// Parses the layers in `layer_path`, set up by `manifest`, whose weights take precedence
+    /// over the weights in file names
+    pub fn from_manifest<T: AsRef<str>, P: AsRef<Path>>(
+        layer_path: P,
+        layers_order: &[T],
+        manifest: Option<&GroupManifest>,
+    ) -> Result<Self, NftgenError> {
+        let layer_path = layer_path.as_ref();
+        let image_cache = Arc::new(ImageCache::default());
+        let (mut layers, mut categories) = parse_layer_tree(layer_path)?;
+        if let Some(manifest) = manifest {
+            manifest.apply(layer_path, &mut layers, &mut categories)?;
+        }
+        let layers = layers
+            .into_iter()
+            .map(|layer| layer.with_image_cache(image_cache.clone()))
+            .collect();
+        let mut tiers: Vec<Tier> = manifest
+            .iter()
+            .flat_map(|manifest| &manifest.tiers)
+            .map(|(name, &weight)| Tier::new(name, weight))
+            .collect();
+        tiers.sort_by(|a, b| b.weight.total_cmp(&a.weight));
 
         if let Some(layer_type_str) = layer_path.file_name() {
             let layer_type = layer_type_str.to_string_lossy().to_string();
             let order = LayerGroup::get_order(&layer_type, layers_order)?;
-            Ok(LayerGroup {
+            let layer_group = LayerGroup {
                 layer_type,
+                trait_type: manifest.and_then(|manifest| manifest.trait_type.clone()),
                 layers,
+                categories,
+                tiers,
                 order,
-            })
+                skip_probability: 0.0,
+                link: None,
+                probabilities: vec![],
+                category_layers: vec![],
+            };
+            Ok(layer_group.with_weights())
         } else {
             Err(NftgenError::InvalidLayerPath(layer_path.to_owned()))
         }
     }
 
-    pub fn pick(&self) -> &Layer {
-        let weights: Vec<_> = self.layers.iter().map(|l| l.weight).collect();
-        let dist = WeightedIndex::new(&weights).unwrap();
-        let mut rng = rand::thread_rng();
+    /// Computes the probability of every layer and the layers inside every category, which
+    /// only change with the weights, tiers and categories set up in `from_manifest`
+    fn with_weights(mut self) -> Self {
+        self.probabilities = self
+            .layers
+            .iter()
+            .map(|layer| self.compute_probability(layer))
+            .collect();
+        self.category_layers = self
+            .categories
+            .iter()
+            .map(|category| {
+                (0..self.layers.len())
+                    .filter(|&i| self.layers[i].category.starts_with(&category.path))
+                    .collect()
+            })
+            .collect();
+        self
+    }
+
+    /// Makes the group optional: NFTs get no layer from this group with probability `skip_probability`
+    pub fn with_skip_probability(mut self, skip_probability: f64) -> Self {
+        self.skip_probability = skip_probability.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Probability that NFTs get no layer from this group, see `with_skip_probability`
+    pub fn skip_probability(&self) -> f64 {
+        self.skip_probability
+    }
+
+    /// Links this group with other groups through the variants of their layers, see `Layer::variant`
+    /// Every layer except `none` must have a variant
+    pub fn with_link(mut self, link: &str) -> Result<Self, NftgenError> {
+        self.layers = self
+            .layers
+            .into_iter()
+            .map(|layer| layer.with_link(link))
+            .collect();
+        if let Some(layer) = self
+            .layers
+            .iter()
+            .find(|layer| !layer.is_none() && layer.variant().is_none())
+        {
+            return Err(NftgenError::InvalidLink(format!(
+                "{} has no variant, name it like <name>@<variant>",
+                layer.image_path.to_string_lossy()
+            )));
+        }
+        self.link = Some(link.to_string());
+        Ok(self)
+    }
+
+    /// Shares `image_cache` between all layers of this group
+    pub fn with_image_cache(mut self, image_cache: Arc<ImageCache>) -> Self {
+        self.layers = self
+            .layers
+            .into_iter()
+            .map(|layer| layer.with_image_cache(image_cache.clone()))
+            .collect();
+        self
+    }
+
+    /// Sets how the layer named `layer_name`, or every layer if none, blends with the layers below
+    /// Layers of nested groups can also be named with their category, e.g. `Shirts/red`
+    pub fn with_blend_mode(
+        mut self,
+        blend_mode: BlendMode,
+        layer_name: Option<&str>,
+    ) -> Result<Self, NftgenError> {
+        for layer in self.layers_named(layer_name)? {
+            layer.blend_mode = blend_mode;
+        }
+        Ok(self)
+    }
+
+    /// Sets the opacity of the layer named `layer_name`, or of every layer if none
+    pub fn with_opacity(
+        mut self,
+        opacity: f64,
+        layer_name: Option<&str>,
+    ) -> Result<Self, NftgenError> {
+        for layer in self.layers_named(layer_name)? {
+            layer.opacity = opacity.clamp(0.0, 1.0);
+        }
+        Ok(self)
+    }
+
+    /// Places the layer named `layer_name`, or every layer if none, at `position` on the canvas
+    pub fn with_position(
+        mut self,
+        position: Position,
+        layer_name: Option<&str>,
+    ) -> Result<Self, NftgenError> {
+        for layer in self.layers_named(layer_name)? {
+            layer.position = Some(position);
+        }
+        Ok(self)
+    }
+
+    /// Returns true if the group has a layer named `layer_name`, with or without its category
+    pub fn has_layer(&self, layer_name: &str) -> bool {
+        self.layers
+            .iter()
+            .any(|layer| LayerGroup::is_named(layer, Some(layer_name)))
+    }
+
+    fn is_named(layer: &Layer, layer_name: Option<&str>) -> bool {
+        layer_name.is_none()
+            || layer.name() == layer_name
+            || layer.qualified_name().as_deref() == layer_name
+    }
+
+    fn layers_named(&mut self, layer_name: Option<&str>) -> Result<Vec<&mut Layer>, NftgenError> {
+        let layers: Vec<_> = self
+            .layers
+            .iter_mut()
+            .filter(|layer| LayerGroup::is_named(layer, layer_name))
+            .collect();
+        match layer_name {
+            Some(layer_name) if layers.is_empty() => Err(NftgenError::UnknownLayer(format!(
+                "{}/{}",
+                self.layer_type, layer_name
+            ))),
+            _ => Ok(layers),
+        }
+    }
+
+    /// Attribute name of this group in the metadata
+    pub fn trait_type(&self) -> &str {
+        self.trait_type.as_deref().unwrap_or(&self.layer_type)
+    }
+
+    /// Records `layer`, or none if the group was skipped, as the pick of this group
+    pub fn picked<'a>(&'a self, layer: Option<&'a Layer>) -> PickedLayer<'a> {
+        PickedLayer {
+            trait_type: self.trait_type(),
+            ..PickedLayer::new(&self.layer_type, layer)
+        }
+    }
 
-        &self.layers[dist.sample(&mut rng)]
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    /// Layers with a non-zero probability, see `probability`
+    pub fn pickable_layers(&self) -> impl Iterator<Item = &Layer> {
+        self.layers
+            .iter()
+            .zip(&self.probabilities)
+            .filter(|(_, &probability)| probability > 0.0)
+            .map(|(layer, _)| layer)
+    }
+
+    pub fn categories(&self) -> &[LayerCategory] {
+        &self.categories
+    }
+
+    /// Rarity tiers of this group, empty unless set by a layer manifest
+    pub fn tiers(&self) -> &[Tier] {
+        &self.tiers
+    }
+
+    /// Probability that `layer` is picked, taking the weights of its tier and categories
+    /// into account
+    pub fn probability(&self, layer: &Layer) -> f64 {
+        self.layers
+            .iter()
+            .position(|l| std::ptr::eq(l, layer))
+            .map_or_else(
+                || self.compute_probability(layer),
+                |i| self.probabilities[i],
+            )
+    }
+
+    fn compute_probability(&self, layer: &Layer) -> f64 {
+        let mut probability = match &layer.tier {
+            Some(tier) => self.tier_probability(tier),
+            None => 1.0,
+        };
+        for depth in 0..layer.category.len() {
+            let category = self
+                .categories
+                .iter()
+                .find(|category| category.path == layer.category[..depth + 1])
+                .expect("Layer category should be a category of its group");
+            probability *=
+                category.weight / self.weight_at(&layer.category[..depth], layer.tier.as_deref());
+        }
+        probability * layer.weight / self.weight_at(&layer.category, layer.tier.as_deref())
+    }
+
+    /// Probability that a layer of `tier` is picked, when this group is not skipped
+    pub fn tier_probability(&self, tier: &str) -> f64 {
+        let weight_sum: f64 = self.tiers.iter().map(|t| t.weight).sum();
+        self.tiers
+            .iter()
+            .find(|t| t.name == tier)
+            .map_or(0.0, |t| t.weight / weight_sum)
+    }
+
+    /// Sum of the weights of the layers of `tier` and of the categories holding such layers
+    /// directly in the category `path`
+    fn weight_at(&self, path: &[String], tier: Option<&str>) -> f64 {
+        let layers: f64 = self
+            .layers
+            .iter()
+            .filter(|l| l.category == path && l.tier.as_deref() == tier)
+            .map(|l| l.weight)
+            .sum();
+        let categories: f64 = self
+            .categories
+            .iter()
+            .filter(|c| c.path[..c.path.len() - 1] == *path)
+            .filter(|c| {
+                self.layers
+                    .iter()
+                    .any(|l| l.category.starts_with(&c.path) && l.tier.as_deref() == tier)
+            })
+            .map(|c| c.weight)
+            .sum();
+        layers + categories
+    }
+
+    /// Attribute this group is linked on, if any
+    pub fn link(&self) -> Option<&str> {
+        self.link.as_deref()
+    }
+
+    /// Variants of the layers with a non-zero weight, empty unless the group is linked
+    pub fn variants(&self) -> BTreeSet<&str> {
+        self.layers
+            .iter()
+            .filter(|l| l.weight > 0.0)
+            .filter_map(|l| l.variant())
+            .collect()
+    }
+
+    /// Returns true if NFTs can end up without a layer from this group
+    pub fn is_optional(&self) -> bool {
+        self.skip_probability > 0.0 || self.layers.iter().any(|l| l.is_none() && l.weight > 0.0)
+    }
+
+    /// Number of distinct values this group can contribute to an NFT, including `none`
+    pub fn num_choices(&self) -> usize {
+        self.num_choices_where(|_| true)
+    }
+
+    /// Like `num_choices`, counting only the layers of `variant`
+    pub fn num_variant_choices(&self, variant: &str) -> usize {
+        self.num_choices_where(|l| l.variant() == Some(variant))
+    }
+
+    fn num_choices_where(&self, filter: impl Fn(&Layer) -> bool) -> usize {
+        let pickable = self
+            .layers
+            .iter()
+            .filter(|l| l.weight > 0.0 && !l.is_none() && filter(l))
+            .count();
+        pickable + self.is_optional() as usize
+    }
+
+    /// Splits `num` NFTs between the values of this group in exact proportion to their weights
+    /// The skip probability of an optional group becomes the share of NFTs without a layer
+    /// Fails unless every value gets a whole number of NFTs
+    pub fn quotas(&self, num: usize) -> Result<Vec<(PickedLayer<'_>, usize)>, NftgenError> {
+        let is_whole = |share: f64| (share - share.round()).abs() <= 1e-9;
+        let skipped = self.skip_probability * num as f64;
+        if !is_whole(skipped) {
+            return Err(NftgenError::QuotaUnmet(format!(
+                "{} would be skipped by {:.2} of {} NFTs, which is not a whole number",
+                self.layer_type, skipped, num
+            )));
+        }
+        let skipped = skipped.round() as usize;
+        let (layers, weights): (Vec<&Layer>, Vec<f64>) = self
+            .layers
+            .iter()
+            .zip(self.probabilities.iter().copied())
+            .filter(|(_, probability)| *probability > 0.0)
+            .unzip();
+
+        let weight_sum: f64 = weights.iter().sum();
+        if let Some((layer, share)) = layers
+            .iter()
+            .zip(&weights)
+            .map(|(layer, weight)| (layer, weight / weight_sum * (num - skipped) as f64))
+            .find(|&(_, share)| !is_whole(share))
+        {
+            return Err(NftgenError::QuotaUnmet(format!(
+                "{}/{} would get {:.2} of {} NFTs, which is not a whole number",
+                self.layer_type,
+                self.picked(Some(layer)).qualified_name(),
+                share,
+                num - skipped
+            )));
+        }
+        let counts = apportion(&weights, num - skipped);
+
+        let mut quotas: Vec<_> = layers
+            .into_iter()
+            .zip(counts)
+            .map(|(layer, count)| (self.picked(Some(layer)), count))
+            .collect();
+        if skipped > 0 {
+            quotas.push((self.picked(None), skipped));
+        }
+        Ok(quotas)
+    }
+
+    /// Decides whether to skip this group for an NFT, according to its skip probability
+    pub fn skip<R: Rng + ?Sized>(&self, rng: &mut R) -> bool {
+        self.skip_probability > 0.0 && rng.gen_bool(self.skip_probability)
+    }
+
+    /// Picks a layer according to the layer weights, using `rng` as the source of randomness
+    pub fn pick<R: Rng + ?Sized>(&self, rng: &mut R) -> &Layer {
+        self.pick_where(rng, |_| true)
+            .expect("Layer group should contain a layer with a non-zero weight")
+    }
+
+    /// Picks a layer among those for which `allowed` returns true
+    /// Groups with tiers first pick a tier by weight, skipping tiers without allowed layers
+    /// Nested groups then pick a category by weight, then a layer or category inside it,
+    /// skipping categories without allowed layers
+    /// Returns none if no allowed layer has a non-zero weight
+    pub fn pick_where<R, F>(&self, rng: &mut R, allowed: F) -> Option<&Layer>
+    where
+        R: Rng + ?Sized,
+        F: Fn(&Layer) -> bool,
+    {
+        let tier = if self.tiers.is_empty() {
+            None
+        } else {
+            let weights: Vec<_> = self
+                .tiers
+                .iter()
+                .map(|t| {
+                    let has_allowed =
+                        self.layers.iter().zip(&self.probabilities).any(|(l, &p)| {
+                            l.tier.as_deref() == Some(&t.name) && p > 0.0 && allowed(l)
+                        });
+                    if has_allowed {
+                        t.weight
+                    } else {
+                        0.0
+                    }
+                })
+                .collect();
+            let dist = WeightedIndex::new(&weights).ok()?;
+            Some(self.tiers[dist.sample(rng)].name.as_str())
+        };
+        let allowed = |l: &Layer| l.tier.as_deref() == tier && allowed(l);
+
+        let mut path: &[String] = &[];
+        loop {
+            let layers: Vec<_> = self.layers.iter().filter(|l| l.category == path).collect();
+            let categories: Vec<_> = self
+                .categories
+                .iter()
+                .zip(&self.category_layers)
+                .filter(|(c, _)| c.path[..c.path.len() - 1] == *path)
+                .collect();
+            let weights: Vec<_> = layers
+                .iter()
+                .map(|l| if allowed(l) { l.weight } else { 0.0 })
+                .chain(categories.iter().map(|(c, indices)| {
+                    let has_allowed = indices
+                        .iter()
+                        .any(|&i| self.probabilities[i] > 0.0 && allowed(&self.layers[i]));
+                    if has_allowed {
+                        c.weight
+                    } else {
+                        0.0
+                    }
+                }))
+                .collect();
+            let dist = WeightedIndex::new(&weights).ok()?;
+
+            let index = dist.sample(rng);
+            match layers.get(index) {
+                Some(layer) => return Some(layer),
+                None => path = &categories[index - layers.len()].0.path,
+            }
+        }
     }
 
     fn get_order<T: AsRef<str>>(layer_type: &str, layers_order: &[T]) -> Result<u8, NftgenError> {
@@ -67,3 +550,256 @@ impl PartialEq for LayerGroup {
         self.order == other.order
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nft::tests::fixture::Fixture;
+    use crate::token_rng;
+
+    #[test]
+    fn pick_is_deterministic_for_seeded_rng() {
+        let fixture = Fixture::create_layers_dirs("minimal.png", &["background"]);
+        let layer_group =
+            LayerGroup::new(fixture.path.join("background"), &["background"]).unwrap();
+
+        for token_id in 0..20 {
+            let first = layer_group.pick(&mut token_rng(1234, token_id));
+            let second = layer_group.pick(&mut token_rng(1234, token_id));
+            assert_eq!(first, second);
+        }
+    }
+
+    #[test]
+    fn skip_probability() {
+        let fixture = Fixture::create_layers_dirs("minimal.png", &["hat"]);
+        let layer_group = LayerGroup::new(fixture.path.join("hat"), &["hat"]).unwrap();
+        let mut rng = token_rng(0, 0);
+
+        assert!(!layer_group.is_optional());
+        assert_eq!(layer_group.num_choices(), 9);
+        assert!(!layer_group.skip(&mut rng));
+
+        let layer_group = layer_group.with_skip_probability(1.0);
+        assert!(layer_group.is_optional());
+        assert_eq!(layer_group.num_choices(), 10);
+        assert!(layer_group.skip(&mut rng));
+    }
+
+    #[test]
+    fn blend_mode_and_opacity() {
+        let fixture = Fixture::create_layers_dirs("minimal.png", &["lighting"]);
+        let layer_group = LayerGroup::new(fixture.path.join("lighting"), &["lighting"])
+            .unwrap()
+            .with_blend_mode(BlendMode::Screen, None)
+            .unwrap()
+            .with_blend_mode(BlendMode::Multiply, Some("image2"))
+            .unwrap()
+            .with_opacity(1.5, Some("image2"))
+            .unwrap();
+
+        for layer in layer_group.layers() {
+            if layer.name() == Some("image2") {
+                assert_eq!(layer.blend_mode, BlendMode::Multiply);
+                assert_eq!(layer.opacity, 1.0);
+            } else {
+                assert_eq!(layer.blend_mode, BlendMode::Screen);
+            }
+        }
+
+        assert!(matches!(
+            layer_group.with_opacity(0.5, Some("nope")),
+            Err(NftgenError::UnknownLayer(_))
+        ));
+    }
+
+    #[test]
+    fn quotas() {
+        let fixture = Fixture::create_layers_dirs("minimal.png", &["hat"]);
+        let layer_group = LayerGroup::new(fixture.path.join("hat"), &["hat"])
+            .unwrap()
+            .with_skip_probability(0.1);
+
+        // 10 NFTs skip the group, weights 1..=9 split the other 90
+        let quotas = layer_group.quotas(100).unwrap();
+        assert_eq!(quotas.iter().map(|(_, count)| count).sum::<usize>(), 100);
+        assert_eq!(quotas.last().unwrap(), &(PickedLayer::new("hat", None), 10));
+        let image3 = quotas.iter().find(|(pick, _)| pick.name() == "image3");
+        assert_eq!(image3.unwrap().1, 6);
+
+        // 95 NFTs leave 85.5 for the layers, and 85.5 / 45 * 3 for image3
+        for num in [95, 91] {
+            assert!(matches!(
+                layer_group.quotas(num),
+                Err(NftgenError::QuotaUnmet(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn picked_none_layer_has_no_layer() {
+        let none = Layer::new("hat/none#3.png", 3);
+        let cap = Layer::new("hat/cap#3.png", 3);
+
+        assert_eq!(PickedLayer::new("hat", Some(&none)).layer, None);
+        assert_eq!(PickedLayer::new("hat", Some(&none)).name(), "none");
+        assert_eq!(PickedLayer::new("hat", Some(&cap)).layer, Some(&cap));
+        assert_eq!(PickedLayer::new("hat", Some(&cap)).name(), "cap");
+    }
+
+    #[test]
+    fn pick_where_only_picks_allowed_layers() {
+        let fixture = Fixture::create_layers_dirs("minimal.png", &["background"]);
+        let layer_group =
+            LayerGroup::new(fixture.path.join("background"), &["background"]).unwrap();
+        let mut rng = token_rng(0, 0);
+
+        for _ in 0..20 {
+            let layer = layer_group
+                .pick_where(&mut rng, |l| l.name() == Some("image3"))
+                .unwrap();
+            assert_eq!(layer.name(), Some("image3"));
+        }
+        // image0 has a weight of zero
+        assert_eq!(
+            layer_group.pick_where(&mut rng, |l| l.name() == Some("image0")),
+            None
+        );
+    }
+
+    fn nested_layer_group(fixture: &Fixture) -> LayerGroup {
+        fixture.create_layer_files("clothing", &["cape#4.png"]);
+        fixture.create_layer_files("clothing/shirts#3", &["tee#1.png", "polo#3.png"]);
+        fixture.create_layer_files("clothing/jackets#1", &["parka.png"]);
+        LayerGroup::new(fixture.path.join("clothing"), &["clothing"]).unwrap()
+    }
+
+    #[test]
+    fn nested_probability_and_quotas() {
+        let fixture = Fixture::blank("minimal.png");
+        let layer_group = nested_layer_group(&fixture);
+        assert_eq!(layer_group.categories().len(), 2);
+
+        // cape, shirts and jackets split 8, shirts split their 3 between tee and polo
+        let probability = |name: &str| {
+            let layer = layer_group.layers().iter().find(|l| l.name() == Some(name));
+            layer_group.probability(layer.unwrap())
+        };
+        assert_eq!(probability("cape"), 0.5);
+        assert_eq!(probability("tee"), 3.0 / 32.0);
+        assert_eq!(probability("polo"), 9.0 / 32.0);
+        assert_eq!(probability("parka"), 1.0 / 8.0);
+
+        let quotas = layer_group.quotas(32).unwrap();
+        let quota = |name: &str| {
+            quotas
+                .iter()
+                .find(|(pick, _)| pick.name() == name)
+                .unwrap()
+                .1
+        };
+        assert_eq!(
+            [quota("cape"), quota("tee"), quota("polo"), quota("parka")],
+            [16, 3, 9, 4]
+        );
+    }
+
+    #[test]
+    fn nested_pick_where_picks_category_first() {
+        let fixture = Fixture::blank("minimal.png");
+        let layer_group = nested_layer_group(&fixture);
+        let mut rng = token_rng(0, 0);
+
+        let mut counts = std::collections::HashMap::new();
+        for _ in 0..800 {
+            let layer = layer_group.pick(&mut rng);
+            *counts.entry(layer.category_name()).or_insert(0) += 1;
+        }
+        // Expected 400 loose layers, 300 shirts and 100 jackets
+        assert!((350..450).contains(&counts[&None]));
+        assert!((250..350).contains(&counts[&Some("shirts".to_string())]));
+        assert!((60..140).contains(&counts[&Some("jackets".to_string())]));
+
+        for _ in 0..20 {
+            let layer = layer_group
+                .pick_where(&mut rng, |l| l.name() != Some("cape"))
+                .unwrap();
+            assert!(layer.category_name().is_some());
+        }
+        assert_eq!(
+            layer_group
+                .pick_where(&mut rng, |l| l.name() == Some("parka"))
+                .unwrap()
+                .qualified_name()
+                .unwrap(),
+            "jackets/parka"
+        );
+    }
+
+    fn tiered_layer_group(fixture: &Fixture) -> LayerGroup {
+        fixture.create_layer_files("hat", &["cap#3.png", "beanie.png"]);
+        fixture.create_layer_files("hat/Rare", &["crown.png"]);
+        std::fs::write(
+            fixture.path.join("hat/layers.toml"),
+            r#"
+                [tiers]
+                Common = 90
+                Rare = 10
+
+                [layers]
+                "cap#3.png" = { tier = "Common" }
+                "beanie.png" = { tier = "Common" }
+                "Rare/crown.png" = {}
+            "#,
+        )
+        .unwrap();
+        LayerGroup::new(fixture.path.join("hat"), &["hat"]).unwrap()
+    }
+
+    #[test]
+    fn tiered_probability_and_quotas() {
+        let fixture = Fixture::blank("minimal.png");
+        let layer_group = tiered_layer_group(&fixture);
+        assert_eq!(
+            layer_group.tiers(),
+            [Tier::new("Common", 90.0), Tier::new("Rare", 10.0)]
+        );
+
+        let probability = |name: &str| {
+            let layer = layer_group.layers().iter().find(|l| l.name() == Some(name));
+            layer_group.probability(layer.unwrap())
+        };
+        assert!((probability("cap") - 0.675).abs() < 1e-9);
+        assert!((probability("beanie") - 0.225).abs() < 1e-9);
+        assert!((probability("crown") - 0.1).abs() < 1e-9);
+
+        let quotas = layer_group.quotas(40).unwrap();
+        let quota = |name: &str| {
+            quotas
+                .iter()
+                .find(|(pick, _)| pick.name() == name)
+                .unwrap()
+                .1
+        };
+        assert_eq!([quota("cap"), quota("beanie"), quota("crown")], [27, 9, 4]);
+    }
+
+    #[test]
+    fn tiered_pick_where_picks_tier_first() {
+        let fixture = Fixture::blank("minimal.png");
+        let layer_group = tiered_layer_group(&fixture);
+        let mut rng = token_rng(0, 0);
+
+        let rare = (0..1000)
+            .filter(|_| layer_group.pick(&mut rng).tier.as_deref() == Some("Rare"))
+            .count();
+        assert!((60..140).contains(&rare));
+
+        for _ in 0..20 {
+            let layer = layer_group
+                .pick_where(&mut rng, |l| l.name() != Some("crown"))
+                .unwrap();
+            assert_eq!(layer.tier.as_deref(), Some("Common"));
+        }
+    }
+}
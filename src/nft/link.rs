@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+
+use crate::{Layer, LayerGroup, NftgenError, PickedLayer};
+
+/// Layer groups by the link they share, e.g. `Skin` for `Body`, `Hands` and `Ears`
+pub fn linked_groups(layer_groups: &[LayerGroup]) -> BTreeMap<&str, Vec<&LayerGroup>> {
+    let mut linked: BTreeMap<&str, Vec<&LayerGroup>> = BTreeMap::new();
+    for layer_group in layer_groups {
+        if let Some(link) = layer_group.link() {
+            linked.entry(link).or_default().push(layer_group);
+        }
+    }
+    linked
+}
+
+/// Checks that groups linked together have layers for the same variants, so that any
+/// variant picked in one of them can be picked in the others
+pub fn check_links(layer_groups: &[LayerGroup]) -> Result<(), NftgenError> {
+    for (link, groups) in linked_groups(layer_groups) {
+        if layer_groups.iter().any(|group| group.layer_type == link) {
+            return Err(NftgenError::InvalidLink(format!(
+                "{} is already the name of a layer type",
+                link
+            )));
+        }
+        let variants = groups[0].variants();
+        for group in &groups[1..] {
+            if let Some(variant) = variants.symmetric_difference(&group.variants()).next() {
+                return Err(NftgenError::InvalidLink(format!(
+                    "{} variant {} is missing in {} or {}",
+                    link, variant, groups[0].layer_type, group.layer_type
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The variant of every link among `picks`, once per link, as `(link, variant)`
+pub fn linked_variants<'a>(picks: &[PickedLayer<'a>]) -> Vec<(&'a str, &'a str)> {
+    let mut variants: Vec<(&str, &str)> = vec![];
+    for (link, variant) in picks.iter().filter_map(|pick| pick.linked_variant()) {
+        if !variants.iter().any(|&(other, _)| other == link) {
+            variants.push((link, variant));
+        }
+    }
+    variants
+}
+
+/// Returns true if all linked layers among `picks` have the same variant as the others of their link
+pub fn links_agree(picks: &[PickedLayer]) -> bool {
+    let variants = linked_variants(picks);
+    picks
+        .iter()
+        .filter_map(|pick| pick.linked_variant())
+        .all(|linked| variants.contains(&linked))
+}
+
+/// Returns true if `layer` can be picked next to layers of the `variants` picked so far
+pub(crate) fn allows_variant(variants: &[(&str, &str)], layer: &Layer) -> bool {
+    match (layer.link.as_deref(), layer.variant()) {
+        (Some(link), Some(variant)) => variants
+            .iter()
+            .all(|&(other_link, other)| other_link != link || other == variant),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nft::tests::fixture::Fixture;
+
+    fn linked_layer_groups(
+        fixture: &Fixture,
+        layers: &[(&str, &[&str])],
+    ) -> Result<Vec<LayerGroup>, NftgenError> {
+        let layer_types: Vec<_> = layers.iter().map(|(layer_type, _)| *layer_type).collect();
+        layers
+            .iter()
+            .map(|(layer_type, files)| {
+                let layer_path = fixture.create_layer_files(layer_type, files);
+                LayerGroup::new(&layer_path, &layer_types)?.with_link("Skin")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn check_links_works() {
+        let fixture = Fixture::blank("minimal.png");
+        let layer_groups = linked_layer_groups(
+            &fixture,
+            &[
+                ("body", &["slim@dark#1.png", "slim@light#1.png"]),
+                ("hands", &["open@dark#1.png", "fist@light#2.png"]),
+            ],
+        )
+        .unwrap();
+        assert!(check_links(&layer_groups).is_ok());
+    }
+
+    #[test]
+    fn check_links_rejects_missing_variants() {
+        let fixture = Fixture::blank("minimal.png");
+        let layer_groups = linked_layer_groups(
+            &fixture,
+            &[
+                ("body", &["slim@dark#1.png", "slim@light#1.png"]),
+                ("hands", &["open@dark#1.png"]),
+            ],
+        )
+        .unwrap();
+        assert!(matches!(
+            check_links(&layer_groups),
+            Err(NftgenError::InvalidLink(_))
+        ));
+    }
+
+    #[test]
+    fn with_link_rejects_layers_without_variant() {
+        let fixture = Fixture::blank("minimal.png");
+        let result = linked_layer_groups(
+            &fixture,
+            &[("body", &["slim@dark#1.png", "slim#1.png", "none#1"])],
+        );
+        assert!(matches!(result, Err(NftgenError::InvalidLink(_))));
+    }
+
+    #[test]
+    fn linked_variants_and_links_agree() {
+        let body = Layer::new("body/slim@dark.png", 1).with_link("Skin");
+        let hands = Layer::new("hands/open@dark.png", 1).with_link("Skin");
+        let light_hands = Layer::new("hands/open@light.png", 1).with_link("Skin");
+        let hat = Layer::new("hat/cap@dark.png", 1);
+
+        let picks = [
+            PickedLayer::new("body", Some(&body)),
+            PickedLayer::new("hands", Some(&hands)),
+            PickedLayer::new("hat", Some(&hat)),
+        ];
+        assert_eq!(linked_variants(&picks), vec![("Skin", "dark")]);
+        assert!(links_agree(&picks));
+
+        let picks = [
+            PickedLayer::new("body", Some(&body)),
+            PickedLayer::new("hands", Some(&light_hands)),
+        ];
+        assert!(!links_agree(&picks));
+        assert!(!allows_variant(&[("Skin", "dark")], &light_hands));
+        assert!(allows_variant(&[("Skin", "dark")], &hands));
+        assert!(allows_variant(&[("Skin", "dark")], &hat));
+    }
+}
@@ -0,0 +1,410 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::{Layer, LayerCategory, NftgenError};
+
+/// Names of the optional manifest file of the layers root or of a layer group directory
+pub const MANIFEST_FILE_NAMES: [&str; 2] = ["layers.toml", "layers.json"];
+
+/// Manifest of the layers root, e.g.
+/// order = ["Background", "Hat"]
+/// [groups.Hat.layers."cap.png"]
+/// weight = 2.5
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LayersManifest {
+    /// Layer types from back to front, used if `--layers-order` is not given
+    #[serde(default)]
+    pub order: Vec<String>,
+    /// Manifests of layer groups by layer type
+    #[serde(default)]
+    pub groups: BTreeMap<String, GroupManifest>,
+}
+
+/// Manifest of a single layer group, in its directory or in the manifest of the layers root
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupManifest {
+    /// Attribute name of the group in the metadata, instead of the directory name
+    pub trait_type: Option<String>,
+    /// Layers by file path relative to the group directory, e.g. `cap.png` or `Shirts/tee.png`
+    /// If any are given, every layer file of the group must be listed
+    #[serde(default)]
+    pub layers: BTreeMap<String, LayerManifest>,
+    /// Categories by path, e.g. `Shirts`
+    #[serde(default)]
+    pub categories: BTreeMap<String, CategoryManifest>,
+    /// Rarity tiers by name with their weights, e.g. `Common = 60`
+    /// If any are given, every layer must be in a tier, either through the `tier` of its entry
+    /// or by being in a directory named after the tier, e.g. `Rare/crown.png`
+    #[serde(default)]
+    pub tiers: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LayerManifest {
+    pub weight: Option<f64>,
+    /// Name of the layer in rules and metadata, instead of the file name
+    pub name: Option<String>,
+    /// Rarity tier of the layer, one of the `tiers` of its group
+    pub tier: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CategoryManifest {
+    pub weight: f64,
+}
+
+/// Reads `layers.toml` or `layers.json` in `dir`, or returns none if there is neither
+pub fn read_manifest<T: DeserializeOwned>(dir: &Path) -> Result<Option<T>, NftgenError> {
+    let paths: Vec<_> = MANIFEST_FILE_NAMES
+        .iter()
+        .map(|file_name| dir.join(file_name))
+        .filter(|path| path.is_file())
+        .collect();
+    let path = match paths.as_slice() {
+        [] => return Ok(None),
+        [path] => path,
+        _ => {
+            return Err(NftgenError::InvalidManifest(format!(
+                "{} has both a layers.toml and a layers.json",
+                dir.to_string_lossy()
+            )))
+        }
+    };
+    log::debug!("Loading layer manifest: {}", path.to_string_lossy());
+
+    let contents = std::fs::read_to_string(path)?;
+    let invalid = |e: &dyn std::fmt::Display| {
+        NftgenError::InvalidManifest(format!("{}: {}", path.to_string_lossy(), e))
+    };
+    let manifest = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => toml::from_str(&contents).map_err(|e| invalid(&e))?,
+        _ => serde_json::from_str(&contents).map_err(|e| invalid(&e))?,
+    };
+    Ok(Some(manifest))
+}
+
+impl GroupManifest {
+    /// Sets the weights and names of `layers` and `categories` of the group at `group_path`
+    /// Fails if the manifest names a file or category that does not exist, or leaves out a
+    /// layer file while listing others
+    pub(crate) fn apply(
+        &self,
+        group_path: &Path,
+        layers: &mut [Layer],
+        categories: &mut [LayerCategory],
+    ) -> Result<(), NftgenError> {
+        let group = group_path.to_string_lossy();
+        let relative_path = |layer: &Layer| -> Option<String> {
+            let path = layer.image_path.strip_prefix(group_path).ok()?;
+            let components: Option<Vec<_>> = path.iter().map(|c| c.to_str()).collect();
+            Some(components?.join("/"))
+        };
+
+        for (file, entry) in &self.layers {
+            if let Some(weight) = entry.weight {
+                check_weight(weight, &format!("{}/{}", group, file))?;
+            }
+            let mut found = false;
+            for layer in layers
+                .iter_mut()
+                .filter(|layer| layer.palette.is_none())
+                .filter(|layer| relative_path(layer).as_deref() == Some(file.as_str()))
+            {
+                found = true;
+                if let Some(weight) = entry.weight {
+                    layer.weight = weight;
+                }
+                if let Some(name) = &entry.name {
+                    layer.display_name = Some(name.clone());
+                }
+                if let Some(tier) = &entry.tier {
+                    layer.tier = Some(tier.clone());
+                }
+            }
+            if !found {
+                return Err(NftgenError::InvalidManifest(format!(
+                    "{} has no layer file {}",
+                    group, file
+                )));
+            }
+        }
+
+        if !self.layers.is_empty() {
+            let missing = layers
+                .iter()
+                .filter(|layer| layer.palette.is_none())
+                .filter_map(relative_path)
+                .find(|file| !self.layers.contains_key(file));
+            if let Some(file) = missing {
+                return Err(NftgenError::InvalidManifest(format!(
+                    "Layer file {}/{} is missing from the manifest",
+                    group, file
+                )));
+            }
+        }
+
+        for (path, entry) in &self.categories {
+            check_weight(entry.weight, &format!("{}/{}", group, path))?;
+            let category = categories
+                .iter_mut()
+                .find(|category| category.path.join("/") == *path)
+                .ok_or_else(|| {
+                    NftgenError::InvalidManifest(format!("{} has no category {}", group, path))
+                })?;
+            category.weight = entry.weight;
+        }
+        self.apply_tiers(&group, layers)
+    }
+
+    /// Puts every layer in its tier, checking that the tiers are known and have layers
+    fn apply_tiers(&self, group: &str, layers: &mut [Layer]) -> Result<(), NftgenError> {
+        for (tier, weight) in &self.tiers {
+            check_weight(*weight, &format!("{} tier {}", group, tier))?;
+        }
+        if let Some(tier) = self
+            .layers
+            .values()
+            .filter_map(|entry| entry.tier.as_ref())
+            .find(|tier| !self.tiers.contains_key(*tier))
+        {
+            return Err(NftgenError::InvalidManifest(format!(
+                "{} has no tier {}",
+                group, tier
+            )));
+        }
+        if self.tiers.is_empty() {
+            return Ok(());
+        }
+
+        for layer in layers.iter_mut() {
+            let directory_tier = layer
+                .category
+                .first()
+                .filter(|category| self.tiers.contains_key(*category));
+            layer.tier = match (layer.tier.take(), directory_tier) {
+                (Some(tier), Some(other)) if tier != *other => {
+                    return Err(NftgenError::InvalidManifest(format!(
+                        "{} is in the directory of tier {} but set to tier {}",
+                        layer.image_path.to_string_lossy(),
+                        other,
+                        tier
+                    )))
+                }
+                (Some(tier), _) => Some(tier),
+                (None, Some(tier)) => Some(tier.clone()),
+                (None, None) => {
+                    return Err(NftgenError::InvalidManifest(format!(
+                        "{} has no tier",
+                        layer.image_path.to_string_lossy()
+                    )))
+                }
+            };
+        }
+        if let Some(tier) = self.tiers.keys().find(|tier| {
+            !layers
+                .iter()
+                .any(|layer| layer.tier.as_ref() == Some(*tier))
+        }) {
+            return Err(NftgenError::InvalidManifest(format!(
+                "{} has no layers of tier {}",
+                group, tier
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn check_weight(weight: f64, key: &str) -> Result<(), NftgenError> {
+    if weight.is_finite() && weight >= 0.0 {
+        Ok(())
+    } else {
+        Err(NftgenError::InvalidManifest(format!(
+            "Invalid weight {} of {}",
+            weight, key
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nft::tests::fixture::Fixture;
+    use crate::{get_layer_groups, LayerGroup};
+
+    fn layer<'a>(layer_group: &'a LayerGroup, file_name: &str) -> &'a Layer {
+        layer_group
+            .layers()
+            .iter()
+            .find(|layer| layer.file_name() == Some(file_name))
+            .unwrap()
+    }
+
+    #[test]
+    fn group_manifest_sets_weights_and_names() {
+        let fixture = Fixture::blank("minimal.png");
+        fixture.create_layer_files("hat", &["cap#5.png", "beanie.png"]);
+        fixture.create_layer_files("hat/winter", &["earmuffs.png"]);
+        std::fs::write(
+            fixture.path.join("hat/layers.toml"),
+            r#"
+                trait_type = "Headwear"
+
+                [layers."cap#5.png"]
+                weight = 2.5
+                name = "Baseball Cap"
+
+                [layers."beanie.png"]
+
+                [layers."winter/earmuffs.png"]
+                weight = 0.5
+
+                [categories.winter]
+                weight = 0.25
+            "#,
+        )
+        .unwrap();
+
+        let layer_group = LayerGroup::new(fixture.path.join("hat"), &["hat"]).unwrap();
+
+        assert_eq!(layer_group.trait_type(), "Headwear");
+        let cap = layer(&layer_group, "cap");
+        assert_eq!(cap.weight, 2.5);
+        assert_eq!(cap.name(), Some("Baseball Cap"));
+        assert_eq!(layer(&layer_group, "beanie").weight, 1.0);
+        assert_eq!(layer(&layer_group, "earmuffs").weight, 0.5);
+        assert_eq!(layer_group.categories()[0].weight, 0.25);
+    }
+
+    #[test]
+    fn group_manifest_rejects_unknown_and_missing_files() {
+        let fixture = Fixture::blank("minimal.png");
+        fixture.create_layer_files("hat", &["cap.png", "beanie.png"]);
+
+        let manifest = fixture.path.join("hat/layers.json");
+        std::fs::write(
+            &manifest,
+            r#"{"layers": {"cap.png": {}, "fedora.png": {}}}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            LayerGroup::new(fixture.path.join("hat"), &["hat"]),
+            Err(NftgenError::InvalidManifest(message)) if message.contains("fedora.png")
+        ));
+
+        std::fs::write(&manifest, r#"{"layers": {"cap.png": {"weight": 2}}}"#).unwrap();
+        assert!(matches!(
+            LayerGroup::new(fixture.path.join("hat"), &["hat"]),
+            Err(NftgenError::InvalidManifest(message)) if message.contains("beanie.png")
+        ));
+
+        std::fs::write(&manifest, r#"{"layers": {"cap.png": {"weight": -1}}}"#).unwrap();
+        assert!(matches!(
+            LayerGroup::new(fixture.path.join("hat"), &["hat"]),
+            Err(NftgenError::InvalidManifest(_))
+        ));
+
+        std::fs::write(&manifest, r#"{"layers": {"cap.png": {"wieght": 2}}}"#).unwrap();
+        assert!(matches!(
+            LayerGroup::new(fixture.path.join("hat"), &["hat"]),
+            Err(NftgenError::InvalidManifest(_))
+        ));
+    }
+
+    #[test]
+    fn root_manifest_sets_order_and_groups() {
+        let fixture = Fixture::blank("minimal.png");
+        fixture.create_layer_files("background", &["red.png"]);
+        fixture.create_layer_files("hat", &["cap.png"]);
+        std::fs::write(
+            fixture.path.join("layers.toml"),
+            r#"
+                order = ["background", "hat"]
+
+                [groups.hat]
+                trait_type = "Headwear"
+
+                [groups.hat.layers."cap.png"]
+                weight = 3
+            "#,
+        )
+        .unwrap();
+
+        let mut layer_groups = get_layer_groups(&fixture.path, &[] as &[&str]).unwrap();
+        layer_groups.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        assert_eq!(layer_groups[0].layer_type, "background");
+        assert_eq!(layer_groups[1].trait_type(), "Headwear");
+        assert_eq!(layer(&layer_groups[1], "cap").weight, 3.0);
+    }
+
+    #[test]
+    fn root_manifest_rejects_unknown_groups() {
+        let fixture = Fixture::blank("minimal.png");
+        fixture.create_layer_files("hat", &["cap.png"]);
+        std::fs::write(
+            fixture.path.join("layers.json"),
+            r#"{"groups": {"hats": {"trait_type": "Headwear"}}}"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            get_layer_groups(&fixture.path, &["hat"]),
+            Err(NftgenError::InvalidManifest(_))
+        ));
+    }
+
+    #[test]
+    fn group_manifest_assigns_tiers() {
+        let fixture = Fixture::blank("minimal.png");
+        fixture.create_layer_files("hat", &["cap.png"]);
+        fixture.create_layer_files("hat/Legendary", &["crown.png"]);
+        let manifest = fixture.path.join("hat/layers.json");
+        let load = |contents: &str| {
+            std::fs::write(&manifest, contents).unwrap();
+            LayerGroup::new(fixture.path.join("hat"), &["hat"])
+        };
+
+        let layer_group = load(
+            r#"{
+                "tiers": {"Common": 9, "Legendary": 1},
+                "layers": {"cap.png": {"tier": "Common"}, "Legendary/crown.png": {}}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(layer(&layer_group, "cap").tier.as_deref(), Some("Common"));
+        assert_eq!(
+            layer(&layer_group, "crown").tier.as_deref(),
+            Some("Legendary")
+        );
+
+        assert!(matches!(
+            load(r#"{"tiers": {"Common": 9, "Legendary": 1}}"#),
+            Err(NftgenError::InvalidManifest(message)) if message.contains("cap.png has no tier")
+        ));
+        assert!(matches!(
+            load(
+                r#"{
+                    "tiers": {"Common": 9, "Legendary": 1},
+                    "layers": {"cap.png": {"tier": "Rare"}, "Legendary/crown.png": {}}
+                }"#
+            ),
+            Err(NftgenError::InvalidManifest(message)) if message.contains("no tier Rare")
+        ));
+        assert!(matches!(
+            load(
+                r#"{
+                    "tiers": {"Common": 9, "Rare": 3, "Legendary": 1},
+                    "layers": {"cap.png": {"tier": "Common"}, "Legendary/crown.png": {}}
+                }"#
+            ),
+            Err(NftgenError::InvalidManifest(message)) if message.contains("no layers of tier Rare")
+        ));
+    }
+}
@@ -1,6 +1,6 @@
-use std::{fmt::Display, fs::read_dir, path::Path};
+use std::{collections::BTreeMap, fs::read_dir, path::Path};
 
-use crate::{Layer, NftgenError};
+use crate::{linked_variants, Dna, NftgenError, PickedLayer};
 use once_cell::sync::Lazy;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
@@ -9,11 +9,17 @@ static IPFS_URI_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"ipfs://.*/").unwr
 
 /// The high level metadata representation of the NFT collection.
 /// - ```description```: Description of the NFT collection.
+/// - ```dna```: Hash of the NFT's traits, unique within the collection.
+/// - ```animation_url```: Animated version of ```image```, only set for NFTs with animated layers.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Metadata<'a> {
     pub description: &'a str,
     pub name: String,
     pub image: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub animation_url: Option<String>,
+    #[serde(default)]
+    pub dna: String,
     pub attributes: Vec<Attribute>,
 }
 
@@ -22,12 +28,15 @@ impl<'a> Metadata<'a> {
         description: &'a str,
         name: String,
         image: String,
+        dna: String,
         attributes: Vec<Attribute>,
-    ) -> Metadata {
+    ) -> Metadata<'a> {
         Metadata {
             description,
             name,
             image,
+            animation_url: None,
+            dna,
             attributes,
         }
     }
@@ -48,35 +57,108 @@ impl Attribute {
     }
 }
 
+/// URI of the file `<id>.<extension>`, until `MetadataWriter` sets the IPFS CID of its directory
+pub fn placeholder_uri(id: u32, extension: &str) -> String {
+    format!("ipfs://placeholder/{}.{}", id, extension)
+}
+
+/// Which attributes describe the layers of nested layer groups, e.g. `Clothing/Shirts/red.png`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum NestedAttributes {
+    /// The layer name as the value of the layer type, e.g. `Clothing: red`
+    Item,
+    /// The category as the value of the layer type, e.g. `Clothing: Shirts`
+    Category,
+    /// The layer name, followed by the category as `<layer type> Category`
+    Both,
+}
+
+/// How picked layers are turned into attributes
+#[derive(Debug, Clone, Copy)]
+pub struct AttributeOptions<'a> {
+    /// Attribute value for layer groups without a layer. Those are left out if none
+    pub none_value: Option<&'a str>,
+    pub nested: NestedAttributes,
+    /// Adds the rarity tier of layers as `<layer type> Tier`
+    pub tiers: bool,
+}
+
+impl Default for AttributeOptions<'_> {
+    fn default() -> Self {
+        AttributeOptions {
+            none_value: None,
+            nested: NestedAttributes::Item,
+            tiers: false,
+        }
+    }
+}
+
 pub struct MetadataBuilder {}
 
 impl MetadataBuilder {
-    pub fn build<'a, T: AsRef<str> + Display>(
+    /// Builds the metadata of an NFT from its picked layers
+    /// Layer groups without a layer are left out of the attributes,
+    /// unless `options.none_value` is given, which is then used as the attribute value
+    /// With `options.tiers`, the tier of a layer follows its attribute as `<layer type> Tier`
+    /// The variant of linked layers follows as one attribute per link
+    /// `image_extension` is the file extension of the NFT image, e.g. `png`
+    pub fn build<'a>(
         id: u32,
         description: &'a str,
         collection_name: &str,
-        ordered_layers: &[T],
-        layers: &[&Layer],
+        picks: &[PickedLayer],
+        dna: &Dna,
+        options: &AttributeOptions,
+        image_extension: &str,
     ) -> Metadata<'a> {
-        let attributes: Vec<Attribute> = ordered_layers
+        let attributes: Vec<Attribute> = picks
             .iter()
-            .zip(
-                layers
-                    .iter()
-                    .map(|&l| l.name().expect("Layer name should be valid unicode")),
+            .flat_map(|pick| MetadataBuilder::layer_attributes(pick, options))
+            .chain(
+                linked_variants(picks)
+                    .into_iter()
+                    .map(|(link, variant)| Attribute::new(link.to_string(), variant.to_string())),
             )
-            .map(|(layer_type, layer_name)| {
-                Attribute::new(layer_type.to_string(), layer_name.to_string())
-            })
             .collect();
 
         Metadata::new(
             description,
             format!("{} #{}", collection_name, id),
-            format!("ipfs://placeholder/{}.png", id),
+            placeholder_uri(id, image_extension),
+            dna.to_string(),
             attributes,
         )
     }
+
+    fn layer_attributes(pick: &PickedLayer, options: &AttributeOptions) -> Vec<Attribute> {
+        let attribute = |trait_type: &str, value: &str| {
+            Attribute::new(trait_type.to_string(), value.to_string())
+        };
+        let layer = match pick.layer {
+            Some(layer) => layer,
+            None => {
+                return options
+                    .none_value
+                    .map(|value| attribute(pick.trait_type, value))
+                    .into_iter()
+                    .collect()
+            }
+        };
+        let mut attributes = match (layer.category_name(), options.nested) {
+            (Some(category), NestedAttributes::Category) => {
+                vec![attribute(pick.trait_type, &category)]
+            }
+            (Some(category), NestedAttributes::Both) => vec![
+                attribute(pick.trait_type, pick.name()),
+                attribute(&format!("{} Category", pick.trait_type), &category),
+            ],
+            _ => vec![attribute(pick.trait_type, pick.name())],
+        };
+        if let (true, Some(tier)) = (options.tiers, &layer.tier) {
+            attributes.push(attribute(&format!("{} Tier", pick.trait_type), tier));
+        }
+        attributes
+    }
 }
 
 pub struct MetadataWriter<'a> {
@@ -111,7 +193,52 @@ impl<'a> MetadataWriter<'a> {
 
     pub fn update_base_uri_for_all_images(&self, base_uri: &str) -> Result<(), NftgenError> {
         log::info!("Updating base_uri for all images with: {}", base_uri);
+        self.update_all(|metadata| {
+            metadata.image = replace_base_uri(&metadata.image, base_uri);
+        })
+    }
+
+    pub fn update_base_uri_for_all_animations(&self, base_uri: &str) -> Result<(), NftgenError> {
+        log::info!("Updating base_uri for all animations with: {}", base_uri);
+        self.update_all(|metadata| {
+            if let Some(animation_url) = &metadata.animation_url {
+                metadata.animation_url = Some(replace_base_uri(animation_url, base_uri));
+            }
+        })
+    }
 
+    /// Reads the DNA of the NFTs saved so far by their token id
+    /// Files not named after a token id are skipped
+    pub fn saved_dna(&self) -> Result<BTreeMap<u32, Dna>, NftgenError> {
+        #[derive(Deserialize)]
+        struct SavedMetadata {
+            dna: Dna,
+        }
+
+        let mut saved = BTreeMap::new();
+        for entry in read_dir(self.path)? {
+            let metadata_file_path = entry?.path();
+            let id = match metadata_file_path
+                .file_name()
+                .and_then(|file_name| file_name.to_str())
+                .and_then(|file_name| file_name.parse().ok())
+            {
+                Some(id) => id,
+                None => continue,
+            };
+            let metadata_json = std::fs::read_to_string(&metadata_file_path)?;
+            let metadata: SavedMetadata = serde_json::from_str(&metadata_json).map_err(|err| {
+                NftgenError::InvalidSavedMetadata {
+                    path: metadata_file_path.clone(),
+                    reason: err.to_string(),
+                }
+            })?;
+            saved.insert(id, metadata.dna);
+        }
+        Ok(saved)
+    }
+
+    fn update_all(&self, update: impl Fn(&mut Metadata)) -> Result<(), NftgenError> {
         let entries = read_dir(self.path)?
             .map(|entry| entry.unwrap())
             .map(|entry| entry.path());
@@ -119,9 +246,7 @@ impl<'a> MetadataWriter<'a> {
         for metadata_file_path in entries {
             let metadata_json = std::fs::read_to_string(&metadata_file_path)?;
             let mut metadata: Metadata = serde_json::from_str(&metadata_json)?;
-            let new_image_uri =
-                IPFS_URI_REGEX.replace_all(&metadata.image, &format!("ipfs://{}/", base_uri));
-            metadata.image = new_image_uri.to_string();
+            update(&mut metadata);
             self.write(
                 &metadata,
                 metadata_file_path.file_name().unwrap().to_str().unwrap(),
@@ -131,12 +256,19 @@ impl<'a> MetadataWriter<'a> {
     }
 }
 
+fn replace_base_uri(uri: &str, base_uri: &str) -> String {
+    IPFS_URI_REGEX
+        .replace_all(uri, &format!("ipfs://{}/", base_uri))
+        .to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     mod metadata_builder {
         use super::*;
+        use crate::Layer;
 
         #[test]
         fn build() {
@@ -144,17 +276,24 @@ mod tests {
                 3,
                 "Great nft collection",
                 "JustGreat",
-                &["background", "face", "eyes"],
                 &[
-                    &Layer::new("red#2.png", 5),
-                    &Layer::new("smile#5.png", 5),
-                    &Layer::new("squint#5.png", 5),
+                    PickedLayer::new("background", Some(&Layer::new("red#2.png", 5))),
+                    PickedLayer::new("face", Some(&Layer::new("smile#5.png", 5))),
+                    PickedLayer::new("eyes", Some(&Layer::new("squint#5.png", 5))),
                 ],
+                &Dna::new([("background", "red")]),
+                &AttributeOptions::default(),
+                "png",
             );
 
             assert_eq!(metadata.name, "JustGreat #3");
             assert_eq!(metadata.description, "Great nft collection");
             assert_eq!(metadata.image, "ipfs://placeholder/3.png");
+            assert_eq!(metadata.animation_url, None);
+            assert!(!serde_json::to_string(&metadata)
+                .unwrap()
+                .contains("animation_url"));
+            assert_eq!(metadata.dna, Dna::new([("background", "red")]).to_string());
             assert_eq!(
                 metadata.attributes,
                 vec![
@@ -173,6 +312,184 @@ mod tests {
                 ]
             );
         }
+
+        #[test]
+        fn build_uses_image_extension() {
+            let metadata = MetadataBuilder::build(
+                3,
+                "Great nft collection",
+                "JustGreat",
+                &[PickedLayer::new(
+                    "background",
+                    Some(&Layer::new("red#2.png", 5)),
+                )],
+                &Dna::new([("background", "red")]),
+                &AttributeOptions::default(),
+                "webp",
+            );
+            assert_eq!(metadata.image, "ipfs://placeholder/3.webp");
+        }
+
+        #[test]
+        fn build_records_linked_variant_once() {
+            let body = Layer::new("slim@dark#2.png", 2).with_link("Skin");
+            let hands = Layer::new("open@dark#2.png", 2).with_link("Skin");
+            let metadata = MetadataBuilder::build(
+                3,
+                "Great nft collection",
+                "JustGreat",
+                &[
+                    PickedLayer::new("body", Some(&body)),
+                    PickedLayer::new("hands", Some(&hands)),
+                ],
+                &Dna::new([("body", "slim")]),
+                &AttributeOptions::default(),
+                "png",
+            );
+            assert_eq!(
+                metadata.attributes,
+                vec![
+                    Attribute::new("body".to_string(), "slim".to_string()),
+                    Attribute::new("hands".to_string(), "open".to_string()),
+                    Attribute::new("Skin".to_string(), "dark".to_string()),
+                ]
+            );
+        }
+    }
+
+    mod metadata_builder_nested {
+        use super::*;
+        use crate::Layer;
+
+        fn build(nested: NestedAttributes) -> Vec<Attribute> {
+            let red = Layer::new("red#2.png", 5);
+            let mut shirt = Layer::new("tee#5.png", 5);
+            shirt.category = vec!["Shirts".to_string()];
+            MetadataBuilder::build(
+                1,
+                "Great nft collection",
+                "JustGreat",
+                &[
+                    PickedLayer::new("background", Some(&red)),
+                    PickedLayer::new("clothing", Some(&shirt)),
+                ],
+                &Dna::new([("background", "red")]),
+                &AttributeOptions {
+                    nested,
+                    ..AttributeOptions::default()
+                },
+                "png",
+            )
+            .attributes
+        }
+
+        #[test]
+        fn reports_item_or_category() {
+            let background = Attribute::new("background".to_string(), "red".to_string());
+            assert_eq!(
+                build(NestedAttributes::Item),
+                vec![
+                    background.clone(),
+                    Attribute::new("clothing".to_string(), "tee".to_string()),
+                ]
+            );
+            assert_eq!(
+                build(NestedAttributes::Category),
+                vec![
+                    background.clone(),
+                    Attribute::new("clothing".to_string(), "Shirts".to_string()),
+                ]
+            );
+            assert_eq!(
+                build(NestedAttributes::Both),
+                vec![
+                    background,
+                    Attribute::new("clothing".to_string(), "tee".to_string()),
+                    Attribute::new("clothing Category".to_string(), "Shirts".to_string()),
+                ]
+            );
+        }
+    }
+
+    mod metadata_builder_tiers {
+        use super::*;
+        use crate::Layer;
+
+        #[test]
+        fn adds_tier_attributes() {
+            let mut crown = Layer::new("crown.png", 1);
+            crown.tier = Some("Legendary".to_string());
+            let build = |tiers: bool| {
+                MetadataBuilder::build(
+                    1,
+                    "Great nft collection",
+                    "JustGreat",
+                    &[PickedLayer::new("hat", Some(&crown))],
+                    &Dna::new([("hat", "crown")]),
+                    &AttributeOptions {
+                        tiers,
+                        ..AttributeOptions::default()
+                    },
+                    "png",
+                )
+                .attributes
+            };
+            let hat = Attribute::new("hat".to_string(), "crown".to_string());
+            assert_eq!(build(false), vec![hat.clone()]);
+            assert_eq!(
+                build(true),
+                vec![
+                    hat,
+                    Attribute::new("hat Tier".to_string(), "Legendary".to_string())
+                ]
+            );
+        }
+    }
+
+    mod metadata_builder_none {
+        use super::*;
+        use crate::Layer;
+
+        fn build(none_value: Option<&str>) -> Metadata<'static> {
+            let red = Layer::new("red#2.png", 5);
+            let none = Layer::new("none#5.png", 5);
+            MetadataBuilder::build(
+                1,
+                "Great nft collection",
+                "JustGreat",
+                &[
+                    PickedLayer::new("background", Some(&red)),
+                    PickedLayer::new("hat", Some(&none)),
+                    PickedLayer::new("glasses", None),
+                ],
+                &Dna::new([("background", "red")]),
+                &AttributeOptions {
+                    none_value,
+                    ..AttributeOptions::default()
+                },
+                "png",
+            )
+        }
+
+        #[test]
+        fn leaves_out_missing_layers() {
+            assert_eq!(
+                build(None).attributes,
+                vec![Attribute::new("background".to_string(), "red".to_string())]
+            );
+        }
+
+        #[test]
+        fn uses_none_value_for_missing_layers() {
+            assert_eq!(
+                build(Some("None")).attributes,
+                vec![
+                    Attribute::new("background".to_string(), "red".to_string()),
+                    Attribute::new("hat".to_string(), "None".to_string()),
+                    Attribute::new("glasses".to_string(), "None".to_string()),
+                ]
+            );
+        }
     }
 
     mod metadata_writer {
@@ -190,6 +507,7 @@ mod tests {
                 "Some description",
                 "Lame collection #5".to_string(),
                 "ipfs://placeholder/5.png".to_string(),
+                "3b2f".to_string(),
                 vec![
                     Attribute {
                         trait_type: "background".to_string(),
@@ -217,6 +535,69 @@ mod tests {
             let metadata_bytes = &std::fs::read(&metadata_path).unwrap();
             let updated_metadata: Metadata = serde_json::from_slice(metadata_bytes).unwrap();
             assert_eq!(updated_metadata.image, "ipfs://bussin-ipfs-cid/5.png");
+            assert_eq!(updated_metadata.animation_url, None);
+        }
+
+        #[test]
+        fn write_and_update_base_uri_for_animations() {
+            let fixture = Fixture::blank("");
+            let metadata_path = fixture.path.join("5.json");
+            let writer = MetadataWriter::new(&fixture.path);
+
+            let mut metadata = Metadata::new(
+                "Some description",
+                "Lame collection #5".to_string(),
+                placeholder_uri(5, "png"),
+                "3b2f".to_string(),
+                vec![],
+            );
+            metadata.animation_url = Some(placeholder_uri(5, "gif"));
+            writer.write(&metadata, "5.json").unwrap();
+
+            writer.update_base_uri_for_all_images("images-cid").unwrap();
+            writer
+                .update_base_uri_for_all_animations("animations-cid")
+                .unwrap();
+            let metadata_bytes = &std::fs::read(&metadata_path).unwrap();
+            let updated_metadata: Metadata = serde_json::from_slice(metadata_bytes).unwrap();
+            assert_eq!(updated_metadata.image, "ipfs://images-cid/5.png");
+            assert_eq!(
+                updated_metadata.animation_url.as_deref(),
+                Some("ipfs://animations-cid/5.gif")
+            );
+        }
+
+        #[test]
+        fn saved_dna_reads_the_dna_by_token_id() {
+            let fixture = Fixture::blank("");
+            let writer = MetadataWriter::new(&fixture.path);
+            for (id, dna) in [(3, "3b2f"), (12, "9a1c")] {
+                let metadata = Metadata::new(
+                    "Some description",
+                    format!("Lame collection #{}", id),
+                    placeholder_uri(id, "png"),
+                    dna.to_string(),
+                    vec![],
+                );
+                writer.write(&metadata, id.to_string()).unwrap();
+            }
+            std::fs::write(fixture.path.join(".DS_Store"), "").unwrap();
+
+            let saved = writer.saved_dna().unwrap();
+            assert_eq!(saved.len(), 2);
+            assert_eq!(saved[&12], serde_json::from_str(r#""9a1c""#).unwrap());
+        }
+
+        #[test]
+        fn saved_dna_names_metadata_without_dna() {
+            let fixture = Fixture::blank("");
+            let writer = MetadataWriter::new(&fixture.path);
+            std::fs::write(fixture.path.join("7"), r#"{"name": "Lame collection #7"}"#).unwrap();
+
+            assert!(matches!(
+                writer.saved_dna(),
+                Err(NftgenError::InvalidSavedMetadata { path, .. }) if path == fixture.path.join("7")
+            ));
         }
     }
 }
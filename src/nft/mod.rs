@@ -1,22 +1,64 @@
 // use rayon::prelude::*;
 use std::{
+    ffi::OsStr,
     fs::DirEntry,
     path::{Path, PathBuf},
 };
 
+mod animation;
+mod blend;
+mod dna;
+mod edition;
+mod format;
 mod image;
 mod image_builder;
+mod image_cache;
 mod layer;
 mod layer_group;
+mod link;
+mod manifest;
 mod metadata;
+mod one_of_one;
+mod palette;
+mod plan;
+mod plan_file;
+mod png_encoder;
+mod position;
+mod quota;
+mod resize;
+mod rng;
+mod rules;
+mod spec;
+mod tier;
 
+use crate::nft::palette::parse_palette_layers;
 use crate::NftgenError;
 
 pub use self::image::*;
+pub use animation::*;
+pub use blend::*;
+pub use dna::*;
+pub use edition::*;
+pub use format::*;
 pub use image_builder::*;
+pub use image_cache::*;
 pub use layer::*;
 pub use layer_group::*;
+pub use link::*;
+pub use manifest::*;
 pub use metadata::*;
+pub use one_of_one::*;
+pub use palette::*;
+pub use plan::*;
+pub use plan_file::*;
+pub use png_encoder::{PngCompression, PngFilter, PngOptions};
+pub use position::*;
+pub use quota::*;
+pub use resize::ResizeFilter;
+pub use rng::*;
+pub use rules::*;
+pub use spec::*;
+pub use tier::*;
 
 pub fn get_layer_groups<T: AsRef<str>, P: AsRef<Path>>(
     layer_dir_root: P,
@@ -28,19 +70,85 @@ pub fn get_layer_groups<T: AsRef<str>, P: AsRef<Path>>(
         log::info!("Found directory of layers: {}", dir.to_string_lossy());
     }
 
-    layer_dirs
+    let manifest: LayersManifest = read_manifest(layer_dir_root)?.unwrap_or_default();
+    let layers_order: Vec<&str> = if layers_order.is_empty() {
+        manifest.order.iter().map(String::as_str).collect()
+    } else {
+        layers_order.iter().map(AsRef::as_ref).collect()
+    };
+    if let Some(layer_type) = manifest.groups.keys().find(|layer_type| {
+        !layer_dirs
+            .iter()
+            .any(|layer_dir| layer_dir.file_name() == Some(OsStr::new(layer_type)))
+    }) {
+        return Err(NftgenError::InvalidManifest(format!(
+            "{} has no layer directory {}",
+            layer_dir_root.to_string_lossy(),
+            layer_type
+        )));
+    }
+
+    let layer_groups = layer_dirs
         .iter()
-        .map(|layer_dir| LayerGroup::new(layer_dir.as_path(), layers_order))
-        .collect()
+        .map(|layer_dir| {
+            let root_manifest = layer_dir
+                .file_name()
+                .and_then(|name| manifest.groups.get(name.to_string_lossy().as_ref()));
+            match (root_manifest, read_manifest(layer_dir)?) {
+                (Some(_), Some(_)) => Err(NftgenError::InvalidManifest(format!(
+                    "{} is set up both by its own manifest and by the manifest of {}",
+                    layer_dir.to_string_lossy(),
+                    layer_dir_root.to_string_lossy()
+                ))),
+                (Some(group_manifest), None) => {
+                    LayerGroup::from_manifest(layer_dir, &layers_order, Some(group_manifest))
+                }
+                (None, group_manifest) => {
+                    LayerGroup::from_manifest(layer_dir, &layers_order, group_manifest.as_ref())
+                }
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for layer_group in &layer_groups {
+        for part in layer_group.layers().iter().flat_map(|layer| &layer.parts) {
+            if !layer_groups
+                .iter()
+                .any(|other| other.layer_type == part.behind)
+            {
+                return Err(NftgenError::UnknownLayer(format!(
+                    "{} in {}",
+                    part.behind,
+                    part.image_path.to_string_lossy()
+                )));
+            }
+        }
+    }
+    Ok(layer_groups)
 }
 
-/// Parses layer files within a directory into Layer structs
+/// Parses layer files within a directory into Layer structs, sorted by file name so that
+/// picks only depend on the seed and not on the order of the directory entries
+/// A sprite with palettes is only a layer through its palette variants
+/// Parts like `long~Head.png` are added to the layer of the same name instead of being layers
 fn parse_layers_from_path<P: AsRef<Path>>(path: P) -> Result<Vec<Layer>, NftgenError> {
     let path = path.as_ref();
-    path.read_dir()?
-        .collect::<Result<Vec<DirEntry>, _>>()?
+    let (sprite_path, palette_layers) = match parse_palette_layers(path)? {
+        Some((sprite_path, palette_layers)) => (Some(sprite_path), palette_layers),
+        None => (None, vec![]),
+    };
+    let mut entries = path.read_dir()?.collect::<Result<Vec<DirEntry>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    let (part_files, layer_files): (Vec<DirEntry>, Vec<DirEntry>) = entries
+        .into_iter()
+        .filter(|l| {
+            let extension = l.path().extension().unwrap_or_default().to_owned();
+            extension == "png" || extension == "gif" || Layer::new(l.path(), 0).is_none()
+        })
+        .filter(|l| Some(l.path()) != sprite_path)
+        .partition(|l| LayerPart::parse(l.path()).is_some());
+    let mut layers = layer_files
         .into_iter()
-        .filter(|l| l.path().extension().unwrap_or_default() == "png")
         .map(|image_file| {
             log::debug!(
                 "Loading image from file: {}",
@@ -48,18 +156,80 @@ fn parse_layers_from_path<P: AsRef<Path>>(path: P) -> Result<Vec<Layer>, NftgenE
             );
             Layer::try_from(image_file)
         })
-        .collect::<Result<Vec<_>, _>>()
+        .collect::<Result<Vec<_>, _>>()?;
+    layers.extend(palette_layers);
+
+    for part_file in part_files {
+        let (name, part) =
+            LayerPart::parse(part_file.path()).expect("Part files should be parsed as parts");
+        let mut owners = layers
+            .iter_mut()
+            .filter(|layer| layer.file_name() == Some(name.as_str()))
+            .peekable();
+        if owners.peek().is_none() {
+            return Err(NftgenError::OrphanLayerPart(part.image_path));
+        }
+        for layer in owners {
+            log::debug!(
+                "Adding part to layer: {}",
+                part.image_path.to_string_lossy()
+            );
+            layer.parts.push(part.clone());
+        }
+    }
+    Ok(layers)
+}
+
+/// Parses the layers of a layer group directory and of its nested category directories,
+/// e.g. `Clothing/Shirts#3/red#2.png`, returning the layers and the categories
+fn parse_layer_tree<P: AsRef<Path>>(
+    path: P,
+) -> Result<(Vec<Layer>, Vec<LayerCategory>), NftgenError> {
+    let path = path.as_ref();
+    let mut layers = parse_layers_from_path(path)?;
+    let mut categories = vec![];
+
+    for category_dir in get_layer_dirs(path)? {
+        let dir_name = category_dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| NftgenError::InvalidFilename(category_dir.clone()))?;
+        let name = dir_name.split('#').next().unwrap_or_default().to_string();
+        let weight = Layer::parse_weight_from_file_stem(dir_name)?;
+        log::debug!(
+            "Loading category from directory: {}",
+            category_dir.to_string_lossy()
+        );
+
+        let (category_layers, category_categories) = parse_layer_tree(&category_dir)?;
+        categories.push(LayerCategory {
+            path: vec![name.clone()],
+            weight,
+        });
+        categories.extend(category_categories.into_iter().map(|mut category| {
+            category.path.insert(0, name.clone());
+            category
+        }));
+        layers.extend(category_layers.into_iter().map(|mut layer| {
+            layer.category.insert(0, name.clone());
+            layer
+        }));
+    }
+    Ok((layers, categories))
 }
 
+/// Returns the directories in `layer_dir_root`, sorted by name
 fn get_layer_dirs<P: AsRef<Path>>(layer_dir_root: P) -> Result<Vec<PathBuf>, NftgenError> {
     let layer_dir_root = layer_dir_root.as_ref();
-    Ok(layer_dir_root
+    let mut layer_dirs: Vec<PathBuf> = layer_dir_root
         .read_dir()?
         .collect::<Result<Vec<DirEntry>, _>>()?
         .into_iter()
         .map(|l| l.path())
         .filter(|l| l.is_dir())
-        .collect())
+        .collect();
+    layer_dirs.sort();
+    Ok(layer_dirs)
 }
 
 #[cfg(test)]
@@ -78,10 +248,40 @@ mod tests {
         let layer_groups = get_layer_groups(&fixture.path, layer_dirs).unwrap();
 
         assert_eq!(layer_groups.len(), 2);
-        assert!(matches!(
-            layer_groups.iter().find(|lg| lg.layer_type == "layer1"),
-            Some(_)
-        ));
+        assert!(layer_groups.iter().any(|lg| lg.layer_type == "layer1"));
+    }
+
+    #[test]
+    fn layer_groups_do_not_depend_on_file_order() {
+        let files: Vec<String> = (0..12).map(|i| format!("{:02}#{}.png", i, i + 1)).collect();
+        let files: Vec<&str> = files.iter().map(String::as_str).collect();
+        let reversed: Vec<&str> = files.iter().rev().copied().collect();
+        let plan = |files: &[&str], categories: &[&str]| {
+            let fixture = Fixture::blank("minimal.png");
+            fixture.create_layer_files("hat", files);
+            for category in categories {
+                fixture.create_layer_files(&format!("hat/{}", category), files);
+            }
+            fixture.create_layer_files("shirt", files);
+
+            let layers = parse_layers_from_path(fixture.path.join("shirt")).unwrap();
+            let names: Vec<_> = layers.iter().map(|layer| layer.name()).collect();
+            let mut sorted = names.clone();
+            sorted.sort();
+            assert_eq!(names, sorted);
+
+            let layer_groups = get_layer_groups(&fixture.path, &["shirt", "hat"]).unwrap();
+            plan_tokens(&layer_groups, &Rules::default(), 20, 1)
+                .unwrap()
+                .into_iter()
+                .map(|token| token.dna)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            plan(&files, &["Summer", "Winter", "Autumn"]),
+            plan(&reversed, &["Autumn", "Winter", "Summer"])
+        );
     }
 
     #[test]
@@ -92,8 +292,120 @@ mod tests {
         let layers = parse_layers_from_path(fixture.path.join("background")).unwrap();
 
         for i in 0..10 {
-            let layer = layers.iter().find(|l| l.weight == i as u32).unwrap();
+            let layer = layers.iter().find(|l| l.weight == i as f64).unwrap();
             assert_str_eq!(layer.name().unwrap(), format!("image{}", i));
         }
     }
+
+    #[test]
+    fn parse_layers_from_path_includes_none_layer() {
+        let layer_dirs = &["hat"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        std::fs::write(fixture.path.join("hat/none#20"), "").unwrap();
+        std::fs::write(fixture.path.join("hat/notes.txt"), "").unwrap();
+
+        let layers = parse_layers_from_path(fixture.path.join("hat")).unwrap();
+
+        assert_eq!(layers.len(), 11);
+        let none = layers.iter().find(|l| l.is_none()).unwrap();
+        assert_eq!(none.weight, 20.0);
+    }
+
+    #[test]
+    fn parse_layers_from_path_includes_gif_layers() {
+        let layer_dirs = &["eyes"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        std::fs::copy(
+            Fixture::source_path("animated.gif"),
+            fixture.path.join("eyes/blinking#3.gif"),
+        )
+        .unwrap();
+
+        let layers = parse_layers_from_path(fixture.path.join("eyes")).unwrap();
+
+        assert_eq!(layers.len(), 11);
+        let blinking = layers
+            .iter()
+            .find(|l| l.name() == Some("blinking"))
+            .unwrap();
+        assert_eq!(blinking.weight, 3.0);
+    }
+
+    #[test]
+    fn parse_layers_from_path_adds_parts_to_layers() {
+        let layer_dirs = &["hair"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        std::fs::copy(
+            Fixture::source_path("minimal.png"),
+            fixture.path.join("hair/image3~head.png"),
+        )
+        .unwrap();
+
+        let layers = parse_layers_from_path(fixture.path.join("hair")).unwrap();
+
+        assert_eq!(layers.len(), 10);
+        let image3 = layers.iter().find(|l| l.name() == Some("image3")).unwrap();
+        assert_eq!(image3.parts.len(), 1);
+        assert_eq!(image3.parts[0].behind, "head");
+        assert!(layers
+            .iter()
+            .filter(|l| l.name() != Some("image3"))
+            .all(|l| l.parts.is_empty()));
+    }
+
+    #[test]
+    fn parse_layers_from_path_rejects_orphan_parts() {
+        let layer_dirs = &["hair"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        std::fs::copy(
+            Fixture::source_path("minimal.png"),
+            fixture.path.join("hair/long~head.png"),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            parse_layers_from_path(fixture.path.join("hair")),
+            Err(NftgenError::OrphanLayerPart(_))
+        ));
+    }
+
+    #[test]
+    fn get_layer_groups_rejects_parts_behind_unknown_layers() {
+        let layer_dirs = &["hair"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        std::fs::copy(
+            Fixture::source_path("minimal.png"),
+            fixture.path.join("hair/image3~head.png"),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            get_layer_groups(&fixture.path, &["head", "hair"]),
+            Err(NftgenError::UnknownLayer(_))
+        ));
+    }
+
+    #[test]
+    fn parse_layers_from_path_replaces_sprite_with_palettes() {
+        let layer_dirs = &["fur"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        std::fs::copy(
+            Fixture::source_path("rgb8.png"),
+            fixture.path.join("fur/cat.png"),
+        )
+        .unwrap();
+        std::fs::write(
+            fixture.path.join("fur").join(PALETTES_FILE_NAME),
+            r#"{"sprite": "cat.png", "palettes": {"ginger": {"weight": 5}, "black": {}}}"#,
+        )
+        .unwrap();
+
+        let layers = parse_layers_from_path(fixture.path.join("fur")).unwrap();
+
+        assert_eq!(layers.len(), 12);
+        assert!(layers.iter().all(|l| l.name() != Some("cat")));
+        let ginger = layers.iter().find(|l| l.name() == Some("ginger")).unwrap();
+        assert_eq!(ginger.weight, 5.0);
+        assert_eq!(ginger.image_path, fixture.path.join("fur/cat.png"));
+    }
 }
@@ -0,0 +1,308 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+
+use crate::{collection_rng, placeholder_uri, Attribute, Dna, Metadata, NftgenError};
+
+/// Hand-made NFT that does not come from the layers, e.g. `legend.png` next to its
+/// metadata template `legend.json`
+#[derive(Debug, Clone)]
+pub struct OneOfOne {
+    pub image_path: PathBuf,
+    pub template: OneOfOneTemplate,
+}
+
+/// Metadata of a one of one NFT, e.g.
+/// {"id": 7, "name": "The Founder", "attributes": [{"trait_type": "Tier", "value": "Legendary"}]}
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OneOfOneTemplate {
+    /// Token id of the NFT, a random free id if none
+    pub id: Option<u32>,
+    /// Name of the NFT, `{id}` is replaced by the token id
+    /// Defaults to the name of generated NFTs, `<collection name> #<id>`
+    pub name: Option<String>,
+    /// Description of the NFT, defaults to the description of the collection
+    pub description: Option<String>,
+    #[serde(default)]
+    pub attributes: Vec<Attribute>,
+}
+
+impl OneOfOne {
+    /// Name of the image file without its extension, e.g. `legend`
+    pub fn file_name(&self) -> &str {
+        self.image_path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .expect("One of one file name should be valid unicode")
+    }
+
+    /// DNA of the NFT, unique among NFTs from the layers and other one of ones
+    pub fn dna(&self) -> Dna {
+        Dna::new([("one of one", self.file_name())])
+    }
+
+    /// Builds the metadata of the NFT from its template, like `MetadataBuilder::build` does
+    /// for NFTs from the layers
+    pub fn metadata<'a>(
+        &'a self,
+        id: u32,
+        description: &'a str,
+        collection_name: &str,
+        image_extension: &str,
+    ) -> Metadata<'a> {
+        let name = match &self.template.name {
+            Some(name) => name.replace("{id}", &id.to_string()),
+            None => format!("{} #{}", collection_name, id),
+        };
+        Metadata::new(
+            self.template.description.as_deref().unwrap_or(description),
+            name,
+            placeholder_uri(id, image_extension),
+            self.dna().to_string(),
+            self.template.attributes.clone(),
+        )
+    }
+}
+
+/// Reads the one of one NFTs in `dir`: PNG, APNG or GIF images, each with a JSON metadata
+/// template of the same name, sorted by file name
+/// File names must be valid unicode, since they make up the DNA of the NFTs
+pub fn read_one_of_ones<P: AsRef<Path>>(dir: P) -> Result<Vec<OneOfOne>, NftgenError> {
+    let dir = dir.as_ref();
+    let mut paths = dir
+        .read_dir()?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+
+    let is_image = |path: &Path| {
+        path.extension()
+            .is_some_and(|extension| extension == "png" || extension == "gif")
+    };
+    let images: Vec<_> = paths.iter().filter(|path| is_image(path)).collect();
+    if let Some(template_path) = paths
+        .iter()
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .find(|path| {
+            !images
+                .iter()
+                .any(|image| image.with_extension("json") == **path)
+        })
+    {
+        return Err(NftgenError::InvalidOneOfOne(format!(
+            "{} has no image",
+            template_path.to_string_lossy()
+        )));
+    }
+
+    // The DNA and plans only know the file stem, which must tell the images apart
+    let mut stems = BTreeSet::new();
+    if let Some(image_path) = images
+        .iter()
+        .find(|image_path| !stems.insert(image_path.file_stem()))
+    {
+        return Err(NftgenError::InvalidOneOfOne(format!(
+            "{} has the same name as another image",
+            image_path.to_string_lossy()
+        )));
+    }
+
+    images
+        .into_iter()
+        .map(|image_path| {
+            if image_path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_none()
+            {
+                return Err(NftgenError::InvalidFilename(image_path.clone()));
+            }
+            let template_path = image_path.with_extension("json");
+            if !template_path.is_file() {
+                return Err(NftgenError::InvalidOneOfOne(format!(
+                    "{} has no metadata template {}",
+                    image_path.to_string_lossy(),
+                    template_path.to_string_lossy()
+                )));
+            }
+            log::debug!("Loading one of one NFT: {}", image_path.to_string_lossy());
+            let template = serde_json::from_str(&std::fs::read_to_string(&template_path)?)
+                .map_err(|e| {
+                    NftgenError::InvalidOneOfOne(format!(
+                        "{}: {}",
+                        template_path.to_string_lossy(),
+                        e
+                    ))
+                })?;
+            Ok(OneOfOne {
+                image_path: image_path.clone(),
+                template,
+            })
+        })
+        .collect()
+}
+
+/// Token ids of `one_of_ones` among `num` NFTs, in the same order
+/// One of ones without an id get random free ids, which only depend on `seed`
+pub fn one_of_one_ids(
+    one_of_ones: &[OneOfOne],
+    num: usize,
+    seed: u64,
+) -> Result<Vec<u32>, NftgenError> {
+    if one_of_ones.len() > num {
+        return Err(NftgenError::InvalidOneOfOne(format!(
+            "{} one of ones do not fit into {} NFTs",
+            one_of_ones.len(),
+            num
+        )));
+    }
+    let mut taken = BTreeSet::new();
+    for one_of_one in one_of_ones {
+        if let Some(id) = one_of_one.template.id {
+            if id as usize >= num || !taken.insert(id) {
+                return Err(NftgenError::InvalidOneOfOne(format!(
+                    "{} has the id {}, which is out of range or taken",
+                    one_of_one.image_path.to_string_lossy(),
+                    id
+                )));
+            }
+        }
+    }
+
+    let mut free: Vec<u32> = (0..num as u32).filter(|id| !taken.contains(id)).collect();
+    free.shuffle(&mut collection_rng(seed));
+    let mut free = free.into_iter();
+    Ok(one_of_ones
+        .iter()
+        .map(|one_of_one| {
+            one_of_one
+                .template
+                .id
+                .unwrap_or_else(|| free.next().expect("One of ones should fit into the NFTs"))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nft::tests::fixture::Fixture;
+
+    fn one_of_ones(fixture: &Fixture, templates: &[(&str, &str)]) -> PathBuf {
+        let images: Vec<String> = templates
+            .iter()
+            .map(|(name, _)| format!("{}.png", name))
+            .collect();
+        let images: Vec<&str> = images.iter().map(String::as_str).collect();
+        let dir = fixture.create_layer_files("one_of_ones", &images);
+        for (name, template) in templates {
+            std::fs::write(dir.join(format!("{}.json", name)), template).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn read_one_of_ones_works() {
+        let fixture = Fixture::blank("minimal.png");
+        let dir = one_of_ones(
+            &fixture,
+            &[
+                ("founder", r#"{"id": 3, "name": "The Founder #{id}"}"#),
+                (
+                    "legend",
+                    r#"{"attributes": [{"trait_type": "Tier", "value": "1/1"}]}"#,
+                ),
+            ],
+        );
+
+        let one_of_ones = read_one_of_ones(&dir).unwrap();
+        assert_eq!(one_of_ones.len(), 2);
+        assert_eq!(one_of_ones[0].file_name(), "founder");
+
+        let metadata = one_of_ones[0].metadata(3, "Collection", "Great", "png");
+        assert_eq!(metadata.name, "The Founder #3");
+        assert_eq!(metadata.description, "Collection");
+        let metadata = one_of_ones[1].metadata(5, "Collection", "Great", "png");
+        assert_eq!(metadata.name, "Great #5");
+        assert_eq!(
+            metadata.attributes,
+            vec![Attribute::new("Tier".to_string(), "1/1".to_string())]
+        );
+        assert_ne!(one_of_ones[0].dna(), one_of_ones[1].dna());
+    }
+
+    #[test]
+    fn read_one_of_ones_rejects_missing_templates() {
+        let fixture = Fixture::blank("minimal.png");
+        let dir = one_of_ones(&fixture, &[("legend", "{}")]);
+        std::fs::remove_file(dir.join("legend.json")).unwrap();
+        assert!(matches!(
+            read_one_of_ones(&dir),
+            Err(NftgenError::InvalidOneOfOne(_))
+        ));
+
+        std::fs::write(dir.join("legend.json"), r#"{"nmae": "Legend"}"#).unwrap();
+        assert!(matches!(
+            read_one_of_ones(&dir),
+            Err(NftgenError::InvalidOneOfOne(_))
+        ));
+    }
+
+    #[test]
+    fn read_one_of_ones_rejects_images_of_the_same_name() {
+        let fixture = Fixture::blank("minimal.png");
+        let dir = one_of_ones(&fixture, &[("legend", "{}")]);
+        std::fs::copy(dir.join("legend.png"), dir.join("legend.gif")).unwrap();
+        assert!(matches!(
+            read_one_of_ones(&dir),
+            Err(NftgenError::InvalidOneOfOne(_))
+        ));
+    }
+
+    #[test]
+    fn read_one_of_ones_rejects_invalid_unicode() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let fixture = Fixture::blank("minimal.png");
+        let dir = one_of_ones(&fixture, &[("legend", "{}")]);
+        let name = OsStr::from_bytes(&[0x66, 0x6f, 0x80, 0x6f]);
+        std::fs::rename(dir.join("legend.png"), dir.join(name).with_extension("png")).unwrap();
+        std::fs::rename(
+            dir.join("legend.json"),
+            dir.join(name).with_extension("json"),
+        )
+        .unwrap();
+        assert!(matches!(
+            read_one_of_ones(&dir),
+            Err(NftgenError::InvalidFilename(_))
+        ));
+    }
+
+    #[test]
+    fn one_of_one_ids_keeps_chosen_ids() {
+        let one_of_one = |id: Option<u32>| OneOfOne {
+            image_path: PathBuf::from("legend.png"),
+            template: OneOfOneTemplate {
+                id,
+                ..OneOfOneTemplate::default()
+            },
+        };
+        let one_of_ones = [one_of_one(None), one_of_one(Some(4)), one_of_one(None)];
+
+        let ids = one_of_one_ids(&one_of_ones, 10, 7).unwrap();
+        assert_eq!(ids[1], 4);
+        assert_eq!(ids.iter().collect::<BTreeSet<_>>().len(), 3);
+        assert!(ids.iter().all(|&id| id < 10));
+        assert_eq!(ids, one_of_one_ids(&one_of_ones, 10, 7).unwrap());
+
+        assert!(one_of_one_ids(&one_of_ones, 2, 7).is_err());
+        assert!(one_of_one_ids(&[one_of_one(Some(4)), one_of_one(Some(4))], 10, 7).is_err());
+    }
+}
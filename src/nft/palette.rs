@@ -0,0 +1,218 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::{Animation, Frame, Image, Layer, NftgenError};
+
+/// File in a layer directory that declares palette swapped variants of a sprite, e.g.
+/// {"sprite": "cat.png", "palettes": {"ginger": {"weight": 5, "colors": {"#808080": "#ff8800"}}}}
+pub const PALETTES_FILE_NAME: &str = "palettes.json";
+
+/// Colors of a sprite swapped for other colors, e.g. the fur color of a cat
+/// Only the RGB channels are swapped, so anti-aliased and shadow pixels keep their alpha
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub name: String,
+    colors: HashMap<[u8; 3], [u8; 3]>,
+}
+
+impl Palette {
+    pub fn new<S: Into<String>>(name: S, colors: HashMap<[u8; 3], [u8; 3]>) -> Self {
+        Palette {
+            name: name.into(),
+            colors,
+        }
+    }
+
+    /// Returns a copy of `image` with the colors of this palette swapped
+    pub fn apply(&self, image: &Image) -> Image {
+        let data = image
+            .data()
+            .chunks_exact(4)
+            .flat_map(
+                |pixel| match self.colors.get(&[pixel[0], pixel[1], pixel[2]]) {
+                    Some(&[r, g, b]) => [r, g, b, pixel[3]],
+                    None => [pixel[0], pixel[1], pixel[2], pixel[3]],
+                },
+            )
+            .collect();
+        Image::new(data, 4, image.width, image.height)
+    }
+
+    /// Like `apply`, for every frame of `animation`
+    pub fn apply_to_animation(&self, animation: &Animation) -> Animation {
+        Animation {
+            frames: animation
+                .frames
+                .iter()
+                .map(|frame| Frame {
+                    image: self.apply(&frame.image),
+                    delay_ms: frame.delay_ms,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PalettesFile {
+    sprite: PathBuf,
+    palettes: BTreeMap<String, PaletteEntry>,
+}
+
+#[derive(Deserialize)]
+struct PaletteEntry {
+    #[serde(default = "default_weight")]
+    weight: f64,
+    #[serde(default)]
+    colors: BTreeMap<String, String>,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+/// Reads the palettes declared in `<layer_dir>/palettes.json` as one layer per palette,
+/// all drawing the same sprite
+/// Returns the path of the sprite and the layers, or none if the directory has no palettes
+pub(crate) fn parse_palette_layers(
+    layer_dir: &Path,
+) -> Result<Option<(PathBuf, Vec<Layer>)>, NftgenError> {
+    let palettes_path = layer_dir.join(PALETTES_FILE_NAME);
+    if !palettes_path.is_file() {
+        return Ok(None);
+    }
+    log::debug!(
+        "Loading palettes from file: {}",
+        palettes_path.to_string_lossy()
+    );
+    let palettes_file: PalettesFile =
+        serde_json::from_str(&std::fs::read_to_string(&palettes_path)?)?;
+    let sprite_path = layer_dir.join(&palettes_file.sprite);
+    if !sprite_path.is_file() {
+        return Err(NftgenError::InvalidPalette(format!(
+            "Sprite does not exist: {}",
+            sprite_path.to_string_lossy()
+        )));
+    }
+
+    let layers = palettes_file
+        .palettes
+        .into_iter()
+        .map(|(name, entry)| {
+            let colors = entry
+                .colors
+                .iter()
+                .map(|(from, to)| Ok((parse_hex_color(from)?, parse_hex_color(to)?)))
+                .collect::<Result<_, NftgenError>>()?;
+            Ok(Layer::new(&sprite_path, entry.weight).with_palette(Palette::new(name, colors)))
+        })
+        .collect::<Result<_, NftgenError>>()?;
+    Ok(Some((sprite_path, layers)))
+}
+
+/// Parses a `#rrggbb` color
+fn parse_hex_color(color: &str) -> Result<[u8; 3], NftgenError> {
+    let invalid = || NftgenError::InvalidPalette(format!("Invalid color: {}", color));
+    let hex = color.strip_prefix('#').ok_or_else(invalid)?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid());
+    Ok([channel(0)?, channel(1)?, channel(2)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nft::tests::fixture::Fixture;
+
+    #[test]
+    fn apply_swaps_rgb_and_keeps_alpha() {
+        let image = Image::new(
+            vec![128, 128, 128, 255, 128, 128, 128, 100, 1, 2, 3, 255],
+            4,
+            3,
+            1,
+        );
+        let palette = Palette::new("ginger", HashMap::from([([128, 128, 128], [255, 136, 0])]));
+        assert_eq!(
+            palette.apply(&image).data(),
+            &[255, 136, 0, 255, 255, 136, 0, 100, 1, 2, 3, 255]
+        );
+    }
+
+    #[test]
+    fn parse_hex_color_works() {
+        assert_eq!(parse_hex_color("#ff8800").unwrap(), [255, 136, 0]);
+        assert_eq!(parse_hex_color("#FF8800").unwrap(), [255, 136, 0]);
+        for invalid in ["ff8800", "#ff880", "#ff8800ff", "#gg8800", "#ff88é"] {
+            assert!(
+                matches!(
+                    parse_hex_color(invalid),
+                    Err(NftgenError::InvalidPalette(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn parse_palette_layers_works() {
+        let fixture = Fixture::blank("rgb8.png");
+        std::fs::copy(
+            Fixture::source_path("rgb8.png"),
+            fixture.path.join("cat.png"),
+        )
+        .unwrap();
+        std::fs::write(
+            fixture.path.join(PALETTES_FILE_NAME),
+            r##"{
+                "sprite": "cat.png",
+                "palettes": {
+                    "ginger": {"weight": 5, "colors": {"#ff0000": "#ff8800"}},
+                    "plain": {}
+                }
+            }"##,
+        )
+        .unwrap();
+
+        let (sprite_path, layers) = parse_palette_layers(&fixture.path).unwrap().unwrap();
+        assert_eq!(sprite_path, fixture.path.join("cat.png"));
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[0].name(), Some("ginger"));
+        assert_eq!(layers[0].weight, 5.0);
+        assert_eq!(layers[1].name(), Some("plain"));
+        assert_eq!(layers[1].weight, 1.0);
+
+        let ginger = layers[0].get_animation().unwrap();
+        assert_eq!(&ginger.first_frame().data()[..4], &[255, 136, 0, 255]);
+        let plain = layers[1].get_animation().unwrap();
+        assert_eq!(
+            plain.first_frame(),
+            &Image::read(Fixture::source_path("rgb8.png")).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_palette_layers_without_palettes() {
+        let fixture = Fixture::blank("");
+        assert!(parse_palette_layers(&fixture.path).unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_palette_layers_rejects_missing_sprite() {
+        let fixture = Fixture::blank("");
+        std::fs::write(
+            fixture.path.join(PALETTES_FILE_NAME),
+            r#"{"sprite": "cat.png", "palettes": {}}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            parse_palette_layers(&fixture.path),
+            Err(NftgenError::InvalidPalette(_))
+        ));
+    }
+}
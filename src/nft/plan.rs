@@ -0,0 +1,373 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use rand::Rng;
+
+use crate::nft::link::allows_variant;
+use crate::{
+    linked_groups, linked_variants, token_rng, Dna, ImageBuilder, LayerGroup, NftgenError,
+    PickedLayer, Rules,
+};
+
+/// Maximum number of re-rolls for a single NFT before giving up on finding a unique DNA
+const MAX_PICK_ATTEMPTS: usize = 10_000;
+
+/// The layers chosen for a single NFT, decided before any image is rendered
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenPlan<'a> {
+    pub id: u32,
+    pub dna: Dna,
+    pub layers: Vec<PickedLayer<'a>>,
+}
+
+/// Returns the DNA of the layers picked for an NFT, including the categories of nested layers
+/// and the variants of linked layers
+pub fn layers_dna(picks: &[PickedLayer]) -> Dna {
+    Dna::new(
+        picks
+            .iter()
+            .map(|pick| (pick.layer_type, pick.qualified_name()))
+            .chain(
+                linked_variants(picks)
+                    .into_iter()
+                    .map(|(link, variant)| (link, Cow::Borrowed(variant))),
+            ),
+    )
+}
+
+/// Number of distinct layer combinations that can be picked from `layer_groups`
+/// Linked groups only combine layers of the same variant
+/// This is an upper bound when layer rules are used
+pub fn count_combinations(layer_groups: &[LayerGroup]) -> u128 {
+    let unlinked = layer_groups
+        .iter()
+        .filter(|group| group.link().is_none())
+        .map(|group| group.num_choices() as u128)
+        .fold(1u128, |total, len| total.saturating_mul(len));
+    linked_groups(layer_groups)
+        .values()
+        .map(|groups| {
+            groups[0]
+                .variants()
+                .into_iter()
+                .map(|variant| {
+                    groups
+                        .iter()
+                        .map(|group| group.num_variant_choices(variant) as u128)
+                        .fold(1u128, |total, len| total.saturating_mul(len))
+                })
+                .fold(0, |total: u128, count| total.saturating_add(count))
+        })
+        .fold(unlinked, |total, count| total.saturating_mul(count))
+}
+
+/// Picks layers for `num` NFTs such that no two NFTs share the same DNA and every NFT satisfies `rules`
+/// Duplicates are re-rolled with the RNG of the NFT, so the plan only depends on `seed`
+/// NFTs that keep rolling duplicates pick one of the unused combinations instead
+pub fn plan_tokens<'a>(
+    layer_groups: &'a [LayerGroup],
+    rules: &Rules,
+    num: usize,
+    seed: u64,
+) -> Result<Vec<TokenPlan<'a>>, NftgenError> {
+    plan_tokens_excluding(layer_groups, rules, num, seed, &HashSet::new())
+}
+
+/// Like `plan_tokens`, without repeating the DNA of `taken` NFTs, e.g. those of other editions
+pub fn plan_tokens_excluding<'a>(
+    layer_groups: &'a [LayerGroup],
+    rules: &Rules,
+    num: usize,
+    seed: u64,
+    taken: &HashSet<Dna>,
+) -> Result<Vec<TokenPlan<'a>>, NftgenError> {
+    let available = count_combinations(layer_groups);
+    if num as u128 > available {
+        return Err(NftgenError::NotEnoughCombinations {
+            requested: num,
+            available,
+        });
+    }
+
+    let mut seen = taken.clone();
+    seen.reserve(num);
+    (0..num as u32)
+        .map(|id| {
+            let mut rng = token_rng(seed, id);
+            for _ in 0..MAX_PICK_ATTEMPTS {
+                let layers = match ImageBuilder::pick(layer_groups, rules, &mut rng) {
+                    Some(layers) if layers.iter().any(|pick| pick.layer.is_some()) => layers,
+                    _ => continue,
+                };
+                let dna = layers_dna(&layers);
+                if seen.insert(dna.clone()) {
+                    return Ok(TokenPlan { id, dna, layers });
+                }
+                log::debug!("Duplicate DNA for NFT {}, re-rolling: {}", id, dna);
+            }
+
+            log::debug!("Picking NFT {} among the unused combinations", id);
+            let layers = pick_unused(layer_groups, rules, &seen, &mut rng)
+                .ok_or(NftgenError::CombinationsExhausted(id))?;
+            let dna = layers_dna(&layers);
+            seen.insert(dna.clone());
+            Ok(TokenPlan { id, dna, layers })
+        })
+        .collect()
+}
+
+/// Picks one of the combinations that satisfy `rules` and whose DNA is not `seen`, each
+/// equally likely, by going through every combination
+fn pick_unused<'a, R: Rng + ?Sized>(
+    layer_groups: &'a [LayerGroup],
+    rules: &Rules,
+    seen: &HashSet<Dna>,
+    rng: &mut R,
+) -> Option<Vec<PickedLayer<'a>>> {
+    let mut unused = 0;
+    let mut chosen = None;
+    visit_combinations(layer_groups, rules, &mut vec![], &mut |picks| {
+        if picks.iter().any(|pick| pick.layer.is_some()) && !seen.contains(&layers_dna(picks)) {
+            unused += 1;
+            if rng.gen_range(0..unused) == 0 {
+                chosen = Some(picks.to_vec());
+            }
+        }
+    });
+    chosen
+}
+
+/// Calls `visit` with every combination of the layers of `layer_groups` that `ImageBuilder::pick`
+/// could pick: linked layers share their variant, and the picks satisfy `rules`
+fn visit_combinations<'a>(
+    layer_groups: &'a [LayerGroup],
+    rules: &Rules,
+    picks: &mut Vec<PickedLayer<'a>>,
+    visit: &mut impl FnMut(&[PickedLayer<'a>]),
+) {
+    let layer_group = match layer_groups.get(picks.len()) {
+        Some(layer_group) => layer_group,
+        None => return visit(picks),
+    };
+    let picked: Vec<_> = picks
+        .iter()
+        .map(|pick| (pick.layer_type, pick.name()))
+        .collect();
+    let variants = linked_variants(picks);
+    let candidates: Vec<_> = layer_group
+        .pickable_layers()
+        .filter(|layer| !layer.is_none() && allows_variant(&variants, layer))
+        .map(|layer| layer_group.picked(Some(layer)))
+        .chain(layer_group.is_optional().then(|| layer_group.picked(None)))
+        .filter(|pick| rules.allows(&picked, (pick.layer_type, pick.name())))
+        .collect();
+    for pick in candidates {
+        picks.push(pick);
+        visit_combinations(layer_groups, rules, picks, visit);
+        picks.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_layer_groups;
+    use crate::nft::tests::fixture::Fixture;
+
+    #[test]
+    fn plan_tokens_has_unique_dna() {
+        let layer_dirs = &["layer1", "layer2"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        let layer_groups = get_layer_groups(&fixture.path, layer_dirs).unwrap();
+
+        let plan = plan_tokens(&layer_groups, &Rules::default(), 50, 7).unwrap();
+
+        let dnas: HashSet<_> = plan.iter().map(|token| token.dna.clone()).collect();
+        assert_eq!(dnas.len(), 50);
+        assert_eq!(
+            plan,
+            plan_tokens(&layer_groups, &Rules::default(), 50, 7).unwrap()
+        );
+    }
+
+    #[test]
+    fn plan_tokens_uses_every_combination() {
+        let layer_dirs = &["layer1"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        let layer_groups = get_layer_groups(&fixture.path, layer_dirs).unwrap();
+
+        // image0#0.png has a weight of zero and can never be picked
+        let result = plan_tokens(&layer_groups, &Rules::default(), 9, 1);
+        assert_eq!(result.unwrap().len(), 9);
+    }
+
+    #[test]
+    fn plan_tokens_excluding_skips_taken_dna() {
+        let layer_dirs = &["layer1"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        let layer_groups = get_layer_groups(&fixture.path, layer_dirs).unwrap();
+
+        let taken: HashSet<_> = plan_tokens(&layer_groups, &Rules::default(), 4, 1)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.dna)
+            .collect();
+        let plan = plan_tokens_excluding(&layer_groups, &Rules::default(), 5, 1, &taken).unwrap();
+        assert!(plan.iter().all(|token| !taken.contains(&token.dna)));
+
+        let result = plan_tokens_excluding(&layer_groups, &Rules::default(), 6, 1, &taken);
+        assert!(matches!(result, Err(NftgenError::CombinationsExhausted(5))));
+    }
+
+    #[test]
+    fn plan_tokens_excluding_fills_editions_with_other_layers() {
+        let fixture = Fixture::blank("minimal.png");
+        for edition in ["genesis", "expansion"] {
+            std::fs::create_dir(fixture.path.join(edition)).unwrap();
+            for layer_type in ["Body", "Eyes"] {
+                let files = [format!("{}1.png", edition), format!("{}2.png", edition)];
+                let files: Vec<&str> = files.iter().map(String::as_str).collect();
+                fixture.create_layer_files(&format!("{}/{}", edition, layer_type), &files);
+            }
+        }
+        let genesis = get_layer_groups(fixture.path.join("genesis"), &["Body", "Eyes"]).unwrap();
+        let expansion =
+            get_layer_groups(fixture.path.join("expansion"), &["Body", "Eyes"]).unwrap();
+
+        let taken: HashSet<_> = plan_tokens(&genesis, &Rules::default(), 4, 1)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.dna)
+            .collect();
+        let plan = plan_tokens_excluding(&expansion, &Rules::default(), 4, 1, &taken).unwrap();
+        assert_eq!(plan.len(), 4);
+    }
+
+    #[test]
+    fn plan_tokens_picks_unused_combinations_when_re_rolls_run_out() {
+        let fixture = Fixture::blank("minimal.png");
+        fixture.create_layer_files("hat", &["cap.png", "crown.png"]);
+        std::fs::write(
+            fixture.path.join("hat/layers.toml"),
+            r#"
+                [layers."cap.png"]
+                weight = 1.0
+
+                [layers."crown.png"]
+                weight = 1e-12
+            "#,
+        )
+        .unwrap();
+        let layer_groups = get_layer_groups(&fixture.path, &["hat"]).unwrap();
+
+        let plan = plan_tokens(&layer_groups, &Rules::default(), 2, 1).unwrap();
+        let names: Vec<_> = plan.iter().map(|token| token.layers[0].name()).collect();
+        assert_eq!(names, ["cap", "crown"]);
+    }
+
+    #[test]
+    fn plan_tokens_errors_when_combinations_run_out() {
+        let layer_dirs = &["layer1"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        let layer_groups = get_layer_groups(&fixture.path, layer_dirs).unwrap();
+
+        let result = plan_tokens(&layer_groups, &Rules::default(), 10, 1);
+        assert!(matches!(
+            result,
+            Err(NftgenError::NotEnoughCombinations {
+                requested: 10,
+                available: 9
+            })
+        ));
+    }
+
+    #[test]
+    fn plan_tokens_follows_rules() {
+        let layer_dirs = &["layer1", "layer2"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        let layer_groups = get_layer_groups(&fixture.path, layer_dirs).unwrap();
+        let rules = Rules::new(
+            vec![(
+                "layer1/image9".parse().unwrap(),
+                "layer2/image9".parse().unwrap(),
+            )],
+            vec![(
+                "layer1/image1".parse().unwrap(),
+                "layer2/image2".parse().unwrap(),
+            )],
+        );
+
+        let plan = plan_tokens(&layer_groups, &rules, 60, 3).unwrap();
+
+        for token in plan {
+            let names: Vec<_> = token.layers.iter().map(|l| l.name()).collect();
+            assert_ne!(names, vec!["image9", "image9"]);
+            if names[0] == "image1" {
+                assert_eq!(names[1], "image2");
+            }
+        }
+    }
+
+    #[test]
+    fn plan_tokens_errors_when_rules_exhaust_combinations() {
+        let layer_dirs = &["layer1", "layer2"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        let layer_groups = get_layer_groups(&fixture.path, layer_dirs).unwrap();
+        // Every layer1 trait requires layer2/image1, leaving only 9 combinations
+        let requires = (1..10)
+            .map(|i| {
+                (
+                    format!("layer1/image{}", i).parse().unwrap(),
+                    "layer2/image1".parse().unwrap(),
+                )
+            })
+            .collect();
+        let rules = Rules::new(vec![], requires);
+
+        let result = plan_tokens(&layer_groups, &rules, 10, 1);
+        assert!(matches!(result, Err(NftgenError::CombinationsExhausted(9))));
+    }
+
+    #[test]
+    fn plan_tokens_links_variants() {
+        let fixture = Fixture::blank("minimal.png");
+        let layer_types = ["body", "hands"];
+        let files: [&[&str]; 2] = [
+            &[
+                "slim@dark.png",
+                "round@dark.png",
+                "slim@light.png",
+                "round@light.png",
+            ],
+            &[
+                "open@dark.png",
+                "fist@dark.png",
+                "open@light.png",
+                "fist@light.png",
+            ],
+        ];
+        let layer_groups: Vec<_> = layer_types
+            .iter()
+            .zip(files)
+            .map(|(layer_type, files)| {
+                let layer_path = fixture.create_layer_files(layer_type, files);
+                LayerGroup::new(layer_path, &layer_types)
+                    .unwrap()
+                    .with_link("Skin")
+                    .unwrap()
+            })
+            .collect();
+
+        // 2 body layers times 2 hands layers for each of the 2 variants
+        assert_eq!(count_combinations(&layer_groups), 8);
+        let plan = plan_tokens(&layer_groups, &Rules::default(), 8, 3).unwrap();
+        for token in plan {
+            let variants: Vec<_> = token
+                .layers
+                .iter()
+                .map(|pick| pick.layer.unwrap().variant())
+                .collect();
+            assert_eq!(variants[0], variants[1]);
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{layers_dna, LayerGroup, NftgenError, PickedLayer, TokenPlan, NONE_LAYER_NAME};
+
+/// Layers picked for every NFT of a collection, written before rendering so that the traits
+/// can be reviewed or edited by hand and then rendered as they are
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlanFile {
+    /// Seed the plan was picked with, none for plans written by hand
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// NFTs added to the collection later on, see `PlanExtension`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<PlanExtension>,
+    pub tokens: Vec<PlannedToken>,
+}
+
+/// NFTs added to a collection with their own seed, from the token id `first_id` on
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlanExtension {
+    pub first_id: u32,
+    pub seed: u64,
+}
+
+/// A single NFT of a plan file, e.g.
+/// {"id": 0, "layers": {"Background": "red", "Clothing": "Shirts/tee", "Hat": "none"}}
+/// or a one of one NFT by the name of its image, e.g. {"id": 7, "one_of_one": "legend"}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlannedToken {
+    pub id: u32,
+    /// Edition of the NFT, if the collection is split into editions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edition: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub one_of_one: Option<String>,
+    /// Layer names with their category by trait type or layer type, `none` for no layer
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub layers: BTreeMap<String, String>,
+    /// Variants of linked layers by link, e.g. `"Skin": "dark"`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variants: BTreeMap<String, String>,
+}
+
+impl PlanFile {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, NftgenError> {
+        let path = path.as_ref();
+        serde_json::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|e| NftgenError::InvalidPlan(format!("{}: {}", path.to_string_lossy(), e)))
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), NftgenError> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+impl PlannedToken {
+    /// Records the layers of `token`, of the `edition` if there are editions
+    pub fn from_plan(token: &TokenPlan, edition: Option<&str>) -> Self {
+        PlannedToken {
+            id: token.id,
+            edition: edition.map(str::to_string),
+            one_of_one: None,
+            layers: token
+                .layers
+                .iter()
+                .map(|pick| {
+                    (
+                        pick.layer_type.to_string(),
+                        pick.qualified_name().into_owned(),
+                    )
+                })
+                .collect(),
+            variants: token
+                .layers
+                .iter()
+                .filter_map(|pick| pick.linked_variant())
+                .map(|(link, variant)| (link.to_string(), variant.to_string()))
+                .collect(),
+        }
+    }
+
+    /// Records the one of one NFT with the image `name`, e.g. `legend` for `legend.png`
+    pub fn from_one_of_one(id: u32, name: &str) -> Self {
+        PlannedToken {
+            id,
+            edition: None,
+            one_of_one: Some(name.to_string()),
+            layers: BTreeMap::new(),
+            variants: BTreeMap::new(),
+        }
+    }
+
+    /// Looks up the layers of this NFT in `layer_groups`, by their name with the category or,
+    /// if that is unambiguous, by their name alone
+    /// Groups are named by their trait type in the metadata or by their layer type
+    /// Fails if a group is missing, unknown or named twice, or if a layer or variant does not exist
+    pub fn resolve<'a>(
+        &self,
+        layer_groups: &'a [LayerGroup],
+    ) -> Result<TokenPlan<'a>, NftgenError> {
+        let invalid =
+            |message: String| NftgenError::InvalidPlan(format!("NFT #{} {}", self.id, message));
+        if let Some(layer_type) = self.layers.keys().find(|layer_type| {
+            !layer_groups.iter().any(|group| {
+                &group.layer_type == *layer_type || group.trait_type() == layer_type.as_str()
+            })
+        }) {
+            return Err(invalid(format!("has an unknown layer type {}", layer_type)));
+        }
+
+        let layers = layer_groups
+            .iter()
+            .map(|layer_group| {
+                let trait_type = layer_group.trait_type();
+                let name = match (
+                    self.layers.get(trait_type),
+                    self.layers.get(&layer_group.layer_type),
+                ) {
+                    (Some(_), Some(_)) if trait_type != layer_group.layer_type => {
+                        return Err(invalid(format!(
+                            "has a layer for {} both as {} and as {}",
+                            layer_group.layer_type, trait_type, layer_group.layer_type
+                        )))
+                    }
+                    (Some(name), _) | (None, Some(name)) => name,
+                    (None, None) => {
+                        return Err(invalid(format!("has no layer for {}", trait_type)))
+                    }
+                };
+                if name == NONE_LAYER_NAME {
+                    return Ok(layer_group.picked(None));
+                }
+                let variant = match layer_group.link() {
+                    Some(link) => Some(
+                        self.variants
+                            .get(link)
+                            .ok_or_else(|| invalid(format!("has no variant for {}", link)))?,
+                    ),
+                    None => None,
+                };
+                let variants: Vec<_> = layer_group
+                    .layers()
+                    .iter()
+                    .filter(|layer| layer.variant() == variant.map(String::as_str))
+                    .collect();
+                let mut layers: Vec<_> = variants
+                    .iter()
+                    .filter(|layer| layer.qualified_name().as_deref() == Some(name.as_str()))
+                    .collect();
+                if layers.is_empty() {
+                    layers = variants
+                        .iter()
+                        .filter(|layer| layer.name() == Some(name.as_str()))
+                        .collect();
+                }
+                match layers[..] {
+                    [layer] => Ok(layer_group.picked(Some(layer))),
+                    [] => Err(invalid(format!(
+                        "has an unknown layer {}/{}",
+                        layer_group.layer_type, name
+                    ))),
+                    _ => Err(invalid(format!(
+                        "has an ambiguous layer {}/{}, name it with its category",
+                        layer_group.layer_type, name
+                    ))),
+                }
+            })
+            .collect::<Result<Vec<PickedLayer>, NftgenError>>()?;
+
+        Ok(TokenPlan {
+            id: self.id,
+            dna: layers_dna(&layers),
+            layers,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nft::tests::fixture::Fixture;
+    use crate::{get_layer_groups, plan_tokens, Rules};
+
+    #[test]
+    fn planned_tokens_resolve_to_the_same_layers() {
+        let fixture = Fixture::blank("minimal.png");
+        fixture.create_layer_files("body", &["slim@dark.png", "slim@light.png"]);
+        fixture.create_layer_files("clothing", &["cape.png"]);
+        fixture.create_layer_files("clothing/shirts", &["tee.png", "polo.png"]);
+        let layer_groups: Vec<_> = get_layer_groups(&fixture.path, &["body", "clothing"])
+            .unwrap()
+            .into_iter()
+            .map(|group| match group.layer_type.as_str() {
+                "body" => group.with_link("Skin").unwrap(),
+                _ => group,
+            })
+            .collect();
+
+        let plan = plan_tokens(&layer_groups, &Rules::default(), 6, 3).unwrap();
+        let plan_file = PlanFile {
+            seed: Some(3),
+            extensions: vec![],
+            tokens: plan
+                .iter()
+                .map(|token| PlannedToken::from_plan(token, None))
+                .collect(),
+        };
+        let path = fixture.path.join("plan.json");
+        plan_file.write(&path).unwrap();
+
+        let plan_file = PlanFile::read(&path).unwrap();
+        for (token, planned) in plan.iter().zip(&plan_file.tokens) {
+            assert_eq!(&planned.resolve(&layer_groups).unwrap(), token);
+        }
+    }
+
+    #[test]
+    fn resolve_rejects_unknown_layers() {
+        let fixture = Fixture::blank("minimal.png");
+        fixture.create_layer_files("hat", &["cap.png"]);
+        fixture.create_layer_files("hat/Summer", &["straw.png", "bucket.png"]);
+        fixture.create_layer_files("hat/Winter", &["beanie.png", "bucket.png"]);
+        let layer_groups = get_layer_groups(&fixture.path, &["hat"]).unwrap();
+        let token = |layers: &[(&str, &str)]| PlannedToken {
+            id: 1,
+            edition: None,
+            one_of_one: None,
+            layers: layers
+                .iter()
+                .map(|(layer_type, name)| (layer_type.to_string(), name.to_string()))
+                .collect(),
+            variants: BTreeMap::new(),
+        };
+
+        assert!(token(&[("hat", "cap")]).resolve(&layer_groups).is_ok());
+        assert!(token(&[("hat", "straw")]).resolve(&layer_groups).is_ok());
+        assert!(token(&[("hat", "Winter/bucket")])
+            .resolve(&layer_groups)
+            .is_ok());
+        let none = token(&[("hat", "none")]).resolve(&layer_groups).unwrap();
+        assert_eq!(none.layers[0].layer, None);
+        for layers in [
+            &[("hat", "fedora")][..],
+            &[("hat", "bucket")][..],
+            &[][..],
+            &[("hat", "cap"), ("glasses", "round")][..],
+        ] {
+            assert!(matches!(
+                token(layers).resolve(&layer_groups),
+                Err(NftgenError::InvalidPlan(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn resolve_finds_groups_by_trait_type() {
+        let fixture = Fixture::blank("minimal.png");
+        fixture.create_layer_files("hat", &["cap.png", "beanie.png"]);
+        std::fs::write(
+            fixture.path.join("hat/layers.toml"),
+            r#"trait_type = "Headwear""#,
+        )
+        .unwrap();
+        let layer_groups = get_layer_groups(&fixture.path, &["hat"]).unwrap();
+        let token = |layers: &[(&str, &str)]| PlannedToken {
+            id: 1,
+            edition: None,
+            one_of_one: None,
+            layers: layers
+                .iter()
+                .map(|(layer_type, name)| (layer_type.to_string(), name.to_string()))
+                .collect(),
+            variants: BTreeMap::new(),
+        };
+
+        let plan = token(&[("Headwear", "cap")])
+            .resolve(&layer_groups)
+            .unwrap();
+        assert_eq!(plan.layers[0].name(), "cap");
+        assert_eq!(plan.layers[0].trait_type, "Headwear");
+        assert_eq!(
+            token(&[("hat", "cap")]).resolve(&layer_groups).unwrap(),
+            plan
+        );
+        assert!(matches!(
+            token(&[("Headwear", "cap"), ("hat", "beanie")]).resolve(&layer_groups),
+            Err(NftgenError::InvalidPlan(_))
+        ));
+    }
+}
@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use clap::ArgEnum;
+
+use crate::{Image, NftgenError};
+
+/// zlib compression level of PNG image data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ArgEnum)]
+pub enum PngCompression {
+    Fast,
+    #[default]
+    Default,
+    Best,
+}
+
+impl From<PngCompression> for png::Compression {
+    fn from(compression: PngCompression) -> Self {
+        match compression {
+            PngCompression::Fast => png::Compression::Fast,
+            PngCompression::Default => png::Compression::Default,
+            PngCompression::Best => png::Compression::Best,
+        }
+    }
+}
+
+/// Filter applied to every row of PNG image data before compressing it
+/// `adaptive` picks the filter that suits each row best
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ArgEnum)]
+pub enum PngFilter {
+    None,
+    Sub,
+    Up,
+    Avg,
+    Paeth,
+    #[default]
+    Adaptive,
+}
+
+impl PngFilter {
+    const ALL: [PngFilter; 6] = [
+        PngFilter::None,
+        PngFilter::Sub,
+        PngFilter::Up,
+        PngFilter::Avg,
+        PngFilter::Paeth,
+        PngFilter::Adaptive,
+    ];
+}
+
+/// How PNG images are encoded. The pixels are the same with every option
+/// - ```reduce_colors```: Saves opaque images as RGB and images of at most 256 colors with a palette
+/// - ```optimize```: Tries every filter and color type at the best compression and keeps the
+///   smallest file, ignoring `compression` and `filter`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PngOptions {
+    pub compression: PngCompression,
+    pub filter: PngFilter,
+    pub reduce_colors: bool,
+    pub optimize: bool,
+}
+
+impl Default for PngOptions {
+    fn default() -> Self {
+        PngOptions {
+            compression: PngCompression::Default,
+            filter: PngFilter::Adaptive,
+            reduce_colors: true,
+            optimize: false,
+        }
+    }
+}
+
+/// Encodes `frames` as a PNG, or as an APNG looping forever if there is more than one frame
+/// `delays_ms` holds the delay of every frame of an APNG
+pub(crate) fn encode_png(
+    frames: &[&Image],
+    delays_ms: &[u32],
+    options: &PngOptions,
+) -> Result<Vec<u8>, NftgenError> {
+    let pixels = Pixels::rgba(frames);
+    let reduced = match options.reduce_colors || options.optimize {
+        true => pixels.reduce(),
+        false => None,
+    };
+    if !options.optimize {
+        let pixels = reduced.as_ref().unwrap_or(&pixels);
+        return pixels.encode(delays_ms, options.compression, options.filter);
+    }
+
+    let mut smallest: Option<Vec<u8>> = None;
+    for pixels in reduced.iter().chain([&pixels]) {
+        for filter in PngFilter::ALL {
+            let encoded = pixels.encode(delays_ms, PngCompression::Best, filter)?;
+            if smallest
+                .as_ref()
+                .is_none_or(|smallest| encoded.len() < smallest.len())
+            {
+                smallest = Some(encoded);
+            }
+        }
+    }
+    Ok(smallest.unwrap())
+}
+
+/// Splits a delay into the numerator and denominator of an APNG frame delay
+fn apng_delay(delay_ms: u32) -> (u16, u16) {
+    match u16::try_from(delay_ms) {
+        Ok(delay_ms) => (delay_ms, 1000),
+        Err(_) => ((delay_ms / 100).min(u16::MAX as u32) as u16, 10),
+    }
+}
+
+/// Image data of the frames of a PNG in one color type
+struct Pixels {
+    width: u32,
+    height: u32,
+    color: png::ColorType,
+    depth: png::BitDepth,
+    palette: Option<(Vec<u8>, Vec<u8>)>,
+    frames: Vec<Vec<u8>>,
+}
+
+impl Pixels {
+    fn rgba(frames: &[&Image]) -> Self {
+        Pixels {
+            width: frames[0].width,
+            height: frames[0].height,
+            color: png::ColorType::Rgba,
+            depth: png::BitDepth::Eight,
+            palette: None,
+            frames: frames.iter().map(|frame| frame.data().to_vec()).collect(),
+        }
+    }
+
+    /// Converts RGBA pixels to a palette if there are at most 256 colors, or to RGB if
+    /// every pixel is opaque. Returns none if neither is possible
+    fn reduce(&self) -> Option<Self> {
+        let pixels = || self.frames.iter().flat_map(|frame| frame.chunks_exact(4));
+
+        let mut colors: HashMap<&[u8], u8> = HashMap::new();
+        for pixel in pixels() {
+            if colors.len() > 256 {
+                break;
+            }
+            colors.entry(pixel).or_default();
+        }
+        if colors.len() > 256 {
+            if pixels().any(|pixel| pixel[3] != u8::MAX) {
+                return None;
+            }
+            return Some(Pixels {
+                width: self.width,
+                height: self.height,
+                color: png::ColorType::Rgb,
+                depth: png::BitDepth::Eight,
+                palette: None,
+                frames: self
+                    .frames
+                    .iter()
+                    .map(|frame| {
+                        frame
+                            .chunks_exact(4)
+                            .flat_map(|pixel| &pixel[..3])
+                            .copied()
+                            .collect()
+                    })
+                    .collect(),
+            });
+        }
+
+        // Translucent colors go first, so the tRNS chunk only lists their alpha
+        let mut palette: Vec<&[u8]> = colors.keys().copied().collect();
+        palette.sort_by_key(|color| (color[3] == u8::MAX, *color));
+        for (index, color) in palette.iter().enumerate() {
+            colors.insert(color, index as u8);
+        }
+        let plte = palette
+            .iter()
+            .flat_map(|color| &color[..3])
+            .copied()
+            .collect();
+        let trns = palette
+            .iter()
+            .map(|color| color[3])
+            .take_while(|&alpha| alpha != u8::MAX)
+            .collect();
+
+        let bits = match palette.len() {
+            0..=2 => 1,
+            3..=4 => 2,
+            5..=16 => 4,
+            _ => 8,
+        };
+        let row_len = (self.width as usize * bits).div_ceil(8);
+        let frames = self
+            .frames
+            .iter()
+            .map(|frame| {
+                let mut packed = vec![0; row_len * self.height as usize];
+                let width = self.width as usize;
+                for (i, pixel) in frame.chunks_exact(4).enumerate() {
+                    let (row, column) = (i / width, i % width);
+                    let bit = column * bits;
+                    let shift = 8 - bits - bit % 8;
+                    packed[row * row_len + bit / 8] |= colors[pixel] << shift;
+                }
+                packed
+            })
+            .collect();
+
+        Some(Pixels {
+            width: self.width,
+            height: self.height,
+            color: png::ColorType::Indexed,
+            depth: match bits {
+                1 => png::BitDepth::One,
+                2 => png::BitDepth::Two,
+                4 => png::BitDepth::Four,
+                _ => png::BitDepth::Eight,
+            },
+            palette: Some((plte, trns)),
+            frames,
+        })
+    }
+
+    fn encode(
+        &self,
+        delays_ms: &[u32],
+        compression: PngCompression,
+        filter: PngFilter,
+    ) -> Result<Vec<u8>, NftgenError> {
+        let mut encoded = vec![];
+        self.write(&mut encoded, delays_ms, compression, filter)?;
+        Ok(encoded)
+    }
+
+    fn write<W: Write>(
+        &self,
+        w: W,
+        delays_ms: &[u32],
+        compression: PngCompression,
+        filter: PngFilter,
+    ) -> Result<(), NftgenError> {
+        let mut encoder = png::Encoder::new(w, self.width, self.height);
+        encoder.set_color(self.color);
+        encoder.set_depth(self.depth);
+        encoder.set_compression(compression.into());
+        match filter {
+            PngFilter::Adaptive => encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive),
+            PngFilter::None => encoder.set_filter(png::FilterType::NoFilter),
+            PngFilter::Sub => encoder.set_filter(png::FilterType::Sub),
+            PngFilter::Up => encoder.set_filter(png::FilterType::Up),
+            PngFilter::Avg => encoder.set_filter(png::FilterType::Avg),
+            PngFilter::Paeth => encoder.set_filter(png::FilterType::Paeth),
+        }
+        if let Some((plte, trns)) = &self.palette {
+            encoder.set_palette(plte.as_slice());
+            if !trns.is_empty() {
+                encoder.set_trns(trns.as_slice());
+            }
+        }
+        if self.frames.len() > 1 {
+            encoder.set_animated(self.frames.len() as u32, 0)?;
+        }
+
+        let mut writer = encoder.write_header()?;
+        for (i, frame) in self.frames.iter().enumerate() {
+            if self.frames.len() > 1 {
+                let (delay_num, delay_den) = apng_delay(delays_ms[i]);
+                writer.set_frame_delay(delay_num, delay_den)?;
+            }
+            writer.write_image_data(frame)?;
+        }
+        Ok(writer.finish()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nft::tests::fixture::Fixture;
+
+    /// 32x16 image of 512 colors
+    fn gradient(alpha: u8) -> Image {
+        let data = (0..16)
+            .flat_map(|y| (0..32).flat_map(move |x| [x * 8, y * 16, 0, alpha]))
+            .collect();
+        Image::new(data, 4, 32, 16)
+    }
+
+    fn decode(png: &[u8]) -> (png::ColorType, png::BitDepth, Image) {
+        let fixture = Fixture::blank("");
+        let path = fixture.path.join("image.png");
+        std::fs::write(&path, png).unwrap();
+        let reader = png::Decoder::new(png).read_info().unwrap();
+        let (color_type, bit_depth) = (reader.info().color_type, reader.info().bit_depth);
+        (color_type, bit_depth, Image::read(&path).unwrap())
+    }
+
+    #[test]
+    fn reduces_opaque_images_to_rgb() {
+        let image = gradient(u8::MAX);
+        let png = encode_png(&[&image], &[], &PngOptions::default()).unwrap();
+        let (color_type, _, decoded) = decode(&png);
+        assert_eq!(color_type, png::ColorType::Rgb);
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn keeps_translucent_images_of_many_colors_rgba() {
+        let image = gradient(128);
+        let png = encode_png(&[&image], &[], &PngOptions::default()).unwrap();
+        let (color_type, _, decoded) = decode(&png);
+        assert_eq!(color_type, png::ColorType::Rgba);
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn reduces_few_colors_to_palette() {
+        let image = Image::read(Fixture::source_path("palette.png")).unwrap();
+        let png = encode_png(&[&image], &[], &PngOptions::default()).unwrap();
+        let (color_type, bit_depth, decoded) = decode(&png);
+        assert_eq!(color_type, png::ColorType::Indexed);
+        assert_eq!(bit_depth, png::BitDepth::Two);
+        assert_eq!(decoded, image);
+
+        let image = Image::read(Fixture::source_path("composite_expected.png")).unwrap();
+        let png = encode_png(&[&image], &[], &PngOptions::default()).unwrap();
+        assert_eq!(decode(&png).2, image);
+    }
+
+    #[test]
+    fn keeps_rgba_without_color_reduction() {
+        let image = Image::read(Fixture::source_path("palette.png")).unwrap();
+        let options = PngOptions {
+            reduce_colors: false,
+            ..PngOptions::default()
+        };
+        let png = encode_png(&[&image], &[], &options).unwrap();
+        let (color_type, _, decoded) = decode(&png);
+        assert_eq!(color_type, png::ColorType::Rgba);
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn every_compression_and_filter_is_lossless() {
+        let image = gradient(200);
+        for compression in [
+            PngCompression::Fast,
+            PngCompression::Default,
+            PngCompression::Best,
+        ] {
+            for filter in PngFilter::ALL {
+                let options = PngOptions {
+                    compression,
+                    filter,
+                    ..PngOptions::default()
+                };
+                let png = encode_png(&[&image], &[], &options).unwrap();
+                assert_eq!(decode(&png).2, image, "{:?} {:?}", compression, filter);
+            }
+        }
+    }
+
+    #[test]
+    fn optimize_finds_smallest_encoding() {
+        let image = Image::read(Fixture::source_path("composite_expected.png")).unwrap();
+        let optimized = PngOptions {
+            optimize: true,
+            ..PngOptions::default()
+        };
+        let png = encode_png(&[&image], &[], &optimized).unwrap();
+        assert_eq!(decode(&png).2, image);
+
+        for reduce_colors in [true, false] {
+            for filter in PngFilter::ALL {
+                let options = PngOptions {
+                    compression: PngCompression::Best,
+                    filter,
+                    reduce_colors,
+                    optimize: false,
+                };
+                let other = encode_png(&[&image], &[], &options).unwrap();
+                assert!(png.len() <= other.len());
+            }
+        }
+    }
+}
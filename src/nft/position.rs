@@ -0,0 +1,122 @@
+use std::str::FromStr;
+
+use crate::NftgenError;
+
+/// Where a layer smaller or larger than the canvas is placed on it
+/// Written as an anchor, e.g. `bottom` or `top-left`, or as an `<x>:<y>` offset in pixels
+/// from the top left corner of the canvas, e.g. `120:-16`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    Anchor(Anchor),
+    Offset(i64, i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Position {
+    /// Offset of the top left corner of a `sprite` sized layer on a `canvas`, as `(width, height)`
+    pub fn offset(&self, canvas: (u32, u32), sprite: (u32, u32)) -> (i64, i64) {
+        let free = |canvas: u32, sprite: u32| canvas as i64 - sprite as i64;
+        let (free_x, free_y) = (free(canvas.0, sprite.0), free(canvas.1, sprite.1));
+        match self {
+            Position::Offset(x, y) => (*x, *y),
+            Position::Anchor(anchor) => {
+                let (x, y) = match anchor {
+                    Anchor::TopLeft => (0, 0),
+                    Anchor::Top => (1, 0),
+                    Anchor::TopRight => (2, 0),
+                    Anchor::Left => (0, 1),
+                    Anchor::Center => (1, 1),
+                    Anchor::Right => (2, 1),
+                    Anchor::BottomLeft => (0, 2),
+                    Anchor::Bottom => (1, 2),
+                    Anchor::BottomRight => (2, 2),
+                };
+                (free_x * x / 2, free_y * y / 2)
+            }
+        }
+    }
+}
+
+impl FromStr for Position {
+    type Err = NftgenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let anchor = match s.to_lowercase().as_str() {
+            "top-left" => Anchor::TopLeft,
+            "top" => Anchor::Top,
+            "top-right" => Anchor::TopRight,
+            "left" => Anchor::Left,
+            "center" => Anchor::Center,
+            "right" => Anchor::Right,
+            "bottom-left" => Anchor::BottomLeft,
+            "bottom" => Anchor::Bottom,
+            "bottom-right" => Anchor::BottomRight,
+            _ => {
+                let offset = s
+                    .split_once(':')
+                    .and_then(|(x, y)| Some((x.parse().ok()?, y.parse().ok()?)));
+                return match offset {
+                    Some((x, y)) => Ok(Position::Offset(x, y)),
+                    None => Err(NftgenError::InvalidPosition(s.to_string())),
+                };
+            }
+        };
+        Ok(Position::Anchor(anchor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_position() {
+        assert_eq!(
+            "bottom".parse::<Position>().unwrap(),
+            Position::Anchor(Anchor::Bottom)
+        );
+        assert_eq!(
+            "Top-Left".parse::<Position>().unwrap(),
+            Position::Anchor(Anchor::TopLeft)
+        );
+        assert_eq!(
+            "120:-16".parse::<Position>().unwrap(),
+            Position::Offset(120, -16)
+        );
+        assert!(matches!(
+            "middle".parse::<Position>(),
+            Err(NftgenError::InvalidPosition(_))
+        ));
+        assert!("12:".parse::<Position>().is_err());
+    }
+
+    #[test]
+    fn offset() {
+        let canvas = (100, 50);
+        let sprite = (20, 10);
+        let offset = |s: &str| s.parse::<Position>().unwrap().offset(canvas, sprite);
+
+        assert_eq!(offset("top-left"), (0, 0));
+        assert_eq!(offset("center"), (40, 20));
+        assert_eq!(offset("bottom"), (40, 40));
+        assert_eq!(offset("right"), (80, 20));
+        assert_eq!(offset("5:-3"), (5, -3));
+        // Sprites larger than the canvas are cropped around the anchor
+        assert_eq!(
+            Position::Anchor(Anchor::Center).offset((10, 10), (20, 20)),
+            (-5, -5)
+        );
+    }
+}
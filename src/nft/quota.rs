@@ -0,0 +1,544 @@
+use std::collections::{HashMap, HashSet};
+use std::iter::repeat_n;
+
+use rand::prelude::*;
+
+use crate::{
+    collection_rng, count_combinations, layers_dna, linked_groups, links_agree, Dna, LayerGroup,
+    NftgenError, PickedLayer, Rules, TokenPlan,
+};
+
+/// Number of swaps tried per NFT before giving up on meeting the quotas
+const MAX_SWAPS_PER_TOKEN: usize = 200;
+
+/// Minimum number of swaps tried, small collections are often tightly constrained
+const MIN_SWAPS: usize = 200_000;
+
+/// How layer weights turn into the number of NFTs that get each layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum Allocation {
+    /// Weights are relative probabilities, so counts vary around their expected value
+    Weighted,
+    /// Weights are exact counts or percentages, every layer appears exactly its share of NFTs
+    Exact,
+}
+
+/// Splits `total` into integer parts proportional to `weights` using the largest remainder method
+/// The parts always sum up to `total`, unless all weights are zero
+pub fn apportion(weights: &[f64], total: usize) -> Vec<usize> {
+    let weight_sum: f64 = weights.iter().sum();
+    if weight_sum <= 0.0 {
+        return vec![0; weights.len()];
+    }
+
+    let shares: Vec<f64> = weights
+        .iter()
+        .map(|weight| weight / weight_sum * total as f64)
+        .collect();
+    let mut parts: Vec<usize> = shares.iter().map(|share| share.floor() as usize).collect();
+
+    let mut by_remainder: Vec<usize> = (0..weights.len()).collect();
+    by_remainder.sort_by(|&a, &b| {
+        let remainder = |i: usize| shares[i] - parts[i] as f64;
+        remainder(b)
+            .partial_cmp(&remainder(a))
+            .unwrap()
+            .then(a.cmp(&b))
+    });
+    let missing = total - parts.iter().sum::<usize>();
+    for &i in by_remainder.iter().take(missing) {
+        parts[i] += 1;
+    }
+    parts
+}
+
+/// Picks layers for `num` NFTs such that every layer is used exactly as many times as its quota,
+/// no two NFTs share the same DNA and every NFT satisfies `rules`
+///
+/// Each layer group is turned into a shuffled bag of its quotas, and NFTs with duplicate DNA or
+/// broken rules are repaired by swapping values of a group between NFTs, which keeps the quotas intact
+pub fn plan_tokens_exact<'a>(
+    layer_groups: &'a [LayerGroup],
+    rules: &Rules,
+    num: usize,
+    seed: u64,
+) -> Result<Vec<TokenPlan<'a>>, NftgenError> {
+    plan_tokens_exact_excluding(layer_groups, rules, num, seed, &HashSet::new())
+}
+
+/// Like `plan_tokens_exact`, without repeating the DNA of `taken` NFTs, e.g. those of other
+/// editions
+pub fn plan_tokens_exact_excluding<'a>(
+    layer_groups: &'a [LayerGroup],
+    rules: &Rules,
+    num: usize,
+    seed: u64,
+    taken: &HashSet<Dna>,
+) -> Result<Vec<TokenPlan<'a>>, NftgenError> {
+    let available = count_combinations(layer_groups);
+    if num as u128 > available {
+        return Err(NftgenError::NotEnoughCombinations {
+            requested: num,
+            available,
+        });
+    }
+
+    let quotas = layer_groups
+        .iter()
+        .map(|group| group.quotas(num))
+        .collect::<Result<Vec<_>, _>>()?;
+    check_quotas(layer_groups, rules, &quotas)?;
+
+    let mut rng = collection_rng(seed);
+    let columns: Vec<Vec<PickedLayer>> = quotas
+        .iter()
+        .map(|group_quotas| {
+            let mut bag: Vec<_> = group_quotas
+                .iter()
+                .flat_map(|&(pick, count)| repeat_n(pick, count))
+                .collect();
+            bag.shuffle(&mut rng);
+            bag
+        })
+        .collect();
+    let tokens = (0..num)
+        .map(|i| columns.iter().map(|column| column[i]).collect())
+        .collect();
+
+    let mut assignment = Assignment::new(tokens, rules, taken);
+    assignment.repair(&mut rng, (MAX_SWAPS_PER_TOKEN * num).max(MIN_SWAPS));
+
+    if assignment.cost > 0 {
+        let conflicting: Vec<usize> = (0..num).filter(|&i| assignment.is_conflicting(i)).collect();
+        let examples: Vec<String> = conflicting
+            .iter()
+            .take(3)
+            .map(|&i| describe(&assignment.tokens[i]))
+            .collect();
+        return Err(NftgenError::QuotaUnmet(format!(
+            "{} NFTs are left with duplicate traits or traits that break the layer rules, e.g. {}",
+            conflicting.len(),
+            examples.join("; ")
+        )));
+    }
+
+    Ok(assignment
+        .tokens
+        .into_iter()
+        .zip(assignment.dnas)
+        .enumerate()
+        .map(|(id, (layers, dna))| TokenPlan {
+            id: id as u32,
+            dna,
+            layers,
+        })
+        .collect())
+}
+
+/// Reports every quota that can never be met, before trying to place any of them
+/// Groups linked on a variant that NFTs cannot go without need the same count of every variant
+fn check_quotas(
+    layer_groups: &[LayerGroup],
+    rules: &Rules,
+    quotas: &[Vec<(PickedLayer, usize)>],
+) -> Result<(), NftgenError> {
+    let mut unmet = vec![];
+    for (index, group_quotas) in quotas.iter().enumerate() {
+        let capacity = layer_groups
+            .iter()
+            .enumerate()
+            .filter(|&(other, _)| other != index)
+            .map(|(_, group)| group.num_choices() as u128)
+            .fold(1u128, |total, len| total.saturating_mul(len));
+
+        for (pick, count) in group_quotas.iter().filter(|(_, count)| *count > 0) {
+            log::info!(
+                "Quota for {}/{}: {} NFTs",
+                pick.layer_type,
+                pick.name(),
+                count
+            );
+            if *count as u128 > capacity {
+                unmet.push(format!(
+                    "{}/{} is needed for {} NFTs but only {} unique combinations include it",
+                    pick.layer_type,
+                    pick.name(),
+                    count,
+                    capacity
+                ));
+            } else if !rules.can_pick(layer_groups, (pick.layer_type, pick.name())) {
+                unmet.push(format!(
+                    "{}/{} is needed for {} NFTs but the layer rules never allow it",
+                    pick.layer_type,
+                    pick.name(),
+                    count
+                ));
+            }
+        }
+    }
+
+    for (link, groups) in linked_groups(layer_groups) {
+        let required: Vec<_> = layer_groups
+            .iter()
+            .zip(quotas)
+            .filter(|(group, _)| group.link() == Some(link) && !group.is_optional())
+            .collect();
+        let variant_count = |group_quotas: &[(PickedLayer, usize)], variant| -> usize {
+            group_quotas
+                .iter()
+                .filter(|(pick, _)| pick.linked_variant() == Some((link, variant)))
+                .map(|(_, count)| count)
+                .sum()
+        };
+        for variant in groups[0].variants() {
+            for pair in required.windows(2) {
+                let ((first, first_quotas), (second, second_quotas)) = (pair[0], pair[1]);
+                let first_count = variant_count(first_quotas, variant);
+                let second_count = variant_count(second_quotas, variant);
+                if first_count != second_count {
+                    unmet.push(format!(
+                        "{}/{} is needed for {} NFTs in {} but {} NFTs in {}",
+                        link,
+                        variant,
+                        first_count,
+                        first.layer_type,
+                        second_count,
+                        second.layer_type
+                    ));
+                }
+            }
+        }
+    }
+
+    if unmet.is_empty() {
+        Ok(())
+    } else {
+        Err(NftgenError::QuotaUnmet(unmet.join("; ")))
+    }
+}
+
+fn describe(picks: &[PickedLayer]) -> String {
+    picks
+        .iter()
+        .map(|pick| match pick.linked_variant() {
+            Some((_, variant)) => format!("{}/{}@{}", pick.layer_type, pick.name(), variant),
+            None => format!("{}/{}", pick.layer_type, pick.name()),
+        })
+        .collect::<Vec<_>>()
+        .join(" + ")
+}
+
+/// NFTs whose layers are being shuffled around until all of them are unique and valid
+/// `cost` counts NFTs that break the rules or mix variants of linked layers, plus NFTs that duplicate the DNA of another NFT
+struct Assignment<'a, 'r> {
+    tokens: Vec<Vec<PickedLayer<'a>>>,
+    dnas: Vec<Dna>,
+    valid: Vec<bool>,
+    dna_counts: HashMap<Dna, usize>,
+    cost: usize,
+    rules: &'r Rules,
+}
+
+impl<'a, 'r> Assignment<'a, 'r> {
+    /// `taken` DNAs count as used once already, so NFTs with those DNAs are duplicates
+    fn new(tokens: Vec<Vec<PickedLayer<'a>>>, rules: &'r Rules, taken: &HashSet<Dna>) -> Self {
+        let mut assignment = Assignment {
+            dnas: tokens.iter().map(|picks| layers_dna(picks)).collect(),
+            valid: vec![true; tokens.len()],
+            dna_counts: taken.iter().map(|dna| (dna.clone(), 1)).collect(),
+            cost: 0,
+            tokens,
+            rules,
+        };
+        for i in 0..assignment.tokens.len() {
+            assignment.attach(i);
+        }
+        assignment
+    }
+
+    fn is_conflicting(&self, i: usize) -> bool {
+        !self.valid[i] || self.dna_counts[&self.dnas[i]] > 1
+    }
+
+    /// Swaps values of random layer groups between conflicting NFTs and random other NFTs,
+    /// keeping every swap that does not increase the cost
+    /// Gives up early once the cost has not improved for a tenth of `max_swaps`
+    fn repair<R: Rng + ?Sized>(&mut self, rng: &mut R, max_swaps: usize) {
+        let num_groups = self.tokens.first().map_or(0, |picks| picks.len());
+        let mut conflicting: Vec<usize> = (0..self.tokens.len())
+            .filter(|&i| self.is_conflicting(i))
+            .collect();
+
+        let mut best_cost = self.cost;
+        let mut swaps_since_improvement = 0;
+        for _ in 0..max_swaps {
+            if self.cost == 0 || swaps_since_improvement > max_swaps / 10 {
+                break;
+            }
+            swaps_since_improvement += 1;
+            if self.cost < best_cost {
+                best_cost = self.cost;
+                swaps_since_improvement = 0;
+            }
+            if conflicting.is_empty() {
+                conflicting = (0..self.tokens.len())
+                    .filter(|&i| self.is_conflicting(i))
+                    .collect();
+            }
+
+            let index = rng.gen_range(0..conflicting.len());
+            let i = conflicting[index];
+            if !self.is_conflicting(i) {
+                conflicting.swap_remove(index);
+                continue;
+            }
+
+            let group = rng.gen_range(0..num_groups);
+            let j = rng.gen_range(0..self.tokens.len());
+            if i == j || self.tokens[i][group] == self.tokens[j][group] {
+                continue;
+            }
+
+            let cost = self.cost;
+            self.swap(i, j, group);
+            if self.cost > cost {
+                self.swap(i, j, group);
+            } else if self.is_conflicting(j) {
+                conflicting.push(j);
+            }
+        }
+    }
+
+    fn swap(&mut self, i: usize, j: usize, group: usize) {
+        self.detach(i);
+        self.detach(j);
+        let pick = self.tokens[i][group];
+        self.tokens[i][group] = self.tokens[j][group];
+        self.tokens[j][group] = pick;
+        self.attach(i);
+        self.attach(j);
+    }
+
+    fn detach(&mut self, i: usize) {
+        if !self.valid[i] {
+            self.cost -= 1;
+        }
+        let count = self.dna_counts.get_mut(&self.dnas[i]).unwrap();
+        if *count > 1 {
+            self.cost -= 1;
+        }
+        *count -= 1;
+    }
+
+    fn attach(&mut self, i: usize) {
+        let picks = &self.tokens[i];
+        self.valid[i] = picks.iter().any(|pick| pick.layer.is_some())
+            && links_agree(picks)
+            && self.rules.allows_all(picks);
+        if !self.valid[i] {
+            self.cost += 1;
+        }
+
+        self.dnas[i] = layers_dna(picks);
+        let count = self.dna_counts.entry(self.dnas[i].clone()).or_insert(0);
+        if *count > 0 {
+            self.cost += 1;
+        }
+        *count += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_layer_groups;
+    use crate::nft::tests::fixture::Fixture;
+
+    fn count_layer(plan: &[TokenPlan], group: usize, name: &str) -> usize {
+        plan.iter()
+            .filter(|token| token.layers[group].name() == name)
+            .count()
+    }
+
+    #[test]
+    fn apportion_works() {
+        assert_eq!(apportion(&[1.0, 1.0, 2.0], 8), vec![2, 2, 4]);
+        assert_eq!(apportion(&[60.0, 30.0, 10.0], 10), vec![6, 3, 1]);
+        assert_eq!(apportion(&[1.0, 1.0, 1.0], 10), vec![4, 3, 3]);
+        assert_eq!(apportion(&[0.0, 0.0], 10), vec![0, 0]);
+        assert_eq!(apportion(&[1.0, 2.0], 0), vec![0, 0]);
+    }
+
+    #[test]
+    fn plan_tokens_exact_meets_quotas() {
+        let layer_dirs = &["layer1", "layer2"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        let layer_groups = get_layer_groups(&fixture.path, layer_dirs).unwrap();
+
+        // Weights 1..=9 sum up to 45
+        let plan = plan_tokens_exact(&layer_groups, &Rules::default(), 45, 11).unwrap();
+
+        for group in 0..2 {
+            for i in 1..10 {
+                assert_eq!(count_layer(&plan, group, &format!("image{}", i)), i);
+            }
+        }
+        let dnas: HashSet<_> = plan.iter().map(|token| token.dna.clone()).collect();
+        assert_eq!(dnas.len(), 45);
+        assert_eq!(
+            plan,
+            plan_tokens_exact(&layer_groups, &Rules::default(), 45, 11).unwrap()
+        );
+    }
+
+    #[test]
+    fn plan_tokens_exact_follows_rules() {
+        let layer_dirs = &["layer1", "layer2"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        let layer_groups = get_layer_groups(&fixture.path, layer_dirs).unwrap();
+        let rules = Rules::new(
+            vec![(
+                "layer1/image8".parse().unwrap(),
+                "layer2/image1".parse().unwrap(),
+            )],
+            vec![],
+        );
+
+        let plan = plan_tokens_exact(&layer_groups, &rules, 45, 5).unwrap();
+
+        assert_eq!(count_layer(&plan, 0, "image8"), 8);
+        assert_eq!(count_layer(&plan, 1, "image1"), 1);
+        for token in plan {
+            assert!(token.layers[0].name() != "image8" || token.layers[1].name() != "image1");
+        }
+    }
+
+    #[test]
+    fn plan_tokens_exact_excluding_skips_taken_dna() {
+        let fixture = Fixture::blank("minimal.png");
+        let layers = ["a.png", "b.png", "c.png", "d.png", "e.png"];
+        fixture.create_layer_files("layer1", &layers);
+        fixture.create_layer_files("layer2", &layers);
+        let layer_groups = get_layer_groups(&fixture.path, &["layer1", "layer2"]).unwrap();
+
+        let taken: HashSet<_> = plan_tokens_exact(&layer_groups, &Rules::default(), 10, 5)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.dna)
+            .collect();
+        let plan =
+            plan_tokens_exact_excluding(&layer_groups, &Rules::default(), 10, 5, &taken).unwrap();
+        assert_eq!(plan.len(), 10);
+        assert!(plan.iter().all(|token| !taken.contains(&token.dna)));
+    }
+
+    #[test]
+    fn plan_tokens_exact_excluding_fills_editions_with_other_layers() {
+        let fixture = Fixture::blank("minimal.png");
+        for edition in ["genesis", "expansion"] {
+            std::fs::create_dir(fixture.path.join(edition)).unwrap();
+            for layer_type in ["Body", "Eyes"] {
+                let files = [format!("{}1.png", edition), format!("{}2.png", edition)];
+                let files: Vec<&str> = files.iter().map(String::as_str).collect();
+                fixture.create_layer_files(&format!("{}/{}", edition, layer_type), &files);
+            }
+        }
+        let genesis = get_layer_groups(fixture.path.join("genesis"), &["Body", "Eyes"]).unwrap();
+        let expansion =
+            get_layer_groups(fixture.path.join("expansion"), &["Body", "Eyes"]).unwrap();
+
+        let taken: HashSet<_> = plan_tokens_exact(&genesis, &Rules::default(), 4, 5)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.dna)
+            .collect();
+        let plan =
+            plan_tokens_exact_excluding(&expansion, &Rules::default(), 4, 5, &taken).unwrap();
+        assert_eq!(plan.len(), 4);
+    }
+
+    #[test]
+    fn plan_tokens_exact_reports_unmet_quotas() {
+        let fixture = Fixture::blank("minimal.png");
+        fixture.create_layer_files("layer1", &["big#3.png", "small#1.png"]);
+        fixture.create_layer_files("layer2", &["left.png", "right.png"]);
+        let layer_groups = get_layer_groups(&fixture.path, &["layer1", "layer2"]).unwrap();
+        // big needs 3 NFTs, but layer2 only has 2 layers to pair it with
+        let result = plan_tokens_exact(&layer_groups, &Rules::default(), 4, 5);
+
+        match result {
+            Err(NftgenError::QuotaUnmet(message)) => {
+                assert!(message.contains("layer1/big"));
+                assert!(!message.contains("small"));
+                assert!(!message.contains("layer2"));
+            }
+            _ => panic!("expected unmet quotas"),
+        }
+    }
+
+    #[test]
+    fn plan_tokens_exact_rejects_uneven_quotas() {
+        let layer_dirs = &["layer1", "layer2"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        let layer_groups = get_layer_groups(&fixture.path, layer_dirs).unwrap();
+        // Weights 1..=9 sum up to 45, which does not divide 50 NFTs
+        let result = plan_tokens_exact(&layer_groups, &Rules::default(), 50, 5);
+        assert!(matches!(result, Err(NftgenError::QuotaUnmet(_))));
+    }
+
+    fn linked_layer_groups(fixture: &Fixture, hands: &[&str]) -> Vec<LayerGroup> {
+        let layer_types = ["body", "hands"];
+        let files = [
+            &[
+                "slim@dark#1.png",
+                "round@dark#1.png",
+                "slim@light#1.png",
+                "round@light#1.png",
+            ][..],
+            hands,
+        ];
+        layer_types
+            .iter()
+            .zip(files)
+            .map(|(layer_type, files)| {
+                let layer_path = fixture.create_layer_files(layer_type, files);
+                LayerGroup::new(layer_path, &layer_types)
+                    .unwrap()
+                    .with_link("Skin")
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plan_tokens_exact_links_variants() {
+        let fixture = Fixture::blank("minimal.png");
+        let layer_groups = linked_layer_groups(
+            &fixture,
+            &["open@dark#1.png", "fist@dark#1.png", "open@light#2.png"],
+        );
+
+        let plan = plan_tokens_exact(&layer_groups, &Rules::default(), 4, 5).unwrap();
+
+        for token in &plan {
+            assert!(links_agree(&token.layers));
+        }
+        assert_eq!(count_layer(&plan, 0, "slim"), 2);
+        assert_eq!(count_layer(&plan, 1, "open"), 3);
+    }
+
+    #[test]
+    fn plan_tokens_exact_reports_unequal_variant_quotas() {
+        let fixture = Fixture::blank("minimal.png");
+        let layer_groups = linked_layer_groups(&fixture, &["open@dark#1.png", "open@light#3.png"]);
+
+        match plan_tokens_exact(&layer_groups, &Rules::default(), 4, 5) {
+            Err(NftgenError::QuotaUnmet(message)) => {
+                assert!(
+                    message.contains("Skin/dark is needed for 2 NFTs in body but 1 NFTs in hands")
+                );
+            }
+            _ => panic!("expected unmet quotas"),
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::f64::consts::PI;
+
+use clap::ArgEnum;
+
+use crate::NftgenError;
+
+/// Resampling filter used to resize NFT images
+/// `nearest` keeps pixel art crisp when scaling by whole multiples, `bilinear` and `lanczos3`
+/// smooth painted art
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ArgEnum)]
+pub enum ResizeFilter {
+    Nearest,
+    Bilinear,
+    #[default]
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    /// Checks that images `width` pixels wide can be resized to `new_width` pixels
+    /// `nearest` only keeps pixels square when scaling by a whole multiple or divisor
+    pub fn check_scale(self, width: u32, new_width: u32) -> Result<(), NftgenError> {
+        if new_width == 0 {
+            return Err(NftgenError::InvalidSize(
+                "Widths must be at least 1 pixel".to_string(),
+            ));
+        }
+        if self == ResizeFilter::Nearest
+            && !new_width.is_multiple_of(width)
+            && !width.is_multiple_of(new_width)
+        {
+            return Err(NftgenError::InvalidSize(format!(
+                "nearest scales {} pixels wide images by whole multiples, but {} is not one",
+                width, new_width
+            )));
+        }
+        Ok(())
+    }
+
+    /// Radius of the filter kernel in source pixels when upscaling
+    fn support(self) -> f64 {
+        match self {
+            ResizeFilter::Nearest => 0.5,
+            ResizeFilter::Bilinear => 1.0,
+            ResizeFilter::Lanczos3 => 3.0,
+        }
+    }
+
+    fn kernel(self, x: f64) -> f64 {
+        let x = x.abs();
+        match self {
+            ResizeFilter::Nearest => (x < 0.5) as u8 as f64,
+            ResizeFilter::Bilinear => (1.0 - x).max(0.0),
+            ResizeFilter::Lanczos3 if x < 3.0 => sinc(x) * sinc(x / 3.0),
+            ResizeFilter::Lanczos3 => 0.0,
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Resamples 8 bit pixels from `size` to `new_size`, both `(width, height)`
+/// Pixels with an alpha channel (2 or 4 bytes per pixel) are filtered as premultiplied colors,
+/// so transparent pixels do not bleed dark fringes into their neighbours
+pub(crate) fn resample(
+    data: &[u8],
+    bytes_per_pixel: usize,
+    size: (u32, u32),
+    new_size: (u32, u32),
+    filter: ResizeFilter,
+) -> Vec<u8> {
+    if filter == ResizeFilter::Nearest {
+        return nearest(data, bytes_per_pixel, size, new_size);
+    }
+
+    let has_alpha = bytes_per_pixel == 2 || bytes_per_pixel == 4;
+    let mut pixels: Vec<f32> = data.iter().map(|&value| value as f32 / 255.0).collect();
+    if has_alpha {
+        for pixel in pixels.chunks_exact_mut(bytes_per_pixel) {
+            let (color, alpha) = pixel.split_at_mut(bytes_per_pixel - 1);
+            color.iter_mut().for_each(|value| *value *= alpha[0]);
+        }
+    }
+
+    let (width, height) = (size.0 as usize, size.1 as usize);
+    let (new_width, new_height) = (new_size.0 as usize, new_size.1 as usize);
+
+    // Resize rows, then columns
+    let mut horizontal = vec![0.0; new_width * height * bytes_per_pixel];
+    let columns = contributions(width, new_width, filter);
+    for y in 0..height {
+        for (x, (start, weights)) in columns.iter().enumerate() {
+            let out = (y * new_width + x) * bytes_per_pixel;
+            for (i, weight) in weights.iter().enumerate() {
+                let src = (y * width + start + i) * bytes_per_pixel;
+                for c in 0..bytes_per_pixel {
+                    horizontal[out + c] += pixels[src + c] * weight;
+                }
+            }
+        }
+    }
+    let mut resized = vec![0.0; new_width * new_height * bytes_per_pixel];
+    for (y, (start, weights)) in contributions(height, new_height, filter).iter().enumerate() {
+        for (i, weight) in weights.iter().enumerate() {
+            let src_row = (start + i) * new_width * bytes_per_pixel;
+            let out_row = y * new_width * bytes_per_pixel;
+            for x in 0..new_width * bytes_per_pixel {
+                resized[out_row + x] += horizontal[src_row + x] * weight;
+            }
+        }
+    }
+
+    let to_u8 = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    resized
+        .chunks_exact(bytes_per_pixel)
+        .flat_map(|pixel| {
+            let mut out = [0; 4];
+            let alpha = pixel[bytes_per_pixel - 1].clamp(0.0, 1.0);
+            for (c, value) in pixel.iter().enumerate() {
+                out[c] = match has_alpha && c < bytes_per_pixel - 1 {
+                    true if alpha > 0.0 => to_u8(value / alpha),
+                    true => 0,
+                    false => to_u8(*value),
+                };
+            }
+            out.into_iter().take(bytes_per_pixel)
+        })
+        .collect()
+}
+
+/// Source pixels contributing to each output pixel along one axis, as the index of the first
+/// source pixel and the normalized weights of it and the following pixels
+fn contributions(len: usize, new_len: usize, filter: ResizeFilter) -> Vec<(usize, Vec<f32>)> {
+    let scale = len as f64 / new_len as f64;
+    // Widen the kernel when downscaling so every source pixel is taken into account
+    let filter_scale = scale.max(1.0);
+    let support = filter.support() * filter_scale;
+
+    (0..new_len)
+        .map(|out| {
+            let center = (out as f64 + 0.5) * scale;
+            let start = (center - support).floor().max(0.0) as usize;
+            let end = ((center + support).ceil() as usize).min(len);
+            let mut weights: Vec<f32> = (start..end)
+                .map(|src| filter.kernel((src as f64 + 0.5 - center) / filter_scale) as f32)
+                .collect();
+            let sum: f32 = weights.iter().sum();
+            if sum != 0.0 {
+                weights.iter_mut().for_each(|weight| *weight /= sum);
+            }
+            (start, weights)
+        })
+        .collect()
+}
+
+fn nearest(data: &[u8], bytes_per_pixel: usize, size: (u32, u32), new_size: (u32, u32)) -> Vec<u8> {
+    let source = |out: u32, len: u32, new_len: u32| {
+        ((out as u64 * 2 + 1) * len as u64 / (new_len as u64 * 2)) as usize
+    };
+    let mut resized =
+        Vec::with_capacity(new_size.0 as usize * new_size.1 as usize * bytes_per_pixel);
+    for y in 0..new_size.1 {
+        let row = source(y, size.1, new_size.1) * size.0 as usize;
+        for x in 0..new_size.0 {
+            let src = (row + source(x, size.0, new_size.0)) * bytes_per_pixel;
+            resized.extend_from_slice(&data[src..src + bytes_per_pixel]);
+        }
+    }
+    resized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_upscales_by_whole_multiples() {
+        let data = [[1, 1, 1, 255], [2, 2, 2, 0]].concat();
+
+        let resized = resample(&data, 4, (2, 1), (4, 2), ResizeFilter::Nearest);
+        let row = [[1, 1, 1, 255], [1, 1, 1, 255], [2, 2, 2, 0], [2, 2, 2, 0]].concat();
+        assert_eq!(resized, [row.clone(), row].concat());
+    }
+
+    #[test]
+    fn check_scale_rejects_uneven_nearest_scaling() {
+        assert!(ResizeFilter::Nearest.check_scale(32, 1024).is_ok());
+        assert!(ResizeFilter::Nearest.check_scale(1024, 256).is_ok());
+        assert!(ResizeFilter::Lanczos3.check_scale(32, 1000).is_ok());
+        for (filter, new_width) in [(ResizeFilter::Nearest, 1000), (ResizeFilter::Lanczos3, 0)] {
+            assert!(matches!(
+                filter.check_scale(32, new_width),
+                Err(NftgenError::InvalidSize(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn uniform_images_stay_uniform() {
+        let data = [10, 200, 30, 255].repeat(7 * 5);
+
+        for filter in [ResizeFilter::Bilinear, ResizeFilter::Lanczos3] {
+            for new_size in [(3, 2), (7, 5), (20, 11)] {
+                let resized = resample(&data, 4, (7, 5), new_size, filter);
+                assert_eq!(
+                    resized,
+                    [10, 200, 30, 255].repeat((new_size.0 * new_size.1) as usize),
+                    "{:?} {:?}",
+                    filter,
+                    new_size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn transparent_pixels_do_not_darken_colors() {
+        let data = [[255, 0, 0, 255], [0, 0, 0, 0]].concat();
+
+        let resized = resample(&data, 4, (2, 1), (1, 1), ResizeFilter::Bilinear);
+        assert_eq!(resized, vec![255, 0, 0, 128]);
+    }
+
+    #[test]
+    fn downscales_by_filtering_neighbours() {
+        let data = [0, 0, 255, 255];
+
+        // Output pixels cover source pixels at weights 0.25, 0.75, 0.75 (and 0.25 if any)
+        let resized = resample(&data, 1, (4, 1), (2, 1), ResizeFilter::Bilinear);
+        assert_eq!(resized, vec![36, 219]);
+    }
+}
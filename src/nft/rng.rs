@@ -0,0 +1,81 @@
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+/// Deterministic, portable RNG used for every random choice made while generating a token
+pub type TokenRng = ChaCha8Rng;
+
+/// Returns a fresh seed for runs where the user did not provide one
+pub fn random_seed() -> u64 {
+    rand::thread_rng().gen()
+}
+
+/// Derives the RNG for a single token from the run seed and the token id
+/// The result does not depend on the order in which tokens are generated,
+/// so parallel runs with the same seed produce identical collections
+pub fn token_rng(seed: u64, token_id: u32) -> TokenRng {
+    let mut rng_seed = [0u8; 32];
+    rng_seed[..8].copy_from_slice(&seed.to_le_bytes());
+    rng_seed[8..12].copy_from_slice(&token_id.to_le_bytes());
+    TokenRng::from_seed(rng_seed)
+}
+
+/// Derives the RNG for random choices that span the whole collection rather than one token
+pub fn collection_rng(seed: u64) -> TokenRng {
+    let mut rng_seed = [0u8; 32];
+    rng_seed[..8].copy_from_slice(&seed.to_le_bytes());
+    rng_seed[12] = 1;
+    TokenRng::from_seed(rng_seed)
+}
+
+/// Derives the seed the layers of an edition are picked with from the run seed
+/// The first edition keeps the run seed, so collections without editions stay the same
+pub fn edition_seed(seed: u64, edition: usize) -> u64 {
+    if edition == 0 {
+        return seed;
+    }
+    let mut rng_seed = [0u8; 32];
+    rng_seed[..8].copy_from_slice(&seed.to_le_bytes());
+    rng_seed[16..24].copy_from_slice(&(edition as u64).to_le_bytes());
+    rng_seed[13] = 1;
+    TokenRng::from_seed(rng_seed).gen()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_rng_is_deterministic() {
+        let a: Vec<u32> = token_rng(42, 7)
+            .sample_iter(rand::distributions::Standard)
+            .take(8)
+            .collect();
+        let b: Vec<u32> = token_rng(42, 7)
+            .sample_iter(rand::distributions::Standard)
+            .take(8)
+            .collect();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn token_rng_differs_per_token_and_seed() {
+        let base: u64 = token_rng(42, 7).gen();
+        assert_ne!(base, token_rng(42, 8).gen::<u64>());
+        assert_ne!(base, token_rng(43, 7).gen::<u64>());
+        // seed + id must not simply be added together
+        assert_ne!(token_rng(1, 0).gen::<u64>(), token_rng(0, 1).gen::<u64>());
+        assert_ne!(
+            collection_rng(42).gen::<u64>(),
+            token_rng(42, 0).gen::<u64>()
+        );
+    }
+
+    #[test]
+    fn edition_seed_differs_from_nearby_seeds() {
+        assert_eq!(edition_seed(42, 0), 42);
+        let seeds: std::collections::HashSet<_> = (40..45)
+            .flat_map(|seed| (0..5).map(move |edition| edition_seed(seed, edition)))
+            .collect();
+        assert_eq!(seeds.len(), 25);
+    }
+}
@@ -0,0 +1,433 @@
+use std::{fmt::Display, path::Path, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{
+    nft::link::allows_variant, Layer, LayerGroup, NftgenError, PickedLayer, NONE_LAYER_NAME,
+};
+
+/// A single trait within a layer group, written as `<layer_type>/<name>` e.g. `Hat/cap`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TraitRef {
+    pub layer_type: String,
+    pub name: String,
+}
+
+impl TraitRef {
+    pub fn new<S: Into<String>>(layer_type: S, name: S) -> Self {
+        TraitRef {
+            layer_type: layer_type.into(),
+            name: name.into(),
+        }
+    }
+
+    fn is(&self, (layer_type, name): (&str, &str)) -> bool {
+        self.layer_type == layer_type && self.name == name
+    }
+}
+
+impl FromStr for TraitRef {
+    type Err = NftgenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('/') {
+            Some((layer_type, name)) if !layer_type.is_empty() && !name.is_empty() => {
+                Ok(TraitRef::new(layer_type, name))
+            }
+            _ => Err(NftgenError::InvalidRule(format!(
+                "expected <layer_type>/<name>, found {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl TryFrom<String> for TraitRef {
+    type Error = NftgenError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for TraitRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.layer_type, self.name)
+    }
+}
+
+/// Declarative constraints on which traits may appear together in one NFT
+/// - ```excludes```: pairs of traits that never appear together
+/// - ```requires```: `[A, C]` means `A` is only picked together with `C`.
+///   Several requirements of `A` within the same layer group are alternatives
+///
+/// Rules files are JSON, e.g.
+/// `{"excludes": [["Hat/cap", "Hair/mohawk"]], "requires": [["Hat/cap", "Hair/short"]]}`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Rules {
+    #[serde(default)]
+    excludes: Vec<(TraitRef, TraitRef)>,
+    #[serde(default)]
+    requires: Vec<(TraitRef, TraitRef)>,
+}
+
+impl Rules {
+    pub fn new(excludes: Vec<(TraitRef, TraitRef)>, requires: Vec<(TraitRef, TraitRef)>) -> Self {
+        Rules { excludes, requires }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, NftgenError> {
+        let rules_json = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&rules_json)?)
+    }
+
+    /// Returns whether `candidate` can be added to the traits already `picked` for an NFT
+    /// Traits are `(layer_type, name)` pairs
+    pub fn allows(&self, picked: &[(&str, &str)], candidate: (&str, &str)) -> bool {
+        let excluded = self.excludes.iter().any(|(a, b)| {
+            (a.is(candidate) && picked.iter().any(|&p| b.is(p)))
+                || (b.is(candidate) && picked.iter().any(|&p| a.is(p)))
+        });
+
+        !excluded
+            && picked
+                .iter()
+                .all(|&p| self.satisfies(p, candidate) && self.satisfies(candidate, p))
+    }
+
+    /// Returns whether the layers picked for an NFT satisfy every rule
+    pub fn allows_all(&self, picks: &[PickedLayer]) -> bool {
+        (0..picks.len()).all(|k| {
+            let picked: Vec<_> = picks[..k]
+                .iter()
+                .map(|pick| (pick.layer_type, pick.name()))
+                .collect();
+            self.allows(&picked, (picks[k].layer_type, picks[k].name()))
+        })
+    }
+
+    /// Checks that every rule refers to an existing trait and that at least one
+    /// combination of layers satisfies all rules
+    pub fn validate(&self, layer_groups: &[LayerGroup]) -> Result<(), NftgenError> {
+        for trait_ref in self
+            .excludes
+            .iter()
+            .chain(self.requires.iter())
+            .flat_map(|(a, b)| [a, b])
+        {
+            let exists = layer_groups
+                .iter()
+                .filter(|group| group.layer_type == trait_ref.layer_type)
+                .any(|group| {
+                    (group.is_optional() && trait_ref.name == NONE_LAYER_NAME)
+                        || group
+                            .layers()
+                            .iter()
+                            .any(|layer| layer.name() == Some(trait_ref.name.as_str()))
+                });
+            if !exists {
+                return Err(NftgenError::InvalidRule(format!(
+                    "unknown trait {}",
+                    trait_ref
+                )));
+            }
+        }
+
+        if self.search(layer_groups, None) {
+            Ok(())
+        } else {
+            Err(NftgenError::UnsatisfiableRules)
+        }
+    }
+
+    /// Returns whether `trait_` appears in at least one combination of layers that satisfies the rules
+    pub fn can_pick(&self, layer_groups: &[LayerGroup], trait_: (&str, &str)) -> bool {
+        self.search(layer_groups, Some(trait_))
+    }
+
+    /// Whether `other` meets the requirements `trait_` has on the layer group of `other`
+    fn satisfies(&self, trait_: (&str, &str), other: (&str, &str)) -> bool {
+        let mut requirements = self
+            .requires
+            .iter()
+            .filter(|(a, b)| a.is(trait_) && b.layer_type == other.0)
+            .peekable();
+
+        requirements.peek().is_none() || requirements.any(|(_, b)| b.name == other.1)
+    }
+
+    /// Searches for any combination of layers that satisfies the rules and links and contains
+    /// `fixed`, if given. Gives up after `SEARCH_LIMIT` steps and assumes there is one
+    fn search(&self, layer_groups: &[LayerGroup], fixed: Option<(&str, &str)>) -> bool {
+        let mut search = Search {
+            rules: self,
+            layer_groups,
+            fixed,
+            picked: vec![],
+            variants: vec![],
+            steps: 0,
+        };
+        search.visit().unwrap_or_else(|| {
+            log::warn!(
+                "Gave up checking the layer rules after {} steps, assuming they can be satisfied",
+                SEARCH_LIMIT
+            );
+            true
+        })
+    }
+}
+
+/// Number of layers the rules search may try before it gives up
+const SEARCH_LIMIT: usize = 1_000_000;
+
+/// Depth first search over the layer groups that checks after every pick that each
+/// remaining group still has a layer the rules and links allow
+struct Search<'a, 'r> {
+    rules: &'r Rules,
+    layer_groups: &'a [LayerGroup],
+    fixed: Option<(&'r str, &'r str)>,
+    picked: Vec<(&'a str, &'a str)>,
+    variants: Vec<(&'a str, &'a str)>,
+    steps: usize,
+}
+
+impl<'a, 'r> Search<'a, 'r> {
+    /// Returns `None` once `SEARCH_LIMIT` is reached
+    fn visit(&mut self) -> Option<bool> {
+        let group = match self.layer_groups.get(self.picked.len()) {
+            Some(group) => group,
+            None => return Some(true),
+        };
+
+        let candidates: Vec<_> = self.candidates(group).collect();
+        for (layer, name) in candidates {
+            self.steps += 1;
+            if self.steps > SEARCH_LIMIT {
+                return None;
+            }
+            self.picked.push((group.layer_type.as_str(), name));
+            let variant = layer
+                .and_then(|layer| Some((layer.link.as_deref()?, layer.variant()?)))
+                .filter(|&(link, _)| !self.variants.iter().any(|&(other, _)| other == link));
+            self.variants.extend(variant);
+
+            let viable = self.layer_groups[self.picked.len()..]
+                .iter()
+                .all(|group| self.candidates(group).next().is_some());
+            if viable && self.visit()? {
+                return Some(true);
+            }
+
+            if variant.is_some() {
+                self.variants.pop();
+            }
+            self.picked.pop();
+        }
+        Some(false)
+    }
+
+    /// Layers of `group` that can be added to the layers picked so far, with their names.
+    /// `None` stands for skipping an optional group
+    fn candidates(
+        &self,
+        group: &'a LayerGroup,
+    ) -> impl Iterator<Item = (Option<&'a Layer>, &'a str)> + '_ {
+        let layer_type = group.layer_type.as_str();
+        group
+            .pickable_layers()
+            .filter(|layer| !layer.is_none() && allows_variant(&self.variants, layer))
+            .map(|layer| (Some(layer), layer.name().unwrap_or_default()))
+            .chain(group.is_optional().then_some((None, NONE_LAYER_NAME)))
+            .filter(move |&(_, name)| {
+                self.fixed
+                    .is_none_or(|fixed| fixed.0 != layer_type || fixed == (layer_type, name))
+                    && self.rules.allows(&self.picked, (layer_type, name))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_layer_groups;
+    use crate::nft::tests::fixture::Fixture;
+
+    fn trait_ref(s: &str) -> TraitRef {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_trait_ref() {
+        assert_eq!(trait_ref("Hat/cap"), TraitRef::new("Hat", "cap"));
+        assert!("Hat".parse::<TraitRef>().is_err());
+        assert!("/cap".parse::<TraitRef>().is_err());
+    }
+
+    #[test]
+    fn parse_rules_json() {
+        let rules: Rules = serde_json::from_str(
+            r#"{"excludes": [["Hat/cap", "Hair/mohawk"]], "requires": [["Hat/cap", "Hair/short"]]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            rules,
+            Rules::new(
+                vec![(trait_ref("Hat/cap"), trait_ref("Hair/mohawk"))],
+                vec![(trait_ref("Hat/cap"), trait_ref("Hair/short"))],
+            )
+        );
+    }
+
+    #[test]
+    fn excludes_works_in_both_directions() {
+        let rules = Rules::new(
+            vec![(trait_ref("Hat/cap"), trait_ref("Hair/mohawk"))],
+            vec![],
+        );
+
+        assert!(!rules.allows(&[("Hair", "mohawk")], ("Hat", "cap")));
+        assert!(!rules.allows(&[("Hat", "cap")], ("Hair", "mohawk")));
+        assert!(rules.allows(&[("Hair", "short")], ("Hat", "cap")));
+    }
+
+    #[test]
+    fn requires_works_in_both_directions() {
+        let rules = Rules::new(
+            vec![],
+            vec![
+                (trait_ref("Hat/cap"), trait_ref("Hair/short")),
+                (trait_ref("Hat/cap"), trait_ref("Hair/bald")),
+            ],
+        );
+
+        // Required trait picked after
+        assert!(!rules.allows(&[("Hat", "cap")], ("Hair", "mohawk")));
+        assert!(rules.allows(&[("Hat", "cap")], ("Hair", "short")));
+        assert!(rules.allows(&[("Hat", "cap")], ("Hair", "bald")));
+        // Required trait picked before
+        assert!(!rules.allows(&[("Hair", "mohawk")], ("Hat", "cap")));
+        assert!(rules.allows(&[("Hair", "short")], ("Hat", "cap")));
+        // Unrelated traits are unaffected
+        assert!(rules.allows(&[("Hair", "mohawk")], ("Hat", "beanie")));
+    }
+
+    #[test]
+    fn validate_rejects_unknown_traits() {
+        let layer_dirs = &["layer1", "layer2"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        let layer_groups = get_layer_groups(&fixture.path, layer_dirs).unwrap();
+
+        let rules = Rules::new(
+            vec![(trait_ref("layer1/image1"), trait_ref("layer2/nope"))],
+            vec![],
+        );
+        assert!(matches!(
+            rules.validate(&layer_groups),
+            Err(NftgenError::InvalidRule(_))
+        ));
+    }
+
+    #[test]
+    fn validate_rejects_unsatisfiable_rules() {
+        let layer_dirs = &["layer1", "layer2"];
+        let fixture = Fixture::create_layers_dirs("minimal.png", layer_dirs);
+        let layer_groups = get_layer_groups(&fixture.path, layer_dirs).unwrap();
+
+        // Every layer1 trait requires layer2/image1, which excludes every layer1 trait
+        let requires = (1..10)
+            .map(|i| {
+                (
+                    trait_ref(&format!("layer1/image{}", i)),
+                    trait_ref("layer2/image1"),
+                )
+            })
+            .collect();
+        let excludes = (1..10)
+            .map(|i| {
+                (
+                    trait_ref("layer2/image1"),
+                    trait_ref(&format!("layer1/image{}", i)),
+                )
+            })
+            .collect();
+        let rules = Rules::new(excludes, requires);
+        assert!(matches!(
+            rules.validate(&layer_groups),
+            Err(NftgenError::UnsatisfiableRules)
+        ));
+
+        assert!(!rules.can_pick(&layer_groups, ("layer1", "image1")));
+
+        let satisfiable = Rules::new(
+            vec![],
+            vec![(trait_ref("layer1/image1"), trait_ref("layer2/image1"))],
+        );
+        assert!(satisfiable.validate(&layer_groups).is_ok());
+        assert!(satisfiable.can_pick(&layer_groups, ("layer1", "image1")));
+        assert!(satisfiable.can_pick(&layer_groups, ("layer2", "image5")));
+    }
+
+    #[test]
+    fn search_follows_links() {
+        let fixture = Fixture::blank("minimal.png");
+        let layer_types = ["body", "hands"];
+        let layer_groups: Vec<_> = [
+            ("body", ["slim@dark#1.png", "round@light#1.png"]),
+            ("hands", ["open@dark#1.png", "fist@light#1.png"]),
+        ]
+        .iter()
+        .map(|(layer_type, files)| {
+            let layer_path = fixture.create_layer_files(layer_type, files);
+            LayerGroup::new(&layer_path, &layer_types)
+                .unwrap()
+                .with_link("Skin")
+                .unwrap()
+        })
+        .collect();
+
+        // slim is only drawn dark and fist only light
+        let rules = Rules::new(
+            vec![],
+            vec![(trait_ref("body/slim"), trait_ref("hands/fist"))],
+        );
+        assert!(rules.validate(&layer_groups).is_ok());
+        assert!(!rules.can_pick(&layer_groups, ("body", "slim")));
+        assert!(rules.can_pick(&layer_groups, ("body", "round")));
+
+        let rules = Rules::new(
+            vec![(trait_ref("body/round"), trait_ref("hands/fist"))],
+            vec![(trait_ref("body/slim"), trait_ref("hands/fist"))],
+        );
+        assert!(matches!(
+            rules.validate(&layer_groups),
+            Err(NftgenError::UnsatisfiableRules)
+        ));
+    }
+
+    #[test]
+    fn search_prunes_groups_left_without_layers() {
+        let layer_dirs: Vec<_> = (1..=12).map(|i| format!("layer{}", i)).collect();
+        let layer_dirs: Vec<_> = layer_dirs.iter().map(String::as_str).collect();
+        let fixture = Fixture::create_layers_dirs("minimal.png", &layer_dirs);
+        let layer_groups = get_layer_groups(&fixture.path, &layer_dirs).unwrap();
+
+        // Every layer12 trait excludes every layer1 trait, which a plain depth first search
+        // would only find out after trying all 9^11 combinations of the groups in between
+        let excludes = (1..10)
+            .flat_map(|i| {
+                (1..10).map(move |j| {
+                    (
+                        trait_ref(&format!("layer1/image{}", i)),
+                        trait_ref(&format!("layer12/image{}", j)),
+                    )
+                })
+            })
+            .collect();
+        let rules = Rules::new(excludes, vec![]);
+        assert!(matches!(
+            rules.validate(&layer_groups),
+            Err(NftgenError::UnsatisfiableRules)
+        ));
+    }
+}
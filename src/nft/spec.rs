@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::{NftgenError, PlannedToken, NONE_LAYER_NAME};
+
+/// Reads the NFTs of a spec file, CSV or JSON by extension, with one row per token id and one
+/// column per trait type naming its layer, e.g.
+/// id,Background,Clothing,Hat
+/// 0,red,Shirts/tee,
+/// Columns named after one of `links` hold the variant of the linked layers and an `edition`
+/// column the edition of the NFT. Empty cells are `none`
+pub fn read_spec<P: AsRef<Path>>(
+    path: P,
+    links: &[&str],
+) -> Result<Vec<PlannedToken>, NftgenError> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)?;
+    let invalid = |message: String| {
+        NftgenError::InvalidSpec(format!("{}: {}", path.to_string_lossy(), message))
+    };
+    let rows = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => json_rows(&contents),
+        _ => csv_rows(&contents),
+    }
+    .map_err(invalid)?;
+
+    if rows.is_empty() {
+        return Err(invalid("There are no NFTs".to_string()));
+    }
+    rows.into_iter()
+        .enumerate()
+        .map(|(i, row)| {
+            spec_token(row, links).map_err(|message| invalid(format!("Row {} {}", i + 1, message)))
+        })
+        .collect()
+}
+
+fn spec_token(row: BTreeMap<String, String>, links: &[&str]) -> Result<PlannedToken, String> {
+    let mut id = None;
+    let mut token = PlannedToken {
+        id: 0,
+        edition: None,
+        one_of_one: None,
+        layers: BTreeMap::new(),
+        variants: BTreeMap::new(),
+    };
+    for (column, value) in row {
+        let value = value.trim();
+        match column.as_str() {
+            "id" => {
+                id = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("has an invalid id {}", value))?,
+                )
+            }
+            "edition" => token.edition = Some(value.to_string()).filter(|e| !e.is_empty()),
+            link if links.contains(&link) => {
+                token.variants.insert(column.clone(), value.to_string());
+            }
+            _ => {
+                let name = if value.is_empty() {
+                    NONE_LAYER_NAME
+                } else {
+                    value
+                };
+                token.layers.insert(column.clone(), name.to_string());
+            }
+        }
+    }
+    token.id = id.ok_or("has no id")?;
+    Ok(token)
+}
+
+/// Reads a JSON array of objects, e.g. [{"id": 0, "Background": "red"}]
+fn json_rows(contents: &str) -> Result<Vec<BTreeMap<String, String>>, String> {
+    let rows: Vec<BTreeMap<String, serde_json::Value>> =
+        serde_json::from_str(contents).map_err(|e| e.to_string())?;
+    rows.into_iter()
+        .map(|row| {
+            row.into_iter()
+                .map(|(column, value)| match value {
+                    serde_json::Value::String(value) => Ok((column, value)),
+                    serde_json::Value::Number(value) => Ok((column, value.to_string())),
+                    serde_json::Value::Null => Ok((column, String::new())),
+                    value => Err(format!("{} is not a layer name: {}", column, value)),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Reads CSV rows by the column names of the header
+fn csv_rows(contents: &str) -> Result<Vec<BTreeMap<String, String>>, String> {
+    let mut records = csv_records(contents.trim_start_matches('\u{feff}'))?.into_iter();
+    let header = records.next().unwrap_or_default();
+    let columns: Vec<String> = header
+        .iter()
+        .map(|column| column.trim().to_string())
+        .collect();
+    if let Some(column) = columns
+        .iter()
+        .enumerate()
+        .find(|(i, column)| columns[..*i].contains(column))
+        .map(|(_, column)| column)
+    {
+        return Err(format!("The column {} is listed twice", column));
+    }
+
+    records
+        .enumerate()
+        .map(|(i, record)| {
+            if record.len() != columns.len() {
+                return Err(format!(
+                    "Row {} has {} cells but there are {} columns",
+                    i + 1,
+                    record.len(),
+                    columns.len()
+                ));
+            }
+            Ok(columns.iter().cloned().zip(record).collect())
+        })
+        .collect()
+}
+
+/// Splits CSV into records of cells, which may be quoted with `"` and escape quotes as `""`
+/// Blank lines are skipped
+fn csv_records(contents: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = contents.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, _) => cell.push(c),
+            (false, '"') => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut cell)),
+            (false, '\r') => {}
+            (false, '\n') => {
+                record.push(std::mem::take(&mut cell));
+                records.push(std::mem::take(&mut record));
+            }
+            (false, _) => cell.push(c),
+        }
+    }
+    if quoted {
+        return Err("A quoted cell is never closed".to_string());
+    }
+    if !cell.is_empty() || !record.is_empty() {
+        record.push(cell);
+        records.push(record);
+    }
+    records.retain(|record| !(record.len() == 1 && record[0].trim().is_empty()));
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nft::tests::fixture::Fixture;
+
+    #[test]
+    fn read_spec_works() {
+        let fixture = Fixture::blank("minimal.png");
+        let path = fixture.path.join("tokens.csv");
+        std::fs::write(
+            &path,
+            "id,Body,Clothing,Hat,Skin\r\n\
+             7,slim,\"Shirts/tee, blue\",,dark\r\n\
+             \r\n\
+             12,round,cape,\"\"\"cap\"\"\",light\r\n",
+        )
+        .unwrap();
+
+        let tokens = read_spec(&path, &["Skin"]).unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].id, 7);
+        assert_eq!(tokens[0].layers["Clothing"], "Shirts/tee, blue");
+        assert_eq!(tokens[0].layers["Hat"], NONE_LAYER_NAME);
+        assert_eq!(tokens[0].variants["Skin"], "dark");
+        assert!(!tokens[0].layers.contains_key("Skin"));
+        assert_eq!(tokens[1].layers["Hat"], "\"cap\"");
+
+        let path = fixture.path.join("tokens.json");
+        std::fs::write(
+            &path,
+            r#"[{"id": 7, "edition": "Genesis", "Body": "slim", "Hat": null}]"#,
+        )
+        .unwrap();
+        let tokens = read_spec(&path, &[]).unwrap();
+        assert_eq!(tokens[0].edition.as_deref(), Some("Genesis"));
+        assert_eq!(tokens[0].layers["Hat"], NONE_LAYER_NAME);
+    }
+
+    #[test]
+    fn read_spec_rejects_invalid_rows() {
+        let fixture = Fixture::blank("minimal.png");
+        let path = fixture.path.join("tokens.csv");
+        for contents in [
+            "",
+            "Body,Hat\nslim,cap\n",
+            "id,Body\nseven,slim\n",
+            "id,Body\n7,slim,cap\n",
+            "id,Body,Body\n7,slim,round\n",
+            "id,Body\n7,\"slim\n",
+        ] {
+            std::fs::write(&path, contents).unwrap();
+            assert!(matches!(
+                read_spec(&path, &[]),
+                Err(NftgenError::InvalidSpec(_))
+            ));
+        }
+    }
+}
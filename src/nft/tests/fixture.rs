@@ -12,12 +12,18 @@ pub struct Fixture {
 }
 
 impl Fixture {
-    pub fn blank(fixture_filename: &str) -> Self {
-        // First, figure out the right file in `tests/fixtures/`:
+    /// Path of a file in `tests/fixtures/`
+    pub fn source_path(fixture_filename: &str) -> PathBuf {
         let root_dir = &std::env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
         let mut source = PathBuf::from(root_dir);
         source.push("tests/fixtures");
-        source.push(&fixture_filename);
+        source.push(fixture_filename);
+        source
+    }
+
+    pub fn blank(fixture_filename: &str) -> Self {
+        // First, figure out the right file in `tests/fixtures/`:
+        let source = Fixture::source_path(fixture_filename);
 
         // The "real" path of the file is going to be under a temporary directory:
         let tempdir = tempfile::tempdir().unwrap();
@@ -44,6 +50,17 @@ impl Fixture {
         }
         fixture
     }
+
+    /// Creates a layer directory of copies of the fixture file named `layer_files`
+    pub fn create_layer_files(&self, layer_dir: &str, layer_files: &[&str]) -> PathBuf {
+        let layer_path = self.path.join(layer_dir);
+        std::fs::create_dir(&layer_path).expect("Create layer dir should work in test");
+        for layer_file in layer_files.iter() {
+            std::fs::copy(&self.source, layer_path.join(layer_file))
+                .expect("create png file should work in test");
+        }
+        layer_path
+    }
 }
 
 impl Deref for Fixture {
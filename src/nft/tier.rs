@@ -0,0 +1,107 @@
+use serde::Serialize;
+
+use crate::{LayerGroup, TokenPlan};
+
+/// Rarity tier of a layer group, e.g. `Legendary`, picked by its weight before one of the
+/// layers of the tier
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tier {
+    pub name: String,
+    pub weight: f64,
+}
+
+impl Tier {
+    pub fn new(name: &str, weight: f64) -> Self {
+        Tier {
+            name: name.to_string(),
+            weight,
+        }
+    }
+}
+
+/// Number of NFTs with a layer of a tier, next to the share expected from the tier weights
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TierRarity {
+    /// Edition of the NFTs, if the collection is split into editions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edition: Option<String>,
+    pub layer_type: String,
+    pub tier: String,
+    pub count: usize,
+    pub percent: f64,
+    pub expected_percent: f64,
+}
+
+/// Counts the NFTs of `plan` in every tier of `layer_groups`, from the most to the least common
+/// tier of each group. Groups without tiers are left out
+pub fn rarity_report(layer_groups: &[LayerGroup], plan: &[TokenPlan]) -> Vec<TierRarity> {
+    let mut report = vec![];
+    for layer_group in layer_groups {
+        for tier in layer_group.tiers() {
+            let count = plan
+                .iter()
+                .flat_map(|token| &token.layers)
+                .filter(|pick| pick.layer_type == layer_group.layer_type)
+                .filter(|pick| {
+                    pick.layer.and_then(|layer| layer.tier.as_deref()) == Some(&tier.name)
+                })
+                .count();
+            report.push(TierRarity {
+                edition: None,
+                layer_type: layer_group.layer_type.clone(),
+                tier: tier.name.clone(),
+                count,
+                percent: 100.0 * count as f64 / plan.len().max(1) as f64,
+                expected_percent: 100.0
+                    * layer_group.tier_probability(&tier.name)
+                    * (1.0 - layer_group.skip_probability()),
+            });
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nft::tests::fixture::Fixture;
+    use crate::{layers_dna, PickedLayer};
+
+    #[test]
+    fn rarity_report_counts_tiers() {
+        let fixture = Fixture::blank("minimal.png");
+        fixture.create_layer_files("hat", &[]);
+        fixture.create_layer_files("hat/Common", &["cap.png"]);
+        fixture.create_layer_files("hat/Rare", &["crown.png"]);
+        std::fs::write(
+            fixture.path.join("hat/layers.toml"),
+            "[tiers]\nRare = 1\nCommon = 3\n",
+        )
+        .unwrap();
+        let layer_group = LayerGroup::new(fixture.path.join("hat"), &["hat"])
+            .unwrap()
+            .with_skip_probability(0.2);
+        let layers = layer_group.layers();
+        let plan: Vec<_> = [Some(&layers[0]), Some(&layers[0]), Some(&layers[1]), None]
+            .into_iter()
+            .enumerate()
+            .map(|(id, layer)| {
+                let picks = vec![layer_group.picked(layer)];
+                TokenPlan {
+                    id: id as u32,
+                    dna: layers_dna(&picks),
+                    layers: picks,
+                }
+            })
+            .collect();
+        assert_eq!(plan[3].layers[0], PickedLayer::new("hat", None));
+
+        let report = rarity_report(std::slice::from_ref(&layer_group), &plan);
+        assert_eq!(report.len(), 2);
+        assert_eq!((report[0].tier.as_str(), report[0].count), ("Common", 2));
+        assert_eq!(report[0].percent, 50.0);
+        assert!((report[0].expected_percent - 60.0).abs() < 1e-9);
+        assert_eq!((report[1].tier.as_str(), report[1].count), ("Rare", 1));
+        assert!((report[1].expected_percent - 20.0).abs() < 1e-9);
+    }
+}